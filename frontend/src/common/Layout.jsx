@@ -1,6 +1,6 @@
 /* eslint-disable jsx-a11y/accessible-emoji */
 
-import React, { useCallback, useMemo } from 'react';
+import React, { useCallback, useMemo, useState } from 'react';
 import { NavLink, useHistory } from 'react-router-dom';
 import { Api, isLoggedIn, logOut } from './api';
 import { MenuTextButton } from './Buttons';
@@ -9,12 +9,19 @@ import styles from './layout.module.css';
 const Layout = ({ children }) => {
     const api = useMemo(() => isLoggedIn() ? new Api() : null, []);
     const history = useHistory();
+    const [locale, setLocale] = useState(api && api.locale);
 
     const onLogOutClick = useCallback(() => {
         logOut();
         history.replace('/login');
     }, [history]);
 
+    const onLocaleClick = useCallback(async () => {
+        const next = locale === 'da' ? 'en' : 'da';
+        await api.setLocaleAsync(next);
+        setLocale(next);
+    }, [api, locale]);
+
     return (
         <>
             <header>
@@ -37,6 +44,10 @@ const Layout = ({ children }) => {
                             welcome, {api.userName}
                         </span>
 
+                        <MenuTextButton onClick={onLocaleClick}>
+                            {locale === 'da' ? 'english' : 'dansk'}
+                        </MenuTextButton>
+
                         <MenuTextButton onClick={onLogOutClick}>
                             log out
                         </MenuTextButton>
@@ -3,13 +3,44 @@ import decodeJwt from 'jwt-decode';
 
 export const ApiContext = React.createContext();
 
+const REFRESH_TOKEN_KEY = 'refreshToken';
+
 export const isLoggedIn = () => {
     return !!localStorage.getItem('token');
 };
 
-export const loginAsync = async (userName) => {
-    const response = await fetch('/api/login', {
-        body: JSON.stringify(userName),
+// An error reported by the server. The code and params are stable, so they can be used to
+// handle or translate the error; the message is an English fallback.
+export class ApiError extends Error {
+    constructor({ code, message, params }) {
+        super(message);
+        this.code = code;
+        this.params = params || {};
+    }
+}
+
+const readErrorAsync = async (response) => {
+    try {
+        const { error } = await response.json();
+
+        if (error) {
+            return new ApiError(error);
+        }
+    } catch (e) {
+        // The body was not JSON, e.g. from a proxy.
+    }
+
+    return new ApiError({ code: 'unknown', message: response.statusText });
+};
+
+const storeTokens = ({ accessToken, refreshToken }) => {
+    localStorage.setItem('token', accessToken);
+    localStorage.setItem(REFRESH_TOKEN_KEY, refreshToken);
+};
+
+const authenticateAsync = async (url, userName, password) => {
+    const response = await fetch(url, {
+        body: JSON.stringify({ name: userName, password }),
         headers: {
             'Accept': 'application/json',
             'Content-Type': 'application/json'
@@ -18,16 +49,58 @@ export const loginAsync = async (userName) => {
     });
 
     if (response.ok) {
-        const token = await response.json();
-
-        localStorage.setItem('token', token);
+        storeTokens(await response.json());
     } else {
-        throw new Error(response.statusText);
+        throw await readErrorAsync(response);
     }
 };
 
+export const loginAsync = async (userName, password) => {
+    await authenticateAsync('/api/login', userName, password);
+};
+
+export const registerAsync = async (userName, password) => {
+    await authenticateAsync('/api/register', userName, password);
+};
+
+// Exchanges the refresh token for new tokens. Returns false if the session has ended.
+const refreshAsync = async () => {
+    const refreshToken = localStorage.getItem(REFRESH_TOKEN_KEY);
+
+    if (!refreshToken) {
+        return false;
+    }
+
+    const response = await fetch('/api/token/refresh', {
+        body: JSON.stringify(refreshToken),
+        headers: {
+            'Accept': 'application/json',
+            'Content-Type': 'application/json'
+        },
+        method: 'POST',
+    });
+
+    if (response.ok) {
+        storeTokens(await response.json());
+        return true;
+    }
+
+    return false;
+};
+
 export const logOut = () => {
+    const refreshToken = localStorage.getItem(REFRESH_TOKEN_KEY);
+
     localStorage.removeItem('token');
+    localStorage.removeItem(REFRESH_TOKEN_KEY);
+
+    if (refreshToken) {
+        fetch('/api/logout', {
+            body: JSON.stringify(refreshToken),
+            headers: { 'Content-Type': 'application/json' },
+            method: 'POST',
+        });
+    }
 };
 
 export class Api {
@@ -43,50 +116,161 @@ export class Api {
         return this.#decoded.name;
     }
 
+    // The language the user prefers for messages from the server, or undefined to use the
+    // browser's languages.
+    get locale() {
+        return this.#decoded.locale;
+    }
+
+    async setLocaleAsync(locale) {
+        const response = await this.fetchAsync('/api/account/locale', {
+            body: JSON.stringify(locale || null),
+            headers: {
+                'Accept': 'application/json',
+                'Content-Type': 'application/json',
+            },
+            method: 'PUT',
+        });
+
+        if (!response.ok) {
+            throw await readErrorAsync(response);
+        }
+
+        storeTokens(await response.json());
+        this.#jwt = localStorage.getItem('token');
+        this.#decoded = decodeJwt(this.#jwt);
+    }
+
+    // Sends an authorized request, refreshing the access token once if it has expired.
+    async fetchAsync(url, { headers, ...init } = {}) {
+        const send = () => fetch(url, {
+            ...init,
+            headers: { ...headers, 'Authorization': `Bearer ${this.#jwt}` },
+        });
+
+        const response = await send();
+
+        if (response.status === 401 && await refreshAsync()) {
+            this.#jwt = localStorage.getItem('token');
+            this.#decoded = decodeJwt(this.#jwt);
+            return await send();
+        }
+
+        return response;
+    }
+
+    // Opens a stream of server-sent events and adds the listeners to it. EventSource cannot
+    // send headers, so the access token goes in the query string. The browser gives up on a
+    // stream that is rejected, e.g. because the token expired before it reconnected, in
+    // which case the stream is reopened once with refreshed tokens.
+    openStream(url, listeners) {
+        let source;
+        let closed = false;
+        let refreshed = false;
+
+        const open = () => {
+            const token = encodeURIComponent(this.#jwt);
+            source = new EventSource(`${url}?access_token=${token}`);
+
+            for (const [type, listener] of Object.entries(listeners)) {
+                source.addEventListener(type, listener);
+            }
+
+            source.addEventListener('open', () => {
+                refreshed = false;
+            });
+
+            source.onerror = async () => {
+                if (closed || refreshed || source.readyState !== EventSource.CLOSED) {
+                    return;
+                }
+
+                refreshed = true;
+
+                if (await refreshAsync()) {
+                    this.#jwt = localStorage.getItem('token');
+                    this.#decoded = decodeJwt(this.#jwt);
+                    open();
+                }
+            };
+        };
+
+        open();
+
+        return {
+            close: () => {
+                closed = true;
+                source.close();
+            },
+        };
+    }
+
+    async enqueueAsync(size) {
+        const response = await this.fetchAsync('/api/queue', {
+            body: JSON.stringify(size),
+            headers: {
+                'Accept': 'application/json',
+                'Content-Type': 'application/json',
+            },
+            method: 'POST',
+        });
+
+        if (!response.ok) {
+            throw await readErrorAsync(response);
+        }
+    }
+
+    async leaveQueueAsync() {
+        const response = await this.fetchAsync('/api/queue', {
+            headers: {
+                'Accept': 'application/json',
+            },
+            method: 'DELETE',
+        });
+
+        if (!response.ok) {
+            throw await readErrorAsync(response);
+        }
+    }
+
     async loadGamesAsync() {
-        const response = await fetch('/api/games', {
+        const response = await this.fetchAsync('/api/games', {
             headers: {
                 'Accept': 'application/json',
-                'Authorization': `Bearer ${this.#jwt}`,
             },
         });
 
         if (response.ok) {
-            return await response.json();
+            const { games } = await response.json();
+            return games;
         }
 
-        throw new Error(response.statusText);
+        throw await readErrorAsync(response);
     }
 
-    async createGameAsync(opponents) {
-        const response = await fetch('/api/games', {
+    async createLobbyAsync(opponents) {
+        const response = await this.fetchAsync('/api/lobbies', {
             body: JSON.stringify(opponents),
             headers: {
                 'Accept': 'application/json',
-                'Authorization': `Bearer ${this.#jwt}`,
                 'Content-Type': 'application/json',
             },
             method: 'POST',
         });
 
         if (response.ok) {
-            const obj = await response.json();
-
-            if (obj.success) {
-                return obj.payload;
-            }
-
-            throw new Error(obj.error);
+            const { payload } = await response.json();
+            return payload;
         }
 
-        throw new Error(response.statusText);
+        throw await readErrorAsync(response);
     }
 
+    /** @returns {Promise<?import('./types').GameView>} */
     async loadGameAsync(gameId) {
-        const response = await fetch(`/api/games/${gameId}`, {
+        const response = await this.fetchAsync(`/api/games/${gameId}`, {
             headers: {
                 'Accept': 'application/json',
-                'Authorization': `Bearer ${this.#jwt}`,
             },
         });
 
@@ -98,30 +282,21 @@ export class Api {
             return null;
         }
 
-        throw new Error(response.statusText);
+        throw await readErrorAsync(response);
     }
 
     async selectCardsAsync(gameId, cards) {
-        const response = await fetch(`/api/games/${gameId}`, {
+        const response = await this.fetchAsync(`/api/games/${gameId}`, {
             body: JSON.stringify(cards),
             headers: {
                 'Accept': 'application/json',
-                'Authorization': `Bearer ${this.#jwt}`,
                 'Content-Type': 'application/json',
             },
             method: 'PUT',
         });
 
-        if (response.ok) {
-            const obj = await response.json();
-
-            if (obj.success) {
-                return;
-            }
-
-            throw new Error(obj.error);
+        if (!response.ok) {
+            throw await readErrorAsync(response);
         }
-
-        throw new Error(response.statusText);
     }
 }
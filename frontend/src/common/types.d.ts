@@ -0,0 +1,20 @@
+// Generated from the Rust types; do not edit.
+// Run `cargo run -- --print-typescript > frontend/src/common/types.d.ts` to update.
+
+export type UserId = number;
+
+export type MakiRolls = { "makiRolls": "one" } | { "makiRolls": "two" } | { "makiRolls": "three" };
+
+export type Nigiri = { "nigiri": "egg" } | { "nigiri": "salmon" } | { "nigiri": "squid" };
+
+export type Card = { "kind": "chopsticks" } | { "kind": "dumpling" } | { "kind": "makiRolls" } & MakiRolls | { "kind": "nigiri" } & Nigiri | { "kind": "pudding" } | { "kind": "sashimi" } | { "kind": "tempura" } | { "kind": "wasabi" };
+
+export type FaceUpCard = { "kind": "card", id: number, card: Card, } | { "kind": "wasabi", nigiri: Nigiri, };
+
+export type PlayerView = { faceUpCards: Array<FaceUpCard>, hand: { [key in number]?: Card }, numPoints: number, numPuddings: number, selectedCards: Array<number>, };
+
+export type OpponentView = { faceUpCards: Array<FaceUpCard>, id: UserId, isBot: boolean, numCards: number, numPoints: number, numPuddings: number, ready: boolean, };
+
+export type GameView = { round: number, player: PlayerView | null, opponents: Array<OpponentView>, winner: UserId | null, };
+
+export type RoundOver = { round: number, points: { [key in UserId]?: number }, };
@@ -2,6 +2,10 @@ import React, { useCallback, useState } from 'react';
 import { CSSTransition, TransitionGroup } from 'react-transition-group'
 import styles from './game.module.css';
 
+/** @typedef {import('../common/types').Card} CardType */
+/** @typedef {import('../common/types').GameView} GameViewType */
+
+/** @param {{ id: string, userName: string, game: GameViewType, countdown: ?number }} props */
 const Game = ({ id, userName, game, countdown, ...props }) => {
     const [state, setState] = useState({
         selectedCards: game.player.selectedCards.map((id) => id.toString()),
@@ -84,12 +88,13 @@ const Game = ({ id, userName, game, countdown, ...props }) => {
     );
 };
 
-const Player = ({ faceUpCards, id, numPoints, numPuddings, ready }) => {
+const Player = ({ faceUpCards, id, isBot, numPoints, numPuddings, ready }) => {
     return (
         <div className={styles['player']} data-ready={ready}>
             <div>
                 <div>
                     <strong>{id}</strong>
+                    {isBot && <span title="Bot"> 🤖</span>}
                 </div>
                 <div>{numPoints} points</div>
                 <div>{numPuddings} puddings</div>
@@ -137,6 +142,7 @@ const CheckableCard = ({ card, checked, onChecked }) => {
     );
 };
 
+/** @param {CardType} props */
 const Card = (props) => {
     return (
         <div className={styles['card']} data-kind={props.kind}>
@@ -156,6 +162,7 @@ const WasabiFaceUpCard = ({ nigiri }) => {
     );
 };
 
+/** @param {CardType} props */
 const CardText = (props) => {
     let text;
 
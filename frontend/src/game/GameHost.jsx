@@ -1,4 +1,5 @@
 import React from 'react';
+import { Api } from '../common/api';
 import Game from './Game';
 
 class GameHost extends React.Component {
@@ -6,7 +7,8 @@ class GameHost extends React.Component {
         super(props);
 
         this.countdownId = null;
-        this.sse = new EventSource(`/api/games/${props.gameId}/stream`);
+        this.api = new Api();
+        this.sse = null;
 
         this.state = {
             time: null,
@@ -14,12 +16,14 @@ class GameHost extends React.Component {
     }
 
     componentDidMount() {
-        this.sse.addEventListener('cardsselected', this.handleCardsSelected);
-        this.sse.addEventListener('countdownstarted', this.handleCountdownStarted);
-        this.sse.addEventListener('countdowncancelled', this.handleCountdownCancelled);
-        this.sse.addEventListener('turnover', this.handleTurnOver);
-        this.sse.addEventListener('roundover', this.handleRoundOver);
-        this.sse.addEventListener('gameover', this.handleGameOver);
+        this.sse = this.api.openStream(`/api/games/${this.props.gameId}/stream`, {
+            cardsselected: this.handleCardsSelected,
+            countdownstarted: this.handleCountdownStarted,
+            countdowncancelled: this.handleCountdownCancelled,
+            turnover: this.handleTurnOver,
+            roundover: this.handleRoundOver,
+            gameover: this.handleGameOver,
+        });
     }
 
     handleCardsSelected = ({ data }) => {
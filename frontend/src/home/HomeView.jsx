@@ -1,5 +1,5 @@
-import React, { useCallback, useEffect, useMemo, useState } from 'react';
-import { Link } from 'react-router-dom';
+import React, { useCallback, useEffect, useMemo, useRef, useState } from 'react';
+import { Link, useHistory } from 'react-router-dom';
 import { useSubtitle } from '../common/useSubtitle';
 import { Api } from '../common/api';
 import Layout from '../common/Layout';
@@ -10,7 +10,12 @@ export default () => {
     useSubtitle('Home');
 
     const api = useMemo(() => new Api(), []);
+    const history = useHistory();
     const [games, setGames] = useState();
+    const [size, setSize] = useState(2);
+    const [queued, setQueued] = useState(false);
+    const [error, setError] = useState();
+    const stream = useRef();
 
     const loadGames = useCallback(async () => {
         await api.loadGamesAsync().then(setGames);
@@ -20,6 +25,56 @@ export default () => {
         loadGames();
     }, [loadGames]);
 
+    const closeStream = useCallback(() => {
+        if (stream.current) {
+            stream.current.close();
+            stream.current = null;
+        }
+    }, []);
+
+    // Matches are only announced on the user's stream, so it is opened before queueing.
+    const onQuickPlayClick = useCallback(() => {
+        let enqueued = false;
+
+        setError();
+
+        stream.current = api.openStream('/api/stream', {
+            open: async () => {
+                if (enqueued) {
+                    return;
+                }
+
+                enqueued = true;
+
+                try {
+                    await api.enqueueAsync(size);
+                    setQueued(true);
+                } catch (e) {
+                    closeStream();
+                    setError(e);
+                }
+            },
+            matchfound: ({ data }) => {
+                closeStream();
+                history.push(`/play/${JSON.parse(data)}`);
+            },
+        });
+    }, [api, closeStream, history, size]);
+
+    const onLeaveQueueClick = useCallback(async () => {
+        closeStream();
+        setQueued(false);
+        await api.leaveQueueAsync();
+    }, [api, closeStream]);
+
+    // Leaving the page while queued leaves the queue.
+    useEffect(() => () => {
+        if (stream.current) {
+            stream.current.close();
+            api.leaveQueueAsync();
+        }
+    }, [api]);
+
     return (
         <Layout>
             <div className={styles.view}>
@@ -37,6 +92,26 @@ export default () => {
                     <button>New game</button>
                 </Link>
 
+                {queued ? (
+                    <p>
+                        Waiting for {size - 1} more player{size > 2 ? 's' : ''}...
+                        {' '}
+                        <button onClick={onLeaveQueueClick}>Leave queue</button>
+                    </p>
+                ) : (
+                    <p>
+                        <select value={size} onChange={(e) => setSize(Number(e.target.value))}>
+                            {[2, 3, 4, 5].map(n => (
+                                <option key={n} value={n}>{n} players</option>
+                            ))}
+                        </select>
+                        {' '}
+                        <button onClick={onQuickPlayClick}>Quick play</button>
+                    </p>
+                )}
+
+                {error && <div>{error.message}</div>}
+
                 <GameList games={games} />
             </div>
         </Layout>
@@ -1,20 +1,29 @@
 import React, { useCallback, useMemo, useState } from 'react';
 import styles from './login.module.css';
 
-const LoginForm = ({ onLogIn }) => {
+const LoginForm = ({ onLogIn, onRegister }) => {
     const [userName, setUserName] = useState('');
+    const [password, setPassword] = useState('');
 
     const canLogIn = useMemo(() => {
-        return userName.length > 0;
-    }, [userName]);
+        return userName.length > 0 && password.length > 0;
+    }, [userName, password]);
 
     const onUserNameChange = useCallback((e) => {
         setUserName(e.target.value);
     }, []);
 
+    const onPasswordChange = useCallback((e) => {
+        setPassword(e.target.value);
+    }, []);
+
     const onLogInClick = useCallback(() => {
-        onLogIn(userName);
-    }, [onLogIn, userName]);
+        onLogIn(userName, password);
+    }, [onLogIn, userName, password]);
+
+    const onRegisterClick = useCallback(() => {
+        onRegister(userName, password);
+    }, [onRegister, userName, password]);
 
     const onKeyDown = useCallback((e) => {
         if (e.key === 'Enter' && canLogIn) {
@@ -36,12 +45,30 @@ const LoginForm = ({ onLogIn }) => {
                     autoFocus />
             </div>
 
+            <div>
+                <input
+                    className={styles.input}
+                    id="password"
+                    type="password"
+                    placeholder="Enter password"
+                    value={password}
+                    onKeyDown={onKeyDown}
+                    onChange={onPasswordChange} />
+            </div>
+
             <button
                 className={styles.btn}
                 onClick={onLogInClick}
                 disabled={!canLogIn}>
                 Log in
             </button>
+
+            <button
+                className={styles.btn}
+                onClick={onRegisterClick}
+                disabled={!canLogIn}>
+                Register
+            </button>
         </div>
     );
 };
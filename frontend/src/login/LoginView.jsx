@@ -1,6 +1,6 @@
-import React, { useCallback, useEffect } from 'react';
+import React, { useCallback, useEffect, useState } from 'react';
 import { useHistory, useLocation } from 'react-router-dom';
-import { isLoggedIn, loginAsync } from '../common/api';
+import { isLoggedIn, loginAsync, registerAsync } from '../common/api';
 import { useSubtitle } from '../common/useSubtitle';
 import Layout from '../common/Layout';
 import LoginForm from './LoginForm';
@@ -15,9 +15,24 @@ const LoginView = () => {
         history.replace(from);
     }, [history, location.state]);
 
-    const login = useCallback(async (userName) => {
-        await loginAsync(userName);
-        redirect();
+    const [error, setError] = useState(null);
+
+    const login = useCallback(async (userName, password) => {
+        try {
+            await loginAsync(userName, password);
+            redirect();
+        } catch (e) {
+            setError(e.message);
+        }
+    }, [redirect]);
+
+    const register = useCallback(async (userName, password) => {
+        try {
+            await registerAsync(userName, password);
+            redirect();
+        } catch (e) {
+            setError(e.message);
+        }
     }, [redirect]);
 
     useEffect(() => {
@@ -34,7 +49,8 @@ const LoginView = () => {
                 <h2>「天ぷら」</h2>
                 <p>Describe game here</p>
                 <span>Please log in to continue</span>
-                <LoginForm onLogIn={login} />
+                <LoginForm onLogIn={login} onRegister={register} />
+                {error && <span>{error}</span>}
             </div>
         </Layout>
     );
@@ -1,5 +1,4 @@
 import React, { useCallback, useMemo, useState } from 'react';
-import { Link } from 'react-router-dom';
 import { useSubtitle } from '../common/useSubtitle';
 import { Api } from '../common/api';
 import { TextButton } from '../common/Buttons';
@@ -14,7 +13,7 @@ export default () => {
     const init = useMemo(() => [api.userName, ''], [api.userName]);
     const [players, setPlayers] = useState(init);
     const [error, setError] = useState();
-    const [gameId, setGameId] = useState();
+    const [lobbyId, setLobbyId] = useState();
 
     const onChange = useCallback((i, value) => {
         setPlayers(o => [
@@ -48,13 +47,13 @@ export default () => {
     const onStartGameClick = useCallback(async () => {
         if (canStartGame) {
             setError();
-            setGameId();
+            setLobbyId();
 
             const opponents = players.slice(1);
 
             try {
-                const gameId = await api.createGameAsync(opponents);
-                setGameId(gameId);
+                const lobbyId = await api.createLobbyAsync(opponents);
+                setLobbyId(lobbyId);
                 setPlayers(init);
             } catch (e) {
                 setError(e);
@@ -111,9 +110,9 @@ export default () => {
 
                     {error && <div>{error.message}</div>}
 
-                    {gameId && (
+                    {lobbyId && (
                         <p>
-                            Your game is ready <Link to={`/play/${gameId}`}>here</Link>
+                            Invitations sent! The game starts when everyone has accepted.
                         </p>
                     )}
                 </div>
@@ -4,58 +4,207 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
+#[macro_use]
+extern crate prometheus;
+
 mod sushi;
 
 use actix_files::{Files, NamedFile};
-use actix_web::{middleware::Logger, web, App, HttpServer, Result as ActixResult};
+use actix_web::{dev::Service, middleware::Logger, web, App, HttpServer, Result as ActixResult};
 use listenfd::ListenFd;
+use std::env;
 use std::io::Error as IoError;
-use tokio::sync::mpsc::unbounded_channel;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[actix_rt::main]
 async fn main() -> Result<(), IoError> {
-    pretty_env_logger::init();
-
-    let db = sushi::Database::new("./sushi.db");
+    // Read and validate settings before anything else, so mistakes are reported up front
+    let config = match sushi::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(2);
+        }
+    };
 
-    db.migrate().expect("failed to migrate database");
+    pretty_env_logger::formatted_builder()
+        .parse_filters(&config.log_level)
+        .init();
+
+    // Print the specification of an API version, e.g. `--print-openapi v2`
+    if let Some(i) = env::args().position(|arg| arg == "--print-openapi") {
+        let version = env::args()
+            .nth(i + 1)
+            .expect("usage: --print-openapi <version>")
+            .parse::<sushi::ApiVersion>()
+            .expect("invalid API version");
+
+        let spec = serde_json::to_string_pretty(&sushi::openapi(version)).expect("valid json");
+        println!("{}", spec);
+        return Ok(());
+    }
+
+    // Print the TypeScript declarations used by the frontend, e.g. to update `types.d.ts`
+    if env::args().any(|arg| arg == "--print-typescript") {
+        print!("{}", sushi::typescript());
+        return Ok(());
+    }
+
+    if !config.static_dir.is_dir() {
+        warn!(
+            "Static directory {} does not exist; only the API will be served",
+            config.static_dir.display()
+        );
+    }
+
+    // Select storage backend; the memory store runs without a database file
+    let db: sushi::Store = match config.database.backend {
+        sushi::Backend::Memory => Arc::new(sushi::MemoryStore::new()),
+        #[cfg(feature = "postgres")]
+        sushi::Backend::Postgres => {
+            let url = config.database.url.as_deref().expect("validated");
+            Arc::new(sushi::PostgresStore::new(url).expect("failed to connect to database"))
+        }
+        #[cfg(not(feature = "postgres"))]
+        sushi::Backend::Postgres => unreachable!("validated"),
+        sushi::Backend::Sqlite => Arc::new(
+            sushi::SqliteStore::new(config.database.path.display())
+                .expect("failed to open database"),
+        ),
+    };
 
-    let (tx, rx) = unbounded_channel();
+    // Time every call to the store for the metrics
+    let db: sushi::Store = Arc::new(sushi::InstrumentedStore::new(db));
+
+    if env::args().any(|arg| arg == "--migrate-dry-run") {
+        let pending = db
+            .pending_migrations()
+            .await
+            .expect("failed to read migrations");
+
+        if pending.is_empty() {
+            println!("No pending migrations");
+        }
+
+        for migration in pending {
+            println!(
+                "Pending migration {} ({})",
+                migration.version, migration.name
+            );
+        }
+
+        return Ok(());
+    }
+
+    db.migrate().await.expect("failed to migrate database");
+
+    // Grant a role, e.g. `--set-role alice admin`
+    if let Some(i) = env::args().position(|arg| arg == "--set-role") {
+        let args: Vec<_> = env::args().skip(i + 1).take(2).collect();
+
+        let (name, role) = match args.as_slice() {
+            [name, role] => (name, role.parse::<sushi::Role>().expect("invalid role")),
+            _ => panic!("usage: --set-role <name> <role>"),
+        };
+
+        let user_id = db
+            .get_user_id(name)
+            .await
+            .expect("failed to read user")
+            .expect("no such user");
+
+        db.set_user_role(user_id, role)
+            .await
+            .expect("failed to set role");
+
+        println!("{} is now {}", name, role);
+        return Ok(());
+    }
+
+    // Let a user without a password claim their account, e.g. `--issue-claim-code alice`
+    if let Some(i) = env::args().position(|arg| arg == "--issue-claim-code") {
+        let name = env::args()
+            .nth(i + 1)
+            .expect("usage: --issue-claim-code <name>");
+
+        let (user_id, password_hash) = db
+            .get_credentials(&name)
+            .await
+            .expect("failed to read user")
+            .expect("no such user");
+
+        if password_hash.is_some() {
+            panic!("{} already has a password", name);
+        }
+
+        let code = sushi::new_claim_code();
+
+        db.persist_refresh_token(&code.hash, user_id, code.expires)
+            .await
+            .expect("failed to store claim code");
+
+        println!("Claim code for {}, valid until {}:", name, code.expires);
+        println!("{}", code.token);
+        return Ok(());
+    }
 
     // Create game event broadcaster
     let broadcaster = sushi::Broadcaster::new();
 
-    // Start countdown listener
-    tokio::spawn(sushi::receiver(broadcaster.clone(), db.clone(), rx));
+    // Create registry of per-game actors
+    let countdown = Duration::from_millis(config.countdown_ms);
+    let actors = sushi::GameActors::new(db.clone(), broadcaster.clone(), countdown);
+
+    // Start matching users queued for quick play
+    let matchmaker = sushi::Matchmaker::new(db.clone(), broadcaster.clone());
+
+    // Load the keys access tokens are signed with
+    let tokens = sushi::Tokens::from_config(&config.jwt);
+    let static_dir = config.static_dir.clone();
+    let metrics_token = config.metrics_token.clone();
 
     let mut listenfd = ListenFd::from_env();
 
     let mut server = HttpServer::new(move || {
         let db = db.clone();
         let broadcaster = broadcaster.clone();
-        let tx = tx.clone();
+        let actors = actors.clone();
+        let matchmaker = matchmaker.clone();
+        let tokens = tokens.clone();
+        let static_dir = static_dir.clone();
+        let metrics_token = metrics_token.clone();
 
         App::new()
+            .wrap(sushi::error_handlers())
             .wrap(Logger::default())
-            .configure(|cfg| sushi::app(db, broadcaster, tx, cfg))
-            .default_service(static_files())
+            .wrap_fn(|request, service| sushi::time_request(service.call(request)))
+            .configure(|cfg| sushi::app(db, broadcaster, actors, matchmaker, tokens, cfg))
+            .configure(|cfg| sushi::metrics(metrics_token, cfg))
+            .default_service(static_files(&static_dir))
     });
 
-    server = if let Some(l) = listenfd.take_tcp_listener(0).unwrap() {
-        server.listen(l)?
+    if let Some(l) = listenfd.take_tcp_listener(0).unwrap() {
+        server = server.listen(l)?;
     } else {
-        server.bind("127.0.0.1:8080")?
-    };
+        for address in &config.bind {
+            server = server.bind(address)?;
+        }
+    }
 
     server.run().await
 }
 
-fn static_files() -> Files {
-    Files::new("/", "./frontend/build")
+fn static_files(dir: &Path) -> Files {
+    let index = dir.join("index.html");
+
+    Files::new("/", dir)
         .index_file("index.html")
-        .default_handler(web::get().to(index))
+        .default_handler(web::get().to(move || serve_index(index.clone())))
 }
 
-async fn index() -> ActixResult<NamedFile> {
-    Ok(NamedFile::open("./frontend/build/index.html")?)
+async fn serve_index(index: PathBuf) -> ActixResult<NamedFile> {
+    Ok(NamedFile::open(index)?)
 }
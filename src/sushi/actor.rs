@@ -0,0 +1,708 @@
+use super::chat::{self, ChatError, ChatMessage, RateLimiter};
+use super::db::{Error as DbError, Store};
+use super::domain::*;
+use super::metrics;
+use super::pubsub::Broadcaster;
+use super::GameId;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot, Mutex,
+    },
+    time::{delay_for, delay_until, Instant},
+};
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+type Result<T> = std::result::Result<T, Error>;
+type Reply<T> = oneshot::Sender<Result<T>>;
+
+/// The sender of a game's actor, or `None` while it is being spawned or after it stopped.
+/// Each game has its own lock, so loading or saving one game never holds up another.
+type Slot = Arc<Mutex<Option<UnboundedSender<Command>>>>;
+
+#[derive(Debug)]
+pub enum Error {
+    DbError(DbError),
+    GameNotFound,
+    ActorStopped,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            Error::DbError(e) => e.fmt(f),
+            Error::GameNotFound => write!(f, "Game not found"),
+            Error::ActorStopped => write!(f, "Game actor stopped unexpectedly"),
+        }
+    }
+}
+
+impl StdError for Error {}
+
+impl From<DbError> for Error {
+    fn from(error: DbError) -> Error {
+        Error::DbError(error)
+    }
+}
+
+enum Command {
+    IsPlaying {
+        user_id: UserId,
+        reply: Reply<bool>,
+    },
+    View {
+        user_id: UserId,
+        reply: Reply<GameView>,
+    },
+    SelectCards {
+        user_id: UserId,
+        cards: Vec<usize>,
+        reply: Reply<std::result::Result<(), SelectCardsError>>,
+    },
+    Chat {
+        user_id: UserId,
+        text: String,
+        reply: Reply<std::result::Result<ChatMessage, ChatError>>,
+    },
+}
+
+/// Owns one actor per game that is currently in use. Actors are spawned the first time
+/// a game is needed and shut themselves down after being idle for a while.
+#[derive(Clone)]
+pub struct GameActors {
+    db: Store,
+    broadcaster: Broadcaster,
+    countdown: Duration,
+    slots_by_game_id: Arc<Mutex<HashMap<GameId, Slot>>>,
+}
+
+impl GameActors {
+    /// Creates actors that end a turn `countdown` after every player has selected cards.
+    pub fn new(db: Store, broadcaster: Broadcaster, countdown: Duration) -> Self {
+        Self {
+            db,
+            broadcaster,
+            countdown,
+            slots_by_game_id: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Whether the user is seated in the game. Answered from the loaded game, so checking
+    /// access on every request does not read the game from the store.
+    pub async fn is_playing(&self, game_id: GameId, user_id: UserId) -> Result<bool> {
+        self.request(game_id, |reply| Command::IsPlaying { user_id, reply })
+            .await
+    }
+
+    pub async fn view(&self, game_id: GameId, user_id: UserId) -> Result<GameView> {
+        self.request(game_id, |reply| Command::View { user_id, reply })
+            .await
+    }
+
+    pub async fn select_cards(
+        &self,
+        game_id: GameId,
+        user_id: UserId,
+        cards: Vec<usize>,
+    ) -> Result<std::result::Result<(), SelectCardsError>> {
+        self.request(game_id, |reply| Command::SelectCards {
+            user_id,
+            cards,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn chat(
+        &self,
+        game_id: GameId,
+        user_id: UserId,
+        text: String,
+    ) -> Result<std::result::Result<ChatMessage, ChatError>> {
+        self.request(game_id, |reply| Command::Chat {
+            user_id,
+            text,
+            reply,
+        })
+        .await
+    }
+
+    /// How many games have an actor, i.e. have been used recently.
+    pub async fn active_games(&self) -> usize {
+        self.slots_by_game_id.lock().await.len()
+    }
+
+    async fn request<T, F>(&self, game_id: GameId, command: F) -> Result<T>
+    where
+        F: FnOnce(Reply<T>) -> Command,
+    {
+        let (reply, rx) = oneshot::channel();
+        let mut command = command(reply);
+
+        // The actor may shut down between looking up its sender and sending the command,
+        // in which case the command is handed back and a new actor is spawned.
+        loop {
+            let tx = self.sender(game_id).await?;
+
+            match tx.send(command) {
+                Ok(()) => break,
+                Err(e) => command = e.0,
+            }
+        }
+
+        rx.await.map_err(|_| Error::ActorStopped)?
+    }
+
+    async fn sender(&self, game_id: GameId) -> Result<UnboundedSender<Command>> {
+        loop {
+            let slot = self
+                .slots_by_game_id
+                .lock()
+                .await
+                .entry(game_id)
+                .or_default()
+                .clone();
+
+            let mut guard = slot.lock().await;
+
+            if let Some(tx) = guard.as_ref() {
+                return Ok(tx.clone());
+            }
+
+            // A stopping actor retires its slot, in which case a new one is needed.
+            if !self.is_current(game_id, &slot).await {
+                continue;
+            }
+
+            let (game, version) = match self.db.read_game(game_id).await {
+                Ok(Some(loaded)) => loaded,
+                Ok(None) => {
+                    self.retire(game_id, &slot).await;
+                    return Err(Error::GameNotFound);
+                }
+                Err(e) => {
+                    self.retire(game_id, &slot).await;
+                    return Err(e.into());
+                }
+            };
+
+            let (tx, rx) = unbounded_channel();
+
+            debug!("Spawning actor for game {} at version {}", game_id, version);
+
+            let actor = GameActor {
+                actors: self.clone(),
+                slot: slot.clone(),
+                game_id,
+                game,
+                version,
+                countdown: None,
+                rate_limiter: RateLimiter::default(),
+            };
+
+            tokio::spawn(actor.run(rx));
+            *guard = Some(tx.clone());
+            return Ok(tx);
+        }
+    }
+
+    async fn is_current(&self, game_id: GameId, slot: &Slot) -> bool {
+        let slots = self.slots_by_game_id.lock().await;
+        slots.get(&game_id).is_some_and(|s| Arc::ptr_eq(s, slot))
+    }
+
+    /// Removes an empty slot from the registry. Must be called while holding the slot's
+    /// lock, so nobody spawns an actor into it afterwards.
+    async fn retire(&self, game_id: GameId, slot: &Slot) {
+        let mut slots = self.slots_by_game_id.lock().await;
+
+        if slots.get(&game_id).is_some_and(|s| Arc::ptr_eq(s, slot)) {
+            slots.remove(&game_id);
+        }
+    }
+}
+
+struct GameActor {
+    actors: GameActors,
+    slot: Slot,
+    game_id: GameId,
+    game: Game,
+    version: u8,
+    countdown: Option<Instant>,
+    rate_limiter: RateLimiter,
+}
+
+impl GameActor {
+    async fn run(mut self, mut rx: UnboundedReceiver<Command>) {
+        // A game may have been left with every player ready, e.g. by a server restart
+        // during a countdown.
+        if self.game.ready_to_end_turn() {
+            self.start_countdown().await;
+        }
+
+        loop {
+            let slot = self.slot.clone();
+
+            if self.process(&mut rx).await.is_err() {
+                self.cancel_countdown();
+                let mut guard = slot.lock().await;
+                guard.take();
+                self.actors.retire(self.game_id, &slot).await;
+                warn!("Actor for game {} stopped after an error", self.game_id);
+                return;
+            }
+
+            // The actor is idle. Unregister it and finish any commands that were queued
+            // in the meantime, so a replacement actor never observes an older game state.
+            // Only this game's slot is locked meanwhile, so other games are unaffected.
+            let mut guard = slot.lock().await;
+            rx.close();
+
+            while let Ok(command) = rx.try_recv() {
+                if self.handle(command).await.is_err() {
+                    self.cancel_countdown();
+                    guard.take();
+                    self.actors.retire(self.game_id, &slot).await;
+                    return;
+                }
+            }
+
+            if self.countdown.is_none() {
+                guard.take();
+                self.actors.retire(self.game_id, &slot).await;
+                debug!("Actor for game {} stopped", self.game_id);
+                return;
+            }
+
+            // One of the queued commands started a countdown, so keep going.
+            let (tx, new_rx) = unbounded_channel();
+            *guard = Some(tx);
+            rx = new_rx;
+        }
+    }
+
+    /// Processes commands and countdowns until the actor has been idle for a while.
+    async fn process(
+        &mut self,
+        rx: &mut UnboundedReceiver<Command>,
+    ) -> std::result::Result<(), ()> {
+        loop {
+            let countdown = self.countdown;
+
+            tokio::select! {
+                command = rx.recv() => match command {
+                    Some(command) => self.handle(command).await?,
+                    None => return Ok(()),
+                },
+
+                _ = delay_until(countdown.unwrap_or_else(Instant::now)), if countdown.is_some() => {
+                    self.end_turn().await?;
+                }
+
+                _ = delay_for(IDLE_TIMEOUT), if countdown.is_none() => {
+                    info!("Actor for game {} is idle", self.game_id);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn handle(&mut self, command: Command) -> std::result::Result<(), ()> {
+        match command {
+            Command::IsPlaying { user_id, reply } => {
+                let _ = reply.send(Ok(self.is_playing(user_id)));
+                Ok(())
+            }
+
+            // Access is checked by the handler, so users who are not playing can view the
+            // game as spectators and see every player as an opponent.
+            Command::View { user_id, reply } => {
+                let _ = reply.send(Ok(self.game.clone().into(user_id)));
+                Ok(())
+            }
+
+            Command::SelectCards {
+                user_id,
+                cards,
+                reply,
+            } => {
+                if !self.is_playing(user_id) {
+                    let _ = reply.send(Err(Error::GameNotFound));
+                    return Ok(());
+                }
+
+                let events = match self.game.select_cards(user_id, cards) {
+                    Err(e) => {
+                        let _ = reply.send(Ok(Err(e)));
+                        return Ok(());
+                    }
+                    Ok(events) => events,
+                };
+
+                if events.is_empty() {
+                    let _ = reply.send(Ok(Ok(())));
+                    return Ok(());
+                }
+
+                if let Err(e) = self.persist().await {
+                    let _ = reply.send(Err(e));
+                    return Err(());
+                }
+
+                let _ = reply.send(Ok(Ok(())));
+
+                let cancelled = self.cancel_countdown();
+                let broadcaster = &self.actors.broadcaster;
+
+                if cancelled {
+                    broadcaster
+                        .send(self.game_id, &GameEvent::CountdownCancelled)
+                        .await;
+                }
+
+                for event in &events {
+                    broadcaster.send(self.game_id, event).await;
+                }
+
+                if self.game.ready_to_end_turn() {
+                    self.start_countdown().await;
+                }
+
+                Ok(())
+            }
+
+            Command::Chat {
+                user_id,
+                text,
+                reply,
+            } => {
+                if !self.is_playing(user_id) {
+                    let _ = reply.send(Err(Error::GameNotFound));
+                    return Ok(());
+                }
+
+                let checked = chat::validate(&text).and_then(|text| {
+                    self.rate_limiter.check(user_id, Instant::now())?;
+                    Ok(text)
+                });
+
+                let text = match checked {
+                    Err(e) => {
+                        let _ = reply.send(Ok(Err(e)));
+                        return Ok(());
+                    }
+                    Ok(text) => text,
+                };
+
+                // Chat is stored separately from the game, so a failure here does not
+                // leave the in-memory game state behind.
+                let message = match self
+                    .actors
+                    .db
+                    .persist_chat_message(self.game_id, user_id, &text)
+                    .await
+                {
+                    Err(e) => {
+                        let _ = reply.send(Err(e.into()));
+                        return Ok(());
+                    }
+                    Ok(message) => message,
+                };
+
+                self.actors
+                    .broadcaster
+                    .send_chat(self.game_id, &message)
+                    .await;
+
+                let _ = reply.send(Ok(Ok(message)));
+                Ok(())
+            }
+        }
+    }
+
+    async fn end_turn(&mut self) -> std::result::Result<(), ()> {
+        info!("Countdown for {} completed", self.game_id);
+
+        self.cancel_countdown();
+
+        let events = self.game.end_turn();
+
+        if let Err(e) = self.persist().await {
+            error!("failed to update game because {}", e);
+            return Err(());
+        }
+
+        for event in &events {
+            if let GameEvent::GameOver(_) = event {
+                metrics::game_completed();
+            }
+
+            self.actors.broadcaster.send(self.game_id, event).await;
+        }
+
+        Ok(())
+    }
+
+    async fn start_countdown(&mut self) {
+        let countdown = self.actors.countdown;
+
+        if self.countdown.replace(Instant::now() + countdown).is_none() {
+            metrics::countdown_started();
+        }
+
+        self.actors
+            .broadcaster
+            .send(self.game_id, &GameEvent::CountdownStarted(countdown))
+            .await;
+    }
+
+    /// Stops the countdown, if any, and returns whether there was one.
+    fn cancel_countdown(&mut self) -> bool {
+        let cancelled = self.countdown.take().is_some();
+
+        if cancelled {
+            metrics::countdown_ended();
+        }
+
+        cancelled
+    }
+
+    /// Writes the in-memory game through to the database. On failure the in-memory state
+    /// can no longer be trusted, so the caller should stop the actor.
+    async fn persist(&mut self) -> Result<()> {
+        self.actors
+            .db
+            .update_game(self.game_id, &self.game, self.version)
+            .await?;
+
+        self.version += 1;
+        Ok(())
+    }
+
+    fn is_playing(&self, user_id: UserId) -> bool {
+        self.game.user_ids().contains(&user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::db::MemoryStore;
+    use super::*;
+    use linked_hash_set::LinkedHashSet;
+    use std::convert::TryFrom;
+    use std::iter::FromIterator;
+
+    const COUNTDOWN: Duration = Duration::from_millis(3000);
+
+    lazy_static! {
+        static ref PLAYER1: UserId = UserId(1);
+        static ref PLAYER2: UserId = UserId(2);
+    }
+
+    async fn setup() -> (GameActors, Store, GameId) {
+        let db: Store = Arc::new(MemoryStore::new());
+
+        for name in &["a", "b"] {
+            db.get_or_insert_user_id(name).await.unwrap();
+        }
+
+        let user_ids = LinkedHashSet::from_iter(vec![*PLAYER1, *PLAYER2]);
+        let game = Game::try_from(user_ids).unwrap();
+        let game_id = db.persist_game(&game).await.unwrap();
+        let actors = GameActors::new(db.clone(), Broadcaster::new(), COUNTDOWN);
+        (actors, db, game_id)
+    }
+
+    #[actix_rt::test]
+    async fn can_view_game_for_player() {
+        // arrange
+        let (actors, _, game_id) = setup().await;
+
+        // act
+        let view = actors.view(game_id, *PLAYER1).await.unwrap();
+
+        // assert
+        assert!(view.player.is_some());
+        assert_eq!(view.opponents.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn spectator_sees_every_player_as_opponent() {
+        // arrange
+        let (actors, _, game_id) = setup().await;
+
+        // act
+        let view = actors.view(game_id, UserId(42)).await.unwrap();
+
+        // assert
+        assert!(view.player.is_none());
+        assert_eq!(view.opponents.len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn can_check_membership_for_player() {
+        // arrange
+        let (actors, _, game_id) = setup().await;
+
+        // act
+        let playing = actors.is_playing(game_id, *PLAYER1).await.unwrap();
+        let not_playing = actors.is_playing(game_id, UserId(42)).await.unwrap();
+
+        // assert
+        assert!(playing);
+        assert!(!not_playing);
+    }
+
+    #[actix_rt::test]
+    async fn select_cards_writes_through() {
+        // arrange
+        let (actors, db, game_id) = setup().await;
+        let view = actors.view(game_id, *PLAYER1).await.unwrap();
+        let card = *view.player.unwrap().hand.keys().next().unwrap();
+
+        // act
+        let result = actors.select_cards(game_id, *PLAYER1, vec![card]).await;
+
+        // assert
+        assert!(matches!(result, Ok(Ok(()))));
+        let (_, version) = db.read_game(game_id).await.unwrap().unwrap();
+        assert_eq!(version, 1);
+    }
+
+    #[actix_rt::test]
+    async fn cannot_select_cards_if_not_playing() {
+        // arrange
+        let (actors, db, game_id) = setup().await;
+        let view = actors.view(game_id, *PLAYER1).await.unwrap();
+        let card = *view.player.unwrap().hand.keys().next().unwrap();
+
+        // act
+        let error = actors
+            .select_cards(game_id, UserId(42), vec![card])
+            .await
+            .unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameNotFound));
+        let (_, version) = db.read_game(game_id).await.unwrap().unwrap();
+        assert_eq!(version, 0);
+    }
+
+    #[actix_rt::test]
+    async fn cannot_view_missing_game() {
+        // arrange
+        let (actors, _, _) = setup().await;
+
+        // act
+        let error = actors.view(GameId(42), *PLAYER1).await.unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameNotFound));
+        assert_eq!(actors.active_games().await, 0);
+    }
+
+    #[actix_rt::test]
+    async fn games_are_loaded_independently() {
+        // arrange
+        let (actors, db, game_id) = setup().await;
+        let user_ids = LinkedHashSet::from_iter(vec![*PLAYER1, *PLAYER2]);
+        let other_game_id = db
+            .persist_game(&Game::try_from(user_ids).unwrap())
+            .await
+            .unwrap();
+
+        // act
+        let (view, other_view) = futures::future::join(
+            actors.view(game_id, *PLAYER1),
+            actors.view(other_game_id, *PLAYER1),
+        )
+        .await;
+
+        // assert
+        assert!(view.is_ok() && other_view.is_ok());
+        assert_eq!(actors.active_games().await, 2);
+    }
+
+    #[actix_rt::test]
+    async fn select_cards_in_sequence_does_not_conflict() {
+        // arrange
+        let (actors, _, game_id) = setup().await;
+        let view = actors.view(game_id, *PLAYER1).await.unwrap();
+        let cards = view
+            .player
+            .unwrap()
+            .hand
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+
+        // act
+        let results = futures::future::join_all(
+            cards
+                .iter()
+                .map(|card| actors.select_cards(game_id, *PLAYER1, vec![*card])),
+        )
+        .await;
+
+        // assert
+        assert!(results.iter().all(|r| matches!(r, Ok(Ok(())))));
+    }
+
+    #[actix_rt::test]
+    async fn chat_is_stored_and_broadcast() {
+        // arrange
+        let (actors, db, game_id) = setup().await;
+        let mut rx = actors.broadcaster.subscribe(game_id).await;
+
+        // act
+        let result = actors
+            .chat(game_id, *PLAYER1, " hello ".to_string())
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(result.unwrap().text, "hello");
+        let event = rx.recv().await.unwrap();
+        assert!(event.starts_with(b"event: chat\n"));
+        let messages = db.get_chat_messages(game_id, 10).await.unwrap();
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn cannot_chat_if_not_playing() {
+        // arrange
+        let (actors, _, game_id) = setup().await;
+
+        // act
+        let error = actors
+            .chat(game_id, UserId(42), "hello".to_string())
+            .await
+            .unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameNotFound));
+    }
+
+    #[actix_rt::test]
+    async fn chat_is_rate_limited() {
+        // arrange
+        let (actors, _, game_id) = setup().await;
+        let mut results = vec![];
+
+        // act
+        for _ in 0..10 {
+            results.push(actors.chat(game_id, *PLAYER1, "spam".to_string()).await);
+        }
+
+        // assert
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, Ok(Err(ChatError::TooFast)))));
+    }
+}
@@ -0,0 +1,490 @@
+use super::actor::GameActors;
+use super::db::Store;
+use super::domain::UserId;
+use super::errors::ApiError;
+use super::tokens::{hash_token, Tokens, API_TOKEN_PREFIX};
+use super::GameId;
+use actix_web::{
+    dev::Payload,
+    web::{Data, Query},
+    FromRequest, HttpRequest,
+};
+use futures::future::{FutureExt, LocalBoxFuture};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+/// What a user may do. Everyone can play; spectators and admins may also watch games
+/// they are not seated in. Roles are granted with `--set-role <name> <role>`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Player,
+    Spectator,
+    Admin,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            Role::Player => write!(f, "player"),
+            Role::Spectator => write!(f, "spectator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "player" => Ok(Role::Player),
+            "spectator" => Ok(Role::Spectator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role '{}'", s)),
+        }
+    }
+}
+
+/// The user whose access token or, for bots, API token is in the `Authorization`
+/// header. Rejects the request with 401 if the token is missing, invalid or expired.
+/// The user's preferred locale, if any, is used for the messages in the response.
+///
+/// Browsers cannot set headers on an `EventSource`, so requests for server-sent events
+/// may pass the token in the `access_token` query parameter instead.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser {
+    pub id: UserId,
+    pub name: String,
+    pub role: Role,
+    pub is_bot: bool,
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, ApiError>>;
+    type Config = ();
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let request = request.clone();
+        async move { authenticate(&request).await }.boxed_local()
+    }
+}
+
+async fn authenticate(request: &HttpRequest) -> Result<AuthenticatedUser, ApiError> {
+    let token = bearer_token(request)?;
+
+    if token.starts_with(API_TOKEN_PREFIX) {
+        let db = store(request)?;
+
+        return match db.get_api_token_user(&hash_token(&token)).await? {
+            None => Err(ApiError::unauthorized()),
+            Some((id, name, role)) => Ok(AuthenticatedUser {
+                id,
+                name,
+                role,
+                is_bot: true,
+            }),
+        };
+    }
+
+    let claims = tokens(request)?
+        .decode(&token)
+        .map_err(|_| ApiError::unauthorized())?;
+
+    if let Some(locale) = claims.locale {
+        request.extensions_mut().insert(locale);
+    }
+
+    Ok(AuthenticatedUser {
+        id: claims.sub,
+        name: claims.name,
+        role: claims.role,
+        is_bot: false,
+    })
+}
+
+fn bearer_token(request: &HttpRequest) -> Result<String, ApiError> {
+    match request.headers().get("Authorization") {
+        Some(header) => {
+            let header = header.to_str().map_err(|_| ApiError::unauthorized())?;
+
+            match header.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["Bearer", token] => Ok(token.to_string()),
+                _ => Err(ApiError::unauthorized()),
+            }
+        }
+        None if accepts_event_stream(request) => query_token(request),
+        None => Err(ApiError::unauthorized()),
+    }
+}
+
+fn accepts_event_stream(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get("Accept")
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"))
+}
+
+fn query_token(request: &HttpRequest) -> Result<String, ApiError> {
+    #[derive(Deserialize)]
+    struct TokenQuery {
+        access_token: String,
+    }
+
+    Query::<TokenQuery>::from_query(request.query_string())
+        .map(|query| query.into_inner().access_token)
+        .map_err(|_| ApiError::unauthorized())
+}
+
+fn tokens(request: &HttpRequest) -> Result<Data<Tokens>, ApiError> {
+    request
+        .app_data::<Data<Tokens>>()
+        .cloned()
+        .ok_or_else(|| ApiError::internal("tokens are not registered"))
+}
+
+fn store(request: &HttpRequest) -> Result<Store, ApiError> {
+    request
+        .app_data::<Data<Store>>()
+        .map(|db| db.get_ref().clone())
+        .ok_or_else(|| ApiError::internal("store is not registered"))
+}
+
+/// A user with access to the game in the `{game_id}` path segment, either by being
+/// seated in it or by being allowed to watch any game. Rejects the request with 401 if
+/// the user is not logged in, 404 if there is no such game and 403 if the user may not
+/// see it.
+#[derive(Debug)]
+pub struct GameMember {
+    pub user: AuthenticatedUser,
+    pub game_id: GameId,
+    /// `Player` if the user is seated in the game, otherwise the role that allows them
+    /// to watch it.
+    pub role: Role,
+}
+
+impl FromRequest for GameMember {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, ApiError>>;
+    type Config = ();
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let request = request.clone();
+
+        async move {
+            let user = authenticate(&request).await?;
+            let game_id = request.match_info().query("game_id").parse().map(GameId);
+            let game_id = game_id.map_err(|_| ApiError::not_found())?;
+
+            let actors = request
+                .app_data::<Data<GameActors>>()
+                .ok_or_else(|| ApiError::internal("game actors are not registered"))?;
+
+            let role = if actors.is_playing(game_id, user.id).await? {
+                Role::Player
+            } else if user.role == Role::Player {
+                return Err(ApiError::forbidden());
+            } else {
+                user.role
+            };
+
+            Ok(GameMember {
+                user,
+                game_id,
+                role,
+            })
+        }
+        .boxed_local()
+    }
+}
+
+/// A user seated in the game in the `{game_id}` path segment. Like `GameMember`, but
+/// also rejects users who may only watch the game with 403.
+#[derive(Debug)]
+pub struct GamePlayer {
+    pub user_id: UserId,
+    pub game_id: GameId,
+}
+
+impl FromRequest for GamePlayer {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, ApiError>>;
+    type Config = ();
+
+    fn from_request(request: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        GameMember::from_request(request, payload)
+            .map(|member| match member? {
+                GameMember {
+                    user,
+                    game_id,
+                    role: Role::Player,
+                } => Ok(GamePlayer {
+                    user_id: user.id,
+                    game_id,
+                }),
+                _ => Err(ApiError::forbidden()),
+            })
+            .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::db::MemoryStore;
+    use super::super::domain::Game;
+    use super::super::i18n::Locale;
+    use super::super::pubsub::Broadcaster;
+    use super::super::tokens::new_api_token;
+    use super::*;
+    use actix_web::{http::StatusCode, test::TestRequest, ResponseError};
+    use chrono::Duration;
+    use linked_hash_set::LinkedHashSet;
+    use std::convert::TryFrom;
+    use std::iter::FromIterator;
+    use std::sync::Arc;
+
+    lazy_static! {
+        static ref PLAYER1: UserId = UserId(1);
+        static ref PLAYER2: UserId = UserId(2);
+        static ref OUTSIDER: UserId = UserId(3);
+    }
+
+    fn tokens() -> Tokens {
+        let keys = vec![("a".to_string(), b"secret".to_vec())];
+        Tokens::new(keys, Duration::minutes(15), Duration::days(30))
+    }
+
+    async fn setup() -> (Store, GameId) {
+        let db: Store = Arc::new(MemoryStore::new());
+
+        for name in &["a", "b", "c"] {
+            db.get_or_insert_user_id(name).await.unwrap();
+        }
+
+        let user_ids = LinkedHashSet::from_iter(vec![*PLAYER1, *PLAYER2]);
+        let game = Game::try_from(user_ids).unwrap();
+        let game_id = db.persist_game(&game).await.unwrap();
+        (db, game_id)
+    }
+
+    fn request(db: &Store, game_id: &'static str, user: Option<(UserId, Role)>) -> TestRequest {
+        let tokens = tokens();
+        let countdown = std::time::Duration::from_secs(1);
+        let actors = GameActors::new(db.clone(), Broadcaster::new(), countdown);
+        let mut request = TestRequest::default()
+            .param("game_id", game_id)
+            .app_data(Data::new(db.clone()))
+            .app_data(Data::new(actors));
+
+        if let Some((user_id, role)) = user {
+            let refresh_token = tokens.new_refresh_token();
+            let pair = tokens.issue(user_id, "a".to_string(), role, None, &refresh_token);
+            let header = format!("Bearer {}", pair.access_token);
+            request = request.header("Authorization", header);
+        }
+
+        request.app_data(Data::new(tokens))
+    }
+
+    async fn extract<T>(request: TestRequest) -> Result<T, StatusCode>
+    where
+        T: FromRequest<Error = ApiError>,
+    {
+        let (request, mut payload) = request.to_http_parts();
+
+        T::from_request(&request, &mut payload)
+            .await
+            .map_err(|e| e.status_code())
+    }
+
+    #[actix_rt::test]
+    async fn user_without_token_is_unauthorized() {
+        // arrange
+        let (db, _) = setup().await;
+
+        // act
+        let result = extract::<AuthenticatedUser>(request(&db, "1", None)).await;
+
+        // assert
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn user_with_invalid_token_is_unauthorized() {
+        // arrange
+        let (db, _) = setup().await;
+        let request = request(&db, "1", None).header("Authorization", "Bearer nope");
+
+        // act
+        let result = extract::<AuthenticatedUser>(request).await;
+
+        // assert
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn bot_can_authenticate_with_api_token() {
+        // arrange
+        let (db, _) = setup().await;
+        let bot_id = db.insert_bot("bot", *PLAYER1).await.unwrap().unwrap();
+        let (token, hash) = new_api_token();
+        db.persist_api_token(bot_id, *PLAYER1, &hash).await.unwrap();
+        let header = format!("Bearer {}", token);
+
+        // act
+        let user =
+            extract::<AuthenticatedUser>(request(&db, "1", None).header("Authorization", header))
+                .await
+                .unwrap();
+
+        // assert
+        assert_eq!(user.id, bot_id);
+        assert!(user.is_bot);
+    }
+
+    #[actix_rt::test]
+    async fn revoked_api_token_is_unauthorized() {
+        // arrange
+        let (db, _) = setup().await;
+        let bot_id = db.insert_bot("bot", *PLAYER1).await.unwrap().unwrap();
+        let (token, hash) = new_api_token();
+        let api_token = db.persist_api_token(bot_id, *PLAYER1, &hash).await.unwrap();
+        db.delete_api_token(api_token.unwrap().id, bot_id, *PLAYER1)
+            .await
+            .unwrap();
+        let header = format!("Bearer {}", token);
+
+        // act
+        let result =
+            extract::<AuthenticatedUser>(request(&db, "1", None).header("Authorization", header))
+                .await;
+
+        // assert
+        assert_eq!(result.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn event_stream_can_authenticate_with_query_token() {
+        // arrange
+        let (db, _) = setup().await;
+        let tokens = tokens();
+        let refresh_token = tokens.new_refresh_token();
+        let pair = tokens.issue(
+            *PLAYER1,
+            "a".to_string(),
+            Role::Player,
+            None,
+            &refresh_token,
+        );
+        let uri = format!("/stream?access_token={}", pair.access_token);
+
+        // act
+        let stream = extract::<AuthenticatedUser>(
+            request(&db, "1", None)
+                .uri(&uri)
+                .header("Accept", "text/event-stream"),
+        )
+        .await;
+        let json = extract::<AuthenticatedUser>(request(&db, "1", None).uri(&uri)).await;
+
+        // assert
+        assert_eq!(stream.unwrap().id, *PLAYER1);
+        assert_eq!(json.unwrap_err(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn preferred_locale_overrides_accept_language() {
+        // arrange
+        let (db, _) = setup().await;
+        let tokens = tokens();
+        let refresh_token = tokens.new_refresh_token();
+        let pair = tokens.issue(
+            *PLAYER1,
+            "a".to_string(),
+            Role::Player,
+            Some(Locale::Da),
+            &refresh_token,
+        );
+        let (request, mut payload) = request(&db, "1", None)
+            .header("Authorization", format!("Bearer {}", pair.access_token))
+            .header("Accept-Language", "en")
+            .to_http_parts();
+
+        // act
+        AuthenticatedUser::from_request(&request, &mut payload)
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(Locale::of(&request), Locale::Da);
+    }
+
+    #[actix_rt::test]
+    async fn seated_user_is_player() {
+        // arrange
+        let (db, game_id) = setup().await;
+        let request = request(&db, "1", Some((*PLAYER1, Role::Player)));
+
+        // act
+        let member = extract::<GameMember>(request).await.unwrap();
+
+        // assert
+        assert_eq!(member.game_id, game_id);
+        assert_eq!(member.user.id, *PLAYER1);
+        assert_eq!(member.role, Role::Player);
+    }
+
+    #[actix_rt::test]
+    async fn outsider_is_forbidden() {
+        // arrange
+        let (db, _) = setup().await;
+        let request = request(&db, "1", Some((*OUTSIDER, Role::Player)));
+
+        // act
+        let result = extract::<GameMember>(request).await;
+
+        // assert
+        assert_eq!(result.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn missing_game_is_not_found() {
+        // arrange
+        let (db, _) = setup().await;
+        let request = request(&db, "42", Some((*PLAYER1, Role::Player)));
+
+        // act
+        let result = extract::<GameMember>(request).await;
+
+        // assert
+        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn spectator_can_watch_but_not_play() {
+        // arrange
+        let (db, _) = setup().await;
+        let user = Some((*OUTSIDER, Role::Spectator));
+
+        // act
+        let member = extract::<GameMember>(request(&db, "1", user)).await;
+        let player = extract::<GamePlayer>(request(&db, "1", user)).await;
+
+        // assert
+        assert_eq!(member.unwrap().role, Role::Spectator);
+        assert_eq!(player.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn roles_round_trip_through_strings() {
+        for role in &[Role::Player, Role::Spectator, Role::Admin] {
+            assert_eq!(role.to_string().parse::<Role>(), Ok(*role));
+        }
+
+        assert!("root".parse::<Role>().is_err());
+    }
+}
@@ -0,0 +1,30 @@
+use super::domain::UserId;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// An account played by a program instead of a person. Bots authenticate with API tokens
+/// created by their owner and cannot log in with a password.
+#[derive(Debug, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bot {
+    pub id: UserId,
+    pub name: String,
+    pub tokens: Vec<ApiToken>,
+}
+
+/// What is stored about an API token. The token itself is only shown when it is created.
+#[derive(Debug, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub id: i64,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewApiToken {
+    #[serde(flatten)]
+    pub info: ApiToken,
+    pub token: String,
+}
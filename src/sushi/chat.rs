@@ -0,0 +1,138 @@
+use super::domain::UserId;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const MAX_MESSAGE_LENGTH: usize = 500;
+
+/// The number of messages sent with a game when it is loaded.
+pub const HISTORY_LENGTH: usize = 50;
+
+const MAX_MESSAGES_PER_WINDOW: usize = 5;
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessage {
+    pub user_id: UserId,
+    pub name: String,
+    pub text: String,
+    pub sent: DateTime<Utc>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ChatError {
+    Empty,
+    TooLong,
+    TooFast,
+}
+
+impl Display for ChatError {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            ChatError::Empty => write!(f, "The message is empty"),
+            ChatError::TooLong => write!(
+                f,
+                "Messages can be at most {} characters long",
+                MAX_MESSAGE_LENGTH
+            ),
+            ChatError::TooFast => write!(f, "You are sending messages too quickly"),
+        }
+    }
+}
+
+impl Error for ChatError {}
+
+/// Trims the message and checks its length, counted in characters.
+pub fn validate(text: &str) -> Result<String, ChatError> {
+    let text = text.trim();
+
+    if text.is_empty() {
+        return Err(ChatError::Empty);
+    }
+
+    if text.chars().count() > MAX_MESSAGE_LENGTH {
+        return Err(ChatError::TooLong);
+    }
+
+    Ok(text.to_string())
+}
+
+/// Allows each user a few messages within a sliding window.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    sent_by_user_id: HashMap<UserId, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    /// Records a message from the user at `now`, unless the user has sent too many
+    /// messages recently.
+    pub fn check(&mut self, user_id: UserId, now: Instant) -> Result<(), ChatError> {
+        let sent = self.sent_by_user_id.entry(user_id).or_default();
+
+        while sent.front().is_some_and(|t| now - *t >= RATE_WINDOW) {
+            sent.pop_front();
+        }
+
+        if sent.len() >= MAX_MESSAGES_PER_WINDOW {
+            return Err(ChatError::TooFast);
+        }
+
+        sent.push_back(now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_is_trimmed() {
+        assert_eq!(validate("  hi  "), Ok("hi".to_string()));
+    }
+
+    #[test]
+    fn cannot_send_empty_message() {
+        assert_eq!(validate(" \n "), Err(ChatError::Empty));
+    }
+
+    #[test]
+    fn cannot_send_too_long_message() {
+        // arrange
+        let text = "æ".repeat(MAX_MESSAGE_LENGTH + 1);
+
+        // act
+        let result = validate(&text);
+
+        // assert
+        assert_eq!(result, Err(ChatError::TooLong));
+        assert!(validate(&text[2..]).is_ok());
+    }
+
+    #[test]
+    fn rate_limiter_allows_messages_again_after_window() {
+        // arrange
+        let mut limiter = RateLimiter::default();
+        let user_id = UserId(1);
+        let now = Instant::now();
+
+        for _ in 0..MAX_MESSAGES_PER_WINDOW {
+            limiter.check(user_id, now).unwrap();
+        }
+
+        // act
+        let too_fast = limiter.check(user_id, now);
+        let later = limiter.check(user_id, now + RATE_WINDOW);
+
+        // assert
+        assert_eq!(too_fast, Err(ChatError::TooFast));
+        assert_eq!(later, Ok(()));
+        assert_eq!(limiter.check(UserId(2), now), Ok(()));
+    }
+}
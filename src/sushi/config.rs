@@ -0,0 +1,512 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const DEFAULT_CONFIG_PATH: &str = "./sushi.toml";
+const ENV_PREFIX: &str = "SUSHI_";
+
+/// Settings that can be overridden by environment variables and command line flags. A
+/// setting such as `database-url` is read from `SUSHI_DATABASE_URL` and
+/// `--database-url`.
+const SETTINGS: &[&str] = &[
+    "bind",
+    "store",
+    "database-path",
+    "database-url",
+    "static-dir",
+    "countdown-ms",
+    "jwt-keys",
+    "access-token-minutes",
+    "refresh-token-days",
+    "log-level",
+    "metrics-token",
+];
+
+/// Server configuration. Defaults are overridden by the TOML file given by `--config` or
+/// `SUSHI_CONFIG` (`./sushi.toml` if it exists), then by environment variables and
+/// finally by command line flags. The log level is also read from `RUST_LOG`, which
+/// `SUSHI_LOG_LEVEL` takes precedence over.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: Vec<String>,
+    pub static_dir: PathBuf,
+    /// How long players can change their minds once everyone has selected cards.
+    pub countdown_ms: u64,
+    /// A `RUST_LOG` style filter, e.g. `info` or `sushi=debug,actix_web=info`.
+    pub log_level: String,
+    /// The bearer token Prometheus scrapes `/metrics` with. Metrics are not served
+    /// without one.
+    pub metrics_token: Option<String>,
+    pub database: DatabaseConfig,
+    pub jwt: JwtConfig,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub backend: Backend,
+    /// The SQLite database file.
+    pub path: PathBuf,
+    /// The PostgreSQL connection string.
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+    Memory,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtConfig {
+    /// Signing keys, current key first. Without keys a random key is generated at startup
+    /// and tokens do not survive a restart.
+    pub keys: Vec<JwtKey>,
+    pub access_token_minutes: i64,
+    pub refresh_token_days: i64,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct JwtKey {
+    pub id: String,
+    pub secret: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec!["127.0.0.1:8080".to_string()],
+            static_dir: PathBuf::from("./frontend/build"),
+            countdown_ms: 3000,
+            log_level: "info".to_string(),
+            metrics_token: None,
+            database: DatabaseConfig::default(),
+            jwt: JwtConfig::default(),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Sqlite,
+            path: PathBuf::from("./sushi.db"),
+            url: None,
+        }
+    }
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            keys: vec![],
+            access_token_minutes: 15,
+            refresh_token_days: 30,
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sqlite" => Ok(Backend::Sqlite),
+            "postgres" => Ok(Backend::Postgres),
+            "memory" => Ok(Backend::Memory),
+            _ => Err("expected sqlite, postgres or memory".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    Read(PathBuf, String),
+    Parse(PathBuf, String),
+    InvalidSetting(String, String),
+    MissingFlagValue(String),
+    Invalid(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "cannot parse {}: {}", path.display(), e),
+            ConfigError::InvalidSetting(name, e) => write!(f, "invalid {}: {}", name, e),
+            ConfigError::MissingFlagValue(flag) => write!(f, "missing value for {}", flag),
+            ConfigError::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    /// Loads the configuration for this process.
+    pub fn load() -> Result<Self, ConfigError> {
+        let args: Vec<_> = std::env::args().skip(1).collect();
+        Self::from_sources(&args, std::env::vars())
+    }
+
+    fn from_sources<I>(args: &[String], env: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let env: Vec<_> = env.into_iter().collect();
+
+        let rust_log = env
+            .iter()
+            .find(|(key, _)| key == "RUST_LOG")
+            .map(|(_, value)| value.clone());
+
+        let env: Vec<_> = env
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.strip_prefix(ENV_PREFIX)?.to_string(), value)))
+            .collect();
+
+        let flags = parse_flags(args)?;
+
+        let path = flags
+            .iter()
+            .chain(env.iter())
+            .find(|(key, _)| key == "config" || key == "CONFIG")
+            .map(|(_, value)| PathBuf::from(value));
+
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        if let Some(filters) = rust_log {
+            config.set("log-level", &filters)?;
+        }
+
+        for setting in SETTINGS {
+            let env_key = setting.to_uppercase().replace('-', "_");
+
+            if let Some((_, value)) = env.iter().find(|(key, _)| *key == env_key) {
+                config.set(setting, value)?;
+            }
+        }
+
+        for (key, value) in &flags {
+            if SETTINGS.contains(&key.as_str()) {
+                config.set(key, value)?;
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text =
+            fs::read_to_string(path).map_err(|e| ConfigError::Read(path.into(), e.to_string()))?;
+
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.into(), e.to_string()))
+    }
+
+    fn set(&mut self, setting: &str, value: &str) -> Result<(), ConfigError> {
+        let invalid = |e: String| ConfigError::InvalidSetting(setting.to_string(), e);
+
+        match setting {
+            "bind" => self.bind = value.split(',').map(|a| a.trim().to_string()).collect(),
+            "store" => self.database.backend = value.parse().map_err(invalid)?,
+            "database-path" => self.database.path = PathBuf::from(value),
+            "database-url" => self.database.url = Some(value.to_string()),
+            "static-dir" => self.static_dir = PathBuf::from(value),
+            "countdown-ms" => self.countdown_ms = parse_number(value).map_err(invalid)?,
+            "jwt-keys" => self.jwt.keys = parse_keys(value).map_err(invalid)?,
+            "access-token-minutes" => {
+                self.jwt.access_token_minutes = parse_number(value).map_err(invalid)?
+            }
+            "refresh-token-days" => {
+                self.jwt.refresh_token_days = parse_number(value).map_err(invalid)?
+            }
+            "log-level" => self.log_level = value.to_string(),
+            "metrics-token" => self.metrics_token = Some(value.to_string()),
+            _ => unreachable!("unknown setting {}", setting),
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.bind.is_empty() {
+            return Err(ConfigError::Invalid("no bind address".to_string()));
+        }
+
+        for address in &self.bind {
+            if address.parse::<SocketAddr>().is_err() {
+                return Err(ConfigError::Invalid(format!(
+                    "bind address '{}' is not an ip:port pair",
+                    address
+                )));
+            }
+        }
+
+        if self.database.backend == Backend::Postgres {
+            if cfg!(not(feature = "postgres")) {
+                return Err(ConfigError::Invalid(
+                    "the postgres store requires building with the postgres feature".to_string(),
+                ));
+            }
+
+            if self.database.url.is_none() {
+                return Err(ConfigError::Invalid(
+                    "the postgres store requires a database url".to_string(),
+                ));
+            }
+        }
+
+        let mut ids = HashSet::new();
+
+        for key in &self.jwt.keys {
+            if key.id.is_empty() || key.secret.is_empty() {
+                return Err(ConfigError::Invalid(
+                    "jwt keys need both an id and a secret".to_string(),
+                ));
+            }
+
+            if !ids.insert(&key.id) {
+                return Err(ConfigError::Invalid(format!(
+                    "jwt key id '{}' is used more than once",
+                    key.id
+                )));
+            }
+        }
+
+        if self.jwt.access_token_minutes <= 0 || self.jwt.refresh_token_days <= 0 {
+            return Err(ConfigError::Invalid(
+                "token lifetimes must be positive".to_string(),
+            ));
+        }
+
+        if self.log_level.trim().is_empty() {
+            return Err(ConfigError::Invalid("log level is empty".to_string()));
+        }
+
+        if self.metrics_token.as_deref().is_some_and(str::is_empty) {
+            return Err(ConfigError::Invalid("metrics token is empty".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads `--name value` and `--name=value` flags. Flags without a value, such as
+/// `--migrate-dry-run`, are left to the caller.
+fn parse_flags(args: &[String]) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut flags = vec![];
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) => flag,
+            None => continue,
+        };
+
+        if let Some(i) = flag.find('=') {
+            flags.push((flag[..i].to_string(), flag[i + 1..].to_string()));
+        } else if flag == "config" || SETTINGS.contains(&flag) {
+            let value = args
+                .next()
+                .ok_or_else(|| ConfigError::MissingFlagValue(arg.clone()))?;
+
+            flags.push((flag.to_string(), value.clone()));
+        }
+    }
+
+    Ok(flags)
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", value))
+}
+
+/// Reads comma-separated `id:secret` pairs.
+fn parse_keys(value: &str) -> Result<Vec<JwtKey>, String> {
+    value
+        .split(',')
+        .map(|pair| {
+            let mut parts = pair.trim().splitn(2, ':');
+
+            match (parts.next(), parts.next()) {
+                (Some(id), Some(secret)) => Ok(JwtKey {
+                    id: id.to_string(),
+                    secret: secret.to_string(),
+                }),
+                _ => Err("expected id:secret pairs".to_string()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn write_file(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, text).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        // act
+        let config = Config::from_sources(&[], vec![]).unwrap();
+
+        // assert
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn can_read_file() {
+        // arrange
+        let path = write_file(
+            "sushi-config-test.toml",
+            r#"
+            bind = ["0.0.0.0:80", "[::]:80"]
+            countdown_ms = 1000
+
+            [database]
+            backend = "memory"
+
+            [jwt]
+            keys = [{ id = "a", secret = "s" }]
+            "#,
+        );
+
+        // act
+        let config = Config::from_sources(&args(&["--config", &path]), vec![]).unwrap();
+
+        // assert
+        assert_eq!(config.bind, vec!["0.0.0.0:80", "[::]:80"]);
+        assert_eq!(config.countdown_ms, 1000);
+        assert_eq!(config.database.backend, Backend::Memory);
+        assert_eq!(config.jwt.keys[0].id, "a");
+        assert_eq!(config.jwt.access_token_minutes, 15);
+    }
+
+    #[test]
+    fn flags_override_environment() {
+        // arrange
+        let env = env(&[
+            ("SUSHI_COUNTDOWN_MS", "1000"),
+            ("SUSHI_STORE", "memory"),
+            ("SUSHI_JWT_KEYS", "new:one, old:two"),
+        ]);
+
+        // act
+        let config = Config::from_sources(&args(&["--countdown-ms=500"]), env).unwrap();
+
+        // assert
+        assert_eq!(config.countdown_ms, 500);
+        assert_eq!(config.database.backend, Backend::Memory);
+        assert_eq!(config.jwt.keys.len(), 2);
+        assert_eq!(config.jwt.keys[1].secret, "two");
+    }
+
+    #[test]
+    fn log_level_is_read_from_rust_log() {
+        // arrange
+        let rust_log = env(&[("RUST_LOG", "sushi=debug")]);
+        let both = env(&[("RUST_LOG", "sushi=debug"), ("SUSHI_LOG_LEVEL", "warn")]);
+
+        // act
+        let from_rust_log = Config::from_sources(&[], rust_log).unwrap();
+        let from_both = Config::from_sources(&[], both).unwrap();
+
+        // assert
+        assert_eq!(from_rust_log.log_level, "sushi=debug");
+        assert_eq!(from_both.log_level, "warn");
+    }
+
+    #[test]
+    fn other_flags_are_ignored() {
+        // act
+        let config = Config::from_sources(&args(&["--set-role", "a", "admin"]), vec![]);
+
+        // assert
+        assert!(config.is_ok());
+    }
+
+    #[test]
+    fn unknown_file_settings_are_rejected() {
+        // arrange
+        let path = write_file("sushi-config-typo.toml", "countdown = 1000");
+
+        // act
+        let error = Config::from_sources(&args(&["--config", &path]), vec![]).unwrap_err();
+
+        // assert
+        assert!(matches!(error, ConfigError::Parse(..)));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        // act
+        let number = Config::from_sources(&args(&["--countdown-ms", "soon"]), vec![]);
+        let store = Config::from_sources(&args(&["--store", "mysql"]), vec![]);
+        let bind = Config::from_sources(&args(&["--bind", "localhost"]), vec![]);
+        let missing = Config::from_sources(&args(&["--bind"]), vec![]);
+
+        // assert
+        assert!(matches!(number, Err(ConfigError::InvalidSetting(..))));
+        assert!(matches!(store, Err(ConfigError::InvalidSetting(..))));
+        assert!(matches!(bind, Err(ConfigError::Invalid(_))));
+        assert!(matches!(missing, Err(ConfigError::MissingFlagValue(_))));
+    }
+
+    #[test]
+    fn empty_metrics_token_is_rejected() {
+        // arrange
+        let env = env(&[("SUSHI_METRICS_TOKEN", "")]);
+
+        // act
+        let result = Config::from_sources(&[], env);
+
+        // assert
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn duplicate_jwt_key_ids_are_rejected() {
+        // arrange
+        let env = env(&[("SUSHI_JWT_KEYS", "a:one,a:two")]);
+
+        // act
+        let result = Config::from_sources(&[], env);
+
+        // assert
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+}
@@ -0,0 +1,152 @@
+use super::*;
+use serde_json::{Map, Value};
+
+/// The shape of `Game` as currently serialized. Bump this whenever a change to `Game`,
+/// `Player` or `Card` would make old rows fail to deserialize, and add an upgrade below.
+pub const SCHEMA_VERSION: u64 = 2;
+
+const SCHEMA_VERSION_KEY: &str = "schemaVersion";
+
+type Upgrade = fn(Map<String, Value>) -> std::result::Result<Map<String, Value>, String>;
+
+/// `UPGRADES[n]` turns a game stored at schema version `n` into version `n + 1`.
+static UPGRADES: &[Upgrade] = &[upgrade_v0, upgrade_v1];
+
+/// Games written before the schema version was embedded have the same shape as version 1.
+fn upgrade_v0(game: Map<String, Value>) -> std::result::Result<Map<String, Value>, String> {
+    Ok(game)
+}
+
+/// Version 2 records the cards each player has played. Earlier games start with none.
+fn upgrade_v1(mut game: Map<String, Value>) -> std::result::Result<Map<String, Value>, String> {
+    let players = game
+        .get_mut("players")
+        .and_then(Value::as_array_mut)
+        .ok_or("missing players")?;
+
+    for player in players {
+        player
+            .as_object_mut()
+            .ok_or("player is not an object")?
+            .insert("playedCards".to_string(), Value::Array(vec![]));
+    }
+
+    Ok(game)
+}
+
+pub fn serialize(game: &Game) -> Value {
+    let mut json = serde_json::to_value(game).expect("can serialize game to json");
+
+    json.as_object_mut()
+        .expect("game serializes to an object")
+        .insert(SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.into());
+
+    json
+}
+
+pub fn deserialize(game_id: GameId, json: Value) -> Result<Game> {
+    let unreadable = |reason: String| Error::UnreadableGame(game_id, reason);
+
+    let mut game = match json {
+        Value::Object(game) => game,
+        _ => return Err(unreadable("expected a JSON object".to_string())),
+    };
+
+    let version = match game.remove(SCHEMA_VERSION_KEY) {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| unreadable(format!("invalid schema version {}", version)))?,
+    };
+
+    if version > SCHEMA_VERSION {
+        return Err(unreadable(format!("unknown schema version {}", version)));
+    }
+
+    for upgrade in &UPGRADES[version as usize..] {
+        game = upgrade(game).map_err(unreadable)?;
+    }
+
+    serde_json::from_value(Value::Object(game)).map_err(|e| unreadable(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linked_hash_set::LinkedHashSet;
+    use serde_json::json;
+    use std::convert::TryFrom;
+    use std::iter::FromIterator;
+
+    fn game() -> Game {
+        Game::try_from(LinkedHashSet::from_iter(vec![UserId(1), UserId(2)])).unwrap()
+    }
+
+    #[test]
+    fn there_is_an_upgrade_for_every_old_version() {
+        assert_eq!(UPGRADES.len() as u64, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn can_round_trip_game() {
+        // arrange
+        let game = game();
+
+        // act
+        let actual = deserialize(GameId(1), serialize(&game)).unwrap();
+
+        // assert
+        assert_eq!(actual, game);
+    }
+
+    #[test]
+    fn embeds_schema_version() {
+        // act
+        let json = serialize(&game());
+
+        // assert
+        assert_eq!(json[SCHEMA_VERSION_KEY], json!(SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn can_read_game_without_schema_version() {
+        // arrange
+        let game = game();
+        let mut json = serde_json::to_value(&game).unwrap();
+
+        for player in json["players"].as_array_mut().unwrap() {
+            player.as_object_mut().unwrap().remove("playedCards");
+        }
+
+        // act
+        let actual = deserialize(GameId(1), json).unwrap();
+
+        // assert
+        assert_eq!(actual, game);
+    }
+
+    #[test]
+    fn cannot_read_game_from_the_future() {
+        // arrange
+        let mut json = serialize(&game());
+        json[SCHEMA_VERSION_KEY] = json!(SCHEMA_VERSION + 1);
+
+        // act
+        let error = deserialize(GameId(1), json).unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::UnreadableGame(GameId(1), _)));
+    }
+
+    #[test]
+    fn cannot_read_malformed_game() {
+        // arrange
+        let json = json!({ "round": "one" });
+
+        // act
+        let error = deserialize(GameId(1), json).unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::UnreadableGame(GameId(1), _)));
+    }
+}
@@ -0,0 +1,289 @@
+use super::super::metrics;
+use super::*;
+use std::future::Future;
+use std::time::Instant;
+
+/// Wraps another store to record how long each call takes and how often writes run into
+/// a version conflict, whichever backend is used.
+pub struct InstrumentedStore {
+    inner: Store,
+}
+
+impl InstrumentedStore {
+    pub fn new(inner: Store) -> Self {
+        Self { inner }
+    }
+}
+
+async fn time<T, F>(operation: &str, call: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let start = Instant::now();
+    let result = call.await;
+    let conflict = matches!(result, Err(Error::GameVersionConflict));
+
+    metrics::observe_db_call(operation, start.elapsed(), conflict);
+    result
+}
+
+#[async_trait]
+impl GameStore for InstrumentedStore {
+    async fn migrate(&self) -> Result<()> {
+        time("migrate", self.inner.migrate()).await
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<&'static Migration>> {
+        time("pending_migrations", self.inner.pending_migrations()).await
+    }
+
+    async fn get_or_insert_user_id(&self, user_name: &str) -> Result<UserId> {
+        time(
+            "get_or_insert_user_id",
+            self.inner.get_or_insert_user_id(user_name),
+        )
+        .await
+    }
+
+    async fn insert_user(&self, user_name: &str, password_hash: &str) -> Result<Option<UserId>> {
+        time(
+            "insert_user",
+            self.inner.insert_user(user_name, password_hash),
+        )
+        .await
+    }
+
+    async fn get_credentials(&self, user_name: &str) -> Result<Option<(UserId, Option<String>)>> {
+        time("get_credentials", self.inner.get_credentials(user_name)).await
+    }
+
+    async fn set_password_hash(&self, user_id: UserId, password_hash: &str) -> Result<bool> {
+        time(
+            "set_password_hash",
+            self.inner.set_password_hash(user_id, password_hash),
+        )
+        .await
+    }
+
+    async fn get_user_id(&self, user_name: &str) -> Result<Option<UserId>> {
+        time("get_user_id", self.inner.get_user_id(user_name)).await
+    }
+
+    async fn get_user_role(&self, user_id: UserId) -> Result<Option<Role>> {
+        time("get_user_role", self.inner.get_user_role(user_id)).await
+    }
+
+    async fn set_user_role(&self, user_id: UserId, role: Role) -> Result<bool> {
+        time("set_user_role", self.inner.set_user_role(user_id, role)).await
+    }
+
+    async fn get_user_locale(&self, user_id: UserId) -> Result<Option<Locale>> {
+        time("get_user_locale", self.inner.get_user_locale(user_id)).await
+    }
+
+    async fn set_user_locale(&self, user_id: UserId, locale: Option<Locale>) -> Result<bool> {
+        time(
+            "set_user_locale",
+            self.inner.set_user_locale(user_id, locale),
+        )
+        .await
+    }
+
+    async fn insert_bot(&self, name: &str, owner_id: UserId) -> Result<Option<UserId>> {
+        time("insert_bot", self.inner.insert_bot(name, owner_id)).await
+    }
+
+    async fn get_bots(&self, owner_id: UserId) -> Result<Vec<Bot>> {
+        time("get_bots", self.inner.get_bots(owner_id)).await
+    }
+
+    async fn get_bot_ids(&self, game_id: GameId) -> Result<Vec<UserId>> {
+        time("get_bot_ids", self.inner.get_bot_ids(game_id)).await
+    }
+
+    async fn persist_api_token(
+        &self,
+        bot_id: UserId,
+        owner_id: UserId,
+        token_hash: &str,
+    ) -> Result<Option<ApiToken>> {
+        time(
+            "persist_api_token",
+            self.inner.persist_api_token(bot_id, owner_id, token_hash),
+        )
+        .await
+    }
+
+    async fn delete_api_token(
+        &self,
+        token_id: i64,
+        bot_id: UserId,
+        owner_id: UserId,
+    ) -> Result<bool> {
+        time(
+            "delete_api_token",
+            self.inner.delete_api_token(token_id, bot_id, owner_id),
+        )
+        .await
+    }
+
+    async fn get_api_token_user(&self, token_hash: &str) -> Result<Option<(UserId, String, Role)>> {
+        time(
+            "get_api_token_user",
+            self.inner.get_api_token_user(token_hash),
+        )
+        .await
+    }
+
+    async fn persist_refresh_token(
+        &self,
+        token_hash: &str,
+        user_id: UserId,
+        expires: DateTime<Utc>,
+    ) -> Result<()> {
+        time(
+            "persist_refresh_token",
+            self.inner
+                .persist_refresh_token(token_hash, user_id, expires),
+        )
+        .await
+    }
+
+    async fn take_refresh_token(&self, token_hash: &str) -> Result<Option<(UserId, String)>> {
+        time(
+            "take_refresh_token",
+            self.inner.take_refresh_token(token_hash),
+        )
+        .await
+    }
+
+    async fn get_user_name(&self, user_id: UserId) -> Result<Option<String>> {
+        time("get_user_name", self.inner.get_user_name(user_id)).await
+    }
+
+    async fn get_games_for_user(&self, user_id: UserId, query: &GameQuery) -> Result<GamePage> {
+        time(
+            "get_games_for_user",
+            self.inner.get_games_for_user(user_id, query),
+        )
+        .await
+    }
+
+    async fn persist_game(&self, game: &Game) -> Result<GameId> {
+        time("persist_game", self.inner.persist_game(game)).await
+    }
+
+    async fn update_game(&self, game_id: GameId, game: &Game, expected_version: u8) -> Result<()> {
+        time(
+            "update_game",
+            self.inner.update_game(game_id, game, expected_version),
+        )
+        .await
+    }
+
+    async fn read_game(&self, game_id: GameId) -> Result<Option<(Game, u8)>> {
+        time("read_game", self.inner.read_game(game_id)).await
+    }
+
+    async fn persist_chat_message(
+        &self,
+        game_id: GameId,
+        user_id: UserId,
+        text: &str,
+    ) -> Result<ChatMessage> {
+        time(
+            "persist_chat_message",
+            self.inner.persist_chat_message(game_id, user_id, text),
+        )
+        .await
+    }
+
+    async fn get_chat_messages(&self, game_id: GameId, limit: usize) -> Result<Vec<ChatMessage>> {
+        time(
+            "get_chat_messages",
+            self.inner.get_chat_messages(game_id, limit),
+        )
+        .await
+    }
+
+    async fn get_lobbies_for_user(&self, user_id: UserId) -> Result<Vec<LobbyView>> {
+        time(
+            "get_lobbies_for_user",
+            self.inner.get_lobbies_for_user(user_id),
+        )
+        .await
+    }
+
+    async fn persist_lobby(&self, lobby: &Lobby) -> Result<LobbyId> {
+        time("persist_lobby", self.inner.persist_lobby(lobby)).await
+    }
+
+    async fn update_lobby(
+        &self,
+        lobby_id: LobbyId,
+        lobby: &Lobby,
+        expected_version: u8,
+        game: Option<&Game>,
+    ) -> Result<Option<GameId>> {
+        time(
+            "update_lobby",
+            self.inner
+                .update_lobby(lobby_id, lobby, expected_version, game),
+        )
+        .await
+    }
+
+    async fn read_lobby(&self, lobby_id: LobbyId) -> Result<Option<(LobbyView, u8)>> {
+        time("read_lobby", self.inner.read_lobby(lobby_id)).await
+    }
+
+    async fn find_lobby_by_code(&self, code: &str) -> Result<Option<LobbyId>> {
+        time("find_lobby_by_code", self.inner.find_lobby_by_code(code)).await
+    }
+
+    async fn persist_rematch(&self, game_id: GameId, lobby: &Lobby) -> Result<LobbyId> {
+        time(
+            "persist_rematch",
+            self.inner.persist_rematch(game_id, lobby),
+        )
+        .await
+    }
+
+    async fn find_rematch(&self, game_id: GameId) -> Result<Option<LobbyId>> {
+        time("find_rematch", self.inner.find_rematch(game_id)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn calls_are_passed_through() {
+        // arrange
+        let store = InstrumentedStore::new(Arc::new(MemoryStore::new()));
+
+        // act
+        let user_id = store.get_or_insert_user_id("a").await.unwrap();
+
+        // assert
+        assert_eq!(
+            store.get_user_name(user_id).await.unwrap(),
+            Some("a".into())
+        );
+    }
+
+    #[actix_rt::test]
+    async fn conflicts_are_passed_through() {
+        // arrange
+        let store = InstrumentedStore::new(Arc::new(MemoryStore::new()));
+        let lobby = Lobby::new(UserId(1), "a".to_string());
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+
+        // act
+        let result = store.update_lobby(lobby_id, &lobby, 42, None).await;
+
+        // assert
+        assert!(matches!(result, Err(Error::GameVersionConflict)));
+    }
+}
@@ -0,0 +1,1212 @@
+use super::query::{Cursor, GameStatus};
+use super::*;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+struct StoredGame {
+    game: Game,
+    version: u8,
+    user_ids: Vec<UserId>,
+    created: DateTime<Utc>,
+    updated: DateTime<Utc>,
+}
+
+struct StoredApiToken {
+    id: i64,
+    token_hash: String,
+    user_id: UserId,
+    created: DateTime<Utc>,
+}
+
+struct StoredLobby {
+    lobby: Lobby,
+    version: u8,
+    game_id: Option<GameId>,
+    rematch_of: Option<GameId>,
+}
+
+struct StoredChatMessage {
+    game_id: GameId,
+    message: ChatMessage,
+}
+
+#[derive(Default)]
+struct State {
+    user_names: Vec<String>,
+    user_ids_by_name: HashMap<String, UserId>,
+    password_hashes: HashMap<UserId, String>,
+    refresh_tokens: HashMap<String, (UserId, DateTime<Utc>)>,
+    roles: HashMap<UserId, Role>,
+    locales: HashMap<UserId, Locale>,
+    bot_owners: HashMap<UserId, UserId>,
+    api_tokens: Vec<StoredApiToken>,
+    num_api_tokens: i64,
+    games: Vec<StoredGame>,
+    chat_messages: Vec<StoredChatMessage>,
+    lobbies: Vec<StoredLobby>,
+    clock: Option<DateTime<Utc>>,
+}
+
+impl State {
+    /// Returns the current time with the microsecond resolution of the SQL backends,
+    /// always later than the previous call so that games have a well-defined order.
+    fn now(&mut self) -> DateTime<Utc> {
+        let now = Utc::now();
+        let now = now - Duration::nanoseconds(i64::from(now.timestamp_subsec_nanos() % 1000));
+
+        let now = match self.clock {
+            Some(last) if now <= last => last + Duration::microseconds(1),
+            _ => now,
+        };
+
+        self.clock = Some(now);
+        now
+    }
+
+    fn insert_game(&mut self, game: &Game) -> GameId {
+        let now = self.now();
+
+        self.games.push(StoredGame {
+            game: game.clone(),
+            version: 0,
+            user_ids: game.user_ids(),
+            created: now,
+            updated: now,
+        });
+
+        GameId(self.games.len() as i64)
+    }
+
+    fn insert_lobby(&mut self, lobby: &Lobby, rematch_of: Option<GameId>) -> LobbyId {
+        self.lobbies.push(StoredLobby {
+            lobby: lobby.clone(),
+            version: 0,
+            game_id: None,
+            rematch_of,
+        });
+
+        LobbyId(self.lobbies.len() as i64)
+    }
+
+    fn view_lobby(&self, index: usize) -> LobbyView {
+        let stored = &self.lobbies[index];
+
+        LobbyView {
+            id: LobbyId(index as i64 + 1),
+            lobby: stored.lobby.clone(),
+            game_id: stored.game_id,
+        }
+    }
+
+    fn game_mut(&mut self, game_id: GameId) -> Option<&mut StoredGame> {
+        self.games.get_mut(Self::index(game_id.0)?)
+    }
+
+    fn game(&self, game_id: GameId) -> Option<&StoredGame> {
+        self.games.get(Self::index(game_id.0)?)
+    }
+
+    // Ids start at 1 like SQLite's rowids.
+    fn index(id: i64) -> Option<usize> {
+        (id as usize).checked_sub(1)
+    }
+}
+
+/// Keeps everything in process memory. Nothing survives a restart, which makes it useful
+/// for tests and demo servers that should not need a database file.
+#[derive(Default)]
+pub struct MemoryStore {
+    state: Mutex<State>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl GameStore for MemoryStore {
+    async fn migrate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<&'static Migration>> {
+        Ok(vec![])
+    }
+
+    async fn get_or_insert_user_id(&self, user_name: &str) -> Result<UserId> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(user_id) = state.user_ids_by_name.get(user_name) {
+            return Ok(*user_id);
+        }
+
+        info!("Inserting user {}", user_name);
+        state.user_names.push(user_name.to_string());
+        let user_id = UserId(state.user_names.len() as i64);
+        state
+            .user_ids_by_name
+            .insert(user_name.to_string(), user_id);
+
+        Ok(user_id)
+    }
+
+    async fn insert_user(&self, user_name: &str, password_hash: &str) -> Result<Option<UserId>> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.user_ids_by_name.contains_key(user_name) {
+            return Ok(None);
+        }
+
+        info!("Registered user {}", user_name);
+        state.user_names.push(user_name.to_string());
+        let user_id = UserId(state.user_names.len() as i64);
+        state
+            .user_ids_by_name
+            .insert(user_name.to_string(), user_id);
+        state
+            .password_hashes
+            .insert(user_id, password_hash.to_string());
+
+        Ok(Some(user_id))
+    }
+
+    async fn get_credentials(&self, user_name: &str) -> Result<Option<(UserId, Option<String>)>> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .user_ids_by_name
+            .get(user_name)
+            .map(|user_id| (*user_id, state.password_hashes.get(user_id).cloned())))
+    }
+
+    async fn set_password_hash(&self, user_id: UserId, password_hash: &str) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.password_hashes.contains_key(&user_id) {
+            return Ok(false);
+        }
+
+        state
+            .password_hashes
+            .insert(user_id, password_hash.to_string());
+
+        Ok(true)
+    }
+
+    async fn get_user_id(&self, user_name: &str) -> Result<Option<UserId>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.user_ids_by_name.get(user_name).copied())
+    }
+
+    async fn get_user_role(&self, user_id: UserId) -> Result<Option<Role>> {
+        let state = self.state.lock().unwrap();
+
+        Ok(State::index(user_id.0)
+            .filter(|i| *i < state.user_names.len())
+            .map(|_| state.roles.get(&user_id).copied().unwrap_or_default()))
+    }
+
+    async fn set_user_role(&self, user_id: UserId, role: Role) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+
+        match State::index(user_id.0) {
+            Some(i) if i < state.user_names.len() => {
+                state.roles.insert(user_id, role);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn get_user_locale(&self, user_id: UserId) -> Result<Option<Locale>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.locales.get(&user_id).copied())
+    }
+
+    async fn set_user_locale(&self, user_id: UserId, locale: Option<Locale>) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+
+        match State::index(user_id.0) {
+            Some(i) if i < state.user_names.len() => {
+                match locale {
+                    Some(locale) => state.locales.insert(user_id, locale),
+                    None => state.locales.remove(&user_id),
+                };
+
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn insert_bot(&self, name: &str, owner_id: UserId) -> Result<Option<UserId>> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.user_ids_by_name.contains_key(name) {
+            return Ok(None);
+        }
+
+        info!("User {} created bot {}", owner_id, name);
+        state.user_names.push(name.to_string());
+        let user_id = UserId(state.user_names.len() as i64);
+        state.user_ids_by_name.insert(name.to_string(), user_id);
+        state.bot_owners.insert(user_id, owner_id);
+
+        Ok(Some(user_id))
+    }
+
+    async fn get_bots(&self, owner_id: UserId) -> Result<Vec<Bot>> {
+        let state = self.state.lock().unwrap();
+
+        let mut bot_ids: Vec<_> = state
+            .bot_owners
+            .iter()
+            .filter(|(_, owner)| **owner == owner_id)
+            .map(|(bot_id, _)| *bot_id)
+            .collect();
+
+        bot_ids.sort_by_key(|id| id.0);
+
+        Ok(bot_ids
+            .into_iter()
+            .map(|id| Bot {
+                id,
+                name: state.user_names[State::index(id.0).unwrap()].clone(),
+                tokens: state
+                    .api_tokens
+                    .iter()
+                    .filter(|t| t.user_id == id)
+                    .map(|t| ApiToken {
+                        id: t.id,
+                        created: t.created,
+                    })
+                    .collect(),
+            })
+            .collect())
+    }
+
+    async fn get_bot_ids(&self, game_id: GameId) -> Result<Vec<UserId>> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .game(game_id)
+            .map(|game| {
+                game.user_ids
+                    .iter()
+                    .filter(|id| state.bot_owners.contains_key(id))
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn persist_api_token(
+        &self,
+        bot_id: UserId,
+        owner_id: UserId,
+        token_hash: &str,
+    ) -> Result<Option<ApiToken>> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.bot_owners.get(&bot_id) != Some(&owner_id) {
+            return Ok(None);
+        }
+
+        state.num_api_tokens += 1;
+
+        let stored = StoredApiToken {
+            id: state.num_api_tokens,
+            token_hash: token_hash.to_string(),
+            user_id: bot_id,
+            created: Utc::now(),
+        };
+
+        let token = ApiToken {
+            id: stored.id,
+            created: stored.created,
+        };
+
+        state.api_tokens.push(stored);
+        Ok(Some(token))
+    }
+
+    async fn delete_api_token(
+        &self,
+        token_id: i64,
+        bot_id: UserId,
+        owner_id: UserId,
+    ) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.bot_owners.get(&bot_id) != Some(&owner_id) {
+            return Ok(false);
+        }
+
+        let before = state.api_tokens.len();
+        state
+            .api_tokens
+            .retain(|t| t.id != token_id || t.user_id != bot_id);
+
+        Ok(state.api_tokens.len() < before)
+    }
+
+    async fn get_api_token_user(&self, token_hash: &str) -> Result<Option<(UserId, String, Role)>> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .api_tokens
+            .iter()
+            .find(|t| t.token_hash == token_hash)
+            .map(|t| {
+                let name = state.user_names[State::index(t.user_id.0).unwrap()].clone();
+                let role = state.roles.get(&t.user_id).copied().unwrap_or_default();
+                (t.user_id, name, role)
+            }))
+    }
+
+    async fn persist_refresh_token(
+        &self,
+        token_hash: &str,
+        user_id: UserId,
+        expires: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        state
+            .refresh_tokens
+            .insert(token_hash.to_string(), (user_id, expires));
+
+        Ok(())
+    }
+
+    async fn take_refresh_token(&self, token_hash: &str) -> Result<Option<(UserId, String)>> {
+        let mut state = self.state.lock().unwrap();
+
+        Ok(state
+            .refresh_tokens
+            .remove(token_hash)
+            .filter(|(_, expires)| *expires > Utc::now())
+            .map(|(user_id, _)| {
+                let name = state.user_names[State::index(user_id.0).unwrap()].clone();
+                (user_id, name)
+            }))
+    }
+
+    async fn get_user_name(&self, user_id: UserId) -> Result<Option<String>> {
+        let state = self.state.lock().unwrap();
+        let index = State::index(user_id.0);
+        Ok(index.and_then(|index| state.user_names.get(index).cloned()))
+    }
+
+    async fn get_games_for_user(&self, user_id: UserId, query: &GameQuery) -> Result<GamePage> {
+        let state = self.state.lock().unwrap();
+        let abandoned_before = query.abandoned_before();
+        let user_name = |id: &UserId| state.user_names[State::index(id.0).unwrap()].clone();
+
+        let mut games = state
+            .games
+            .iter()
+            .enumerate()
+            .filter(|(_, g)| g.user_ids.contains(&user_id))
+            .filter(|(_, g)| match &query.opponent {
+                None => true,
+                Some(opponent) => g
+                    .user_ids
+                    .iter()
+                    .any(|id| *id != user_id && user_name(id) == *opponent),
+            })
+            .filter(|(_, g)| query.from.is_none_or(|from| g.updated >= from))
+            .filter(|(_, g)| query.to.is_none_or(|to| g.updated <= to))
+            .map(|(index, g)| {
+                let summary = g.game.summary();
+                let game_over = summary.winner.is_some();
+                let pending = summary.players.iter().filter(|p| !p.ready);
+
+                GameListItem {
+                    id: GameId(index as i64 + 1),
+                    players: g.user_ids.iter().map(user_name).collect(),
+                    round: summary.round,
+                    game_over,
+                    winner: summary.winner.as_ref().map(user_name),
+                    your_move: !game_over && pending.clone().any(|p| p.id == user_id),
+                    num_pending: if game_over { 0 } else { pending.count() },
+                    created: g.created,
+                    updated: g.updated,
+                }
+            })
+            .filter(|g| match query.status {
+                None => true,
+                Some(GameStatus::Finished) => g.game_over,
+                Some(GameStatus::Active) => !g.game_over && g.updated >= abandoned_before,
+                Some(GameStatus::Abandoned) => !g.game_over && g.updated < abandoned_before,
+            })
+            .map(|g| (Cursor::of(&g, query.sort), g))
+            .filter(|(cursor, _)| query.after.is_none_or(|after| key(cursor) < key(&after)))
+            .collect::<Vec<_>>();
+
+        games.sort_by_key(|(cursor, _)| std::cmp::Reverse(key(cursor)));
+        games.truncate(query.limit() + 1);
+
+        let games = games.into_iter().map(|(_, g)| g).collect();
+        Ok(GamePage::new(games, query))
+    }
+
+    async fn persist_game(&self, game: &Game) -> Result<GameId> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state.insert_game(game))
+    }
+
+    async fn update_game(&self, game_id: GameId, game: &Game, expected_version: u8) -> Result<()> {
+        info!("Updating game {} at version {}", game_id, expected_version);
+
+        let mut state = self.state.lock().unwrap();
+        let now = state.now();
+
+        match state.game_mut(game_id) {
+            Some(stored) if stored.version == expected_version => {
+                stored.game = game.clone();
+                stored.version += 1;
+                stored.updated = now;
+                Ok(())
+            }
+
+            _ => Err(Error::GameVersionConflict),
+        }
+    }
+
+    async fn read_game(&self, game_id: GameId) -> Result<Option<(Game, u8)>> {
+        info!("Finding game {} for system", game_id);
+
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .game(game_id)
+            .map(|stored| (stored.game.clone(), stored.version)))
+    }
+
+    async fn persist_chat_message(
+        &self,
+        game_id: GameId,
+        user_id: UserId,
+        text: &str,
+    ) -> Result<ChatMessage> {
+        let mut state = self.state.lock().unwrap();
+
+        let message = ChatMessage {
+            user_id,
+            name: state.user_names[State::index(user_id.0).unwrap()].clone(),
+            text: text.to_string(),
+            sent: state.now(),
+        };
+
+        state.chat_messages.push(StoredChatMessage {
+            game_id,
+            message: message.clone(),
+        });
+
+        Ok(message)
+    }
+
+    async fn get_chat_messages(&self, game_id: GameId, limit: usize) -> Result<Vec<ChatMessage>> {
+        let state = self.state.lock().unwrap();
+
+        let mut messages: Vec<_> = state
+            .chat_messages
+            .iter()
+            .rev()
+            .filter(|stored| stored.game_id == game_id)
+            .take(limit)
+            .map(|stored| stored.message.clone())
+            .collect();
+
+        messages.reverse();
+        Ok(messages)
+    }
+
+    async fn get_lobbies_for_user(&self, user_id: UserId) -> Result<Vec<LobbyView>> {
+        let state = self.state.lock().unwrap();
+
+        let lobbies = (0..state.lobbies.len())
+            .rev()
+            .filter(|index| {
+                let stored = &state.lobbies[*index];
+                stored.game_id.is_none() && stored.lobby.is_seated(user_id)
+            })
+            .map(|index| state.view_lobby(index))
+            .collect();
+
+        Ok(lobbies)
+    }
+
+    async fn persist_lobby(&self, lobby: &Lobby) -> Result<LobbyId> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state.insert_lobby(lobby, None))
+    }
+
+    async fn update_lobby(
+        &self,
+        lobby_id: LobbyId,
+        lobby: &Lobby,
+        expected_version: u8,
+        game: Option<&Game>,
+    ) -> Result<Option<GameId>> {
+        info!(
+            "Updating lobby {} at version {}",
+            lobby_id, expected_version
+        );
+
+        let mut state = self.state.lock().unwrap();
+        let index = State::index(lobby_id.0).filter(|i| *i < state.lobbies.len());
+
+        match index {
+            Some(index) if state.lobbies[index].version == expected_version => {
+                let game_id = game.map(|game| state.insert_game(game));
+                let stored = &mut state.lobbies[index];
+                stored.lobby = lobby.clone();
+                stored.version += 1;
+                stored.game_id = stored.game_id.or(game_id);
+                Ok(game_id)
+            }
+
+            _ => Err(Error::GameVersionConflict),
+        }
+    }
+
+    async fn read_lobby(&self, lobby_id: LobbyId) -> Result<Option<(LobbyView, u8)>> {
+        let state = self.state.lock().unwrap();
+        let index = State::index(lobby_id.0).filter(|i| *i < state.lobbies.len());
+
+        Ok(index.map(|index| (state.view_lobby(index), state.lobbies[index].version)))
+    }
+
+    async fn persist_rematch(&self, game_id: GameId, lobby: &Lobby) -> Result<LobbyId> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.lobbies.iter().any(|l| l.rematch_of == Some(game_id)) {
+            return Err(Error::GameVersionConflict);
+        }
+
+        Ok(state.insert_lobby(lobby, Some(game_id)))
+    }
+
+    async fn find_rematch(&self, game_id: GameId) -> Result<Option<LobbyId>> {
+        let state = self.state.lock().unwrap();
+
+        let index = state
+            .lobbies
+            .iter()
+            .position(|stored| stored.rematch_of == Some(game_id));
+
+        Ok(index.map(|index| LobbyId(index as i64 + 1)))
+    }
+
+    async fn find_lobby_by_code(&self, code: &str) -> Result<Option<LobbyId>> {
+        let state = self.state.lock().unwrap();
+
+        let index = state
+            .lobbies
+            .iter()
+            .position(|stored| stored.lobby.code() == Some(code));
+
+        Ok(index.map(|index| LobbyId(index as i64 + 1)))
+    }
+}
+
+fn key(cursor: &Cursor) -> (i32, DateTime<Utc>, i64) {
+    (cursor.priority, cursor.time, cursor.id.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linked_hash_set::LinkedHashSet;
+    use std::convert::TryFrom;
+    use std::iter::FromIterator;
+
+    lazy_static! {
+        static ref PLAYER1: UserId = UserId(1);
+        static ref PLAYER2: UserId = UserId(2);
+        static ref PLAYER3: UserId = UserId(3);
+    }
+
+    async fn setup_store() -> MemoryStore {
+        let store = MemoryStore::new();
+
+        for name in &["a", "b", "c"] {
+            store.get_or_insert_user_id(name).await.unwrap();
+        }
+
+        store
+    }
+
+    fn game() -> Game {
+        Game::try_from(LinkedHashSet::from_iter(vec![*PLAYER1, *PLAYER2, *PLAYER3])).unwrap()
+    }
+
+    fn other_game() -> Game {
+        Game::try_from(LinkedHashSet::from_iter(vec![*PLAYER2, *PLAYER3])).unwrap()
+    }
+
+    fn select_first_card(game: &mut Game, user_id: UserId) {
+        let view = game.clone().into(user_id);
+        let card = *view.player.unwrap().hand.keys().next().unwrap();
+        game.select_cards(user_id, vec![card]).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn get_or_insert_user_id_will_get() {
+        // arrange
+        let store = setup_store().await;
+
+        // act
+        let actual = store.get_or_insert_user_id("b").await;
+
+        // assert
+        assert_eq!(actual.unwrap(), *PLAYER2);
+    }
+
+    #[actix_rt::test]
+    async fn can_read_game() {
+        // arrange
+        let store = setup_store().await;
+        let game = game();
+        let game_id = store.persist_game(&game).await.unwrap();
+
+        // act
+        let actual = store.read_game(game_id).await.unwrap();
+
+        // assert
+        assert_eq!(actual, Some((game, 0)));
+    }
+
+    #[actix_rt::test]
+    async fn cannot_read_missing_game() {
+        // arrange
+        let store = setup_store().await;
+
+        // act
+        let actual = store.read_game(GameId(0)).await.unwrap();
+
+        // assert
+        assert!(actual.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn can_get_games_for_user() {
+        // arrange
+        let store = setup_store().await;
+        let first = store.persist_game(&game()).await.unwrap();
+        let second = store.persist_game(&game()).await.unwrap();
+        store.persist_game(&other_game()).await.unwrap(); // Game without player 1
+        store.update_game(first, &game(), 0).await.unwrap();
+
+        // act
+        let games = store
+            .get_games_for_user(*PLAYER1, &GameQuery::default())
+            .await
+            .unwrap()
+            .games;
+
+        // assert
+        let ids = games.iter().map(|g| g.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![first, second]);
+        assert_eq!(games[0].players, vec!["a", "b", "c"]);
+    }
+
+    #[actix_rt::test]
+    async fn cannot_update_if_expected_version_does_not_match() {
+        // arrange
+        let store = setup_store().await;
+        let game = game();
+        let game_id = store.persist_game(&game).await.unwrap();
+        store.update_game(game_id, &game, 0).await.unwrap();
+
+        // act
+        let error = store.update_game(game_id, &game, 0).await.unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameVersionConflict));
+    }
+
+    #[actix_rt::test]
+    async fn games_where_it_is_your_move_come_first() {
+        // arrange
+        let store = setup_store().await;
+        let waiting = store.persist_game(&game()).await.unwrap();
+        let moved = store.persist_game(&game()).await.unwrap();
+        let mut game = game();
+        select_first_card(&mut game, *PLAYER1);
+        store.update_game(moved, &game, 0).await.unwrap();
+
+        // act
+        let games = store
+            .get_games_for_user(*PLAYER1, &GameQuery::default())
+            .await
+            .unwrap()
+            .games;
+
+        // assert
+        let ids = games.iter().map(|g| g.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![waiting, moved]);
+        assert!(games[0].your_move);
+        assert_eq!(games[0].num_pending, 3);
+        assert!(!games[1].your_move);
+        assert_eq!(games[1].num_pending, 2);
+        assert!(!games[1].game_over);
+        assert_eq!(games[1].winner, None);
+    }
+
+    #[actix_rt::test]
+    async fn can_page_through_games() {
+        // arrange
+        let store = setup_store().await;
+        let mut expected = vec![];
+
+        for _ in 0..5 {
+            expected.insert(0, store.persist_game(&game()).await.unwrap());
+        }
+
+        let mut query = GameQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        // act
+        let mut ids = vec![];
+
+        loop {
+            let page = store.get_games_for_user(*PLAYER1, &query).await.unwrap();
+            ids.extend(page.games.iter().map(|g| g.id));
+
+            match page.next {
+                Some(next) => query.after = Some(next),
+                None => break,
+            }
+        }
+
+        // assert
+        assert_eq!(ids, expected);
+    }
+
+    #[actix_rt::test]
+    async fn can_filter_games_by_opponent() {
+        // arrange
+        let store = setup_store().await;
+        store.persist_game(&game()).await.unwrap();
+        store.persist_game(&other_game()).await.unwrap();
+
+        let query = GameQuery {
+            opponent: Some("c".to_string()),
+            ..Default::default()
+        };
+
+        // act
+        let page = store.get_games_for_user(*PLAYER2, &query).await.unwrap();
+
+        // assert
+        assert_eq!(page.games.len(), 2);
+        assert!(page.next.is_none());
+
+        let query = GameQuery {
+            opponent: Some("a".to_string()),
+            ..query
+        };
+
+        let page = store.get_games_for_user(*PLAYER2, &query).await.unwrap();
+        assert_eq!(page.games.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn can_filter_games_by_status() {
+        // arrange
+        let store = setup_store().await;
+        store.persist_game(&game()).await.unwrap();
+
+        let expected = vec![
+            (GameStatus::Active, 1),
+            (GameStatus::Finished, 0),
+            (GameStatus::Abandoned, 0),
+        ];
+
+        for (status, expected) in expected {
+            let query = GameQuery {
+                status: Some(status),
+                ..Default::default()
+            };
+
+            // act
+            let page = store.get_games_for_user(*PLAYER1, &query).await.unwrap();
+
+            // assert
+            assert_eq!(page.games.len(), expected, "{:?}", status);
+        }
+    }
+
+    fn lobby() -> Lobby {
+        let mut lobby = Lobby::new(*PLAYER1, "a".to_string());
+        lobby.invite(*PLAYER1, *PLAYER2, "b".to_string()).unwrap();
+        lobby
+    }
+
+    #[actix_rt::test]
+    async fn can_read_lobby() {
+        // arrange
+        let store = setup_store().await;
+        let lobby = lobby();
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+
+        // act
+        let (view, version) = store.read_lobby(lobby_id).await.unwrap().unwrap();
+
+        // assert
+        assert_eq!(view.lobby, lobby);
+        assert_eq!(view.game_id, None);
+        assert_eq!(version, 0);
+    }
+
+    #[actix_rt::test]
+    async fn starting_lobby_persists_game() {
+        // arrange
+        let store = setup_store().await;
+        let mut lobby = lobby();
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+        lobby.respond(*PLAYER2, true).unwrap();
+        let game = lobby.take_game().unwrap();
+
+        // act
+        let game_id = store.update_lobby(lobby_id, &lobby, 0, Some(&game)).await;
+
+        // assert
+        let game_id = game_id.unwrap().unwrap();
+        let (view, _) = store.read_lobby(lobby_id).await.unwrap().unwrap();
+        assert_eq!(view.game_id, Some(game_id));
+        assert_eq!(store.read_game(game_id).await.unwrap(), Some((game, 0)));
+        assert!(store
+            .get_lobbies_for_user(*PLAYER2)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn cannot_update_lobby_if_expected_version_does_not_match() {
+        // arrange
+        let store = setup_store().await;
+        let lobby = lobby();
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+        store.update_lobby(lobby_id, &lobby, 0, None).await.unwrap();
+
+        // act
+        let error = store
+            .update_lobby(lobby_id, &lobby, 0, None)
+            .await
+            .unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameVersionConflict));
+    }
+
+    #[actix_rt::test]
+    async fn can_get_lobbies_for_invited_user() {
+        // arrange
+        let store = setup_store().await;
+        let mut lobby = lobby();
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+        lobby.invite(*PLAYER1, *PLAYER3, "c".to_string()).unwrap();
+        lobby.remove(*PLAYER1, *PLAYER2).unwrap();
+        store.update_lobby(lobby_id, &lobby, 0, None).await.unwrap();
+
+        // act
+        let lobbies = store.get_lobbies_for_user(*PLAYER3).await.unwrap();
+
+        // assert
+        assert_eq!(lobbies.len(), 1);
+        assert_eq!(lobbies[0].id, lobby_id);
+        assert!(store
+            .get_lobbies_for_user(*PLAYER2)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn can_find_lobby_by_code() {
+        // arrange
+        let store = setup_store().await;
+        store.persist_lobby(&lobby()).await.unwrap();
+        let lobby = Lobby::open(*PLAYER1, "a".to_string(), 3).unwrap();
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+
+        // act
+        let found = store
+            .find_lobby_by_code(lobby.code().unwrap())
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(found, Some(lobby_id));
+        assert_eq!(store.find_lobby_by_code("NOPE").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    async fn can_get_user_name() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+
+        // act
+        let name = store.get_user_name(user_id).await.unwrap();
+
+        // assert
+        assert_eq!(name.as_deref(), Some("d"));
+        assert_eq!(store.get_user_name(UserId(42)).await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    async fn cannot_persist_second_rematch() {
+        // arrange
+        let store = setup_store().await;
+        let game_id = store.persist_game(&game()).await.unwrap();
+        let lobby_id = store.persist_rematch(game_id, &lobby()).await.unwrap();
+
+        // act
+        let error = store.persist_rematch(game_id, &lobby()).await.unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameVersionConflict));
+        assert_eq!(store.find_rematch(game_id).await.unwrap(), Some(lobby_id));
+    }
+
+    #[actix_rt::test]
+    async fn can_get_recent_chat_messages() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+        let game_id = store.persist_game(&game()).await.unwrap();
+        let other_game_id = store.persist_game(&game()).await.unwrap();
+
+        for text in &["1", "2", "3"] {
+            store
+                .persist_chat_message(game_id, user_id, text)
+                .await
+                .unwrap();
+        }
+
+        store
+            .persist_chat_message(other_game_id, user_id, "4")
+            .await
+            .unwrap();
+
+        // act
+        let messages = store.get_chat_messages(game_id, 2).await.unwrap();
+
+        // assert
+        let texts: Vec<_> = messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, vec!["2", "3"]);
+        assert_eq!(messages[0].name, "d");
+        assert_eq!(messages[0].user_id, user_id);
+    }
+
+    #[actix_rt::test]
+    async fn cannot_register_taken_name() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.insert_user("d", "hash").await.unwrap();
+
+        // act
+        let taken = store.insert_user("d", "other").await.unwrap();
+
+        // assert
+        assert!(user_id.is_some());
+        assert_eq!(taken, None);
+        let credentials = store.get_credentials("d").await.unwrap();
+        assert_eq!(
+            credentials,
+            Some((user_id.unwrap(), Some("hash".to_string())))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn can_only_claim_account_once() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+
+        // act
+        let claimed = store.set_password_hash(user_id, "hash").await.unwrap();
+        let claimed_again = store.set_password_hash(user_id, "other").await.unwrap();
+
+        // assert
+        assert!(claimed);
+        assert!(!claimed_again);
+        let credentials = store.get_credentials("d").await.unwrap();
+        assert_eq!(credentials, Some((user_id, Some("hash".to_string()))));
+    }
+
+    #[actix_rt::test]
+    async fn refresh_token_can_only_be_taken_once() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+        let expires = Utc::now() + Duration::days(1);
+        store
+            .persist_refresh_token("hash", user_id, expires)
+            .await
+            .unwrap();
+
+        // act
+        let taken = store.take_refresh_token("hash").await.unwrap();
+
+        // assert
+        assert_eq!(taken, Some((user_id, "d".to_string())));
+        assert_eq!(store.take_refresh_token("hash").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    async fn cannot_take_expired_refresh_token() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+        let expires = Utc::now() - Duration::seconds(1);
+        store
+            .persist_refresh_token("hash", user_id, expires)
+            .await
+            .unwrap();
+
+        // act
+        let taken = store.take_refresh_token("hash").await.unwrap();
+
+        // assert
+        assert_eq!(taken, None);
+    }
+
+    #[actix_rt::test]
+    async fn users_are_players_until_given_another_role() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+        let before = store.get_user_role(user_id).await.unwrap();
+
+        // act
+        let updated = store.set_user_role(user_id, Role::Admin).await.unwrap();
+
+        // assert
+        assert_eq!(before, Some(Role::Player));
+        assert!(updated);
+        assert_eq!(
+            store.get_user_role(user_id).await.unwrap(),
+            Some(Role::Admin)
+        );
+        assert_eq!(store.get_user_role(UserId(42)).await.unwrap(), None);
+        assert!(!store.set_user_role(UserId(42), Role::Admin).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn can_set_and_clear_user_locale() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+        let before = store.get_user_locale(user_id).await.unwrap();
+
+        // act
+        let updated = store
+            .set_user_locale(user_id, Some(Locale::Da))
+            .await
+            .unwrap();
+        let after = store.get_user_locale(user_id).await.unwrap();
+        store.set_user_locale(user_id, None).await.unwrap();
+
+        // assert
+        assert_eq!(before, None);
+        assert!(updated);
+        assert_eq!(after, Some(Locale::Da));
+        assert_eq!(store.get_user_locale(user_id).await.unwrap(), None);
+        assert!(!store.set_user_locale(UserId(42), None).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn can_create_bot_with_api_token() {
+        // arrange
+        let store = setup_store().await;
+        let owner_id = store.get_or_insert_user_id("d").await.unwrap();
+
+        // act
+        let bot_id = store.insert_bot("bot", owner_id).await.unwrap().unwrap();
+        let token = store
+            .persist_api_token(bot_id, owner_id, "hash")
+            .await
+            .unwrap();
+
+        // assert
+        let token = token.unwrap();
+        assert_eq!(store.insert_bot("bot", owner_id).await.unwrap(), None);
+        assert_eq!(
+            store.get_bots(owner_id).await.unwrap(),
+            vec![Bot {
+                id: bot_id,
+                name: "bot".to_string(),
+                tokens: vec![token],
+            }]
+        );
+        assert_eq!(
+            store.get_api_token_user("hash").await.unwrap(),
+            Some((bot_id, "bot".to_string(), Role::Player))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn only_owner_can_manage_api_tokens() {
+        // arrange
+        let store = setup_store().await;
+        let owner_id = store.get_or_insert_user_id("d").await.unwrap();
+        let other_id = store.get_or_insert_user_id("e").await.unwrap();
+        let bot_id = store.insert_bot("bot", owner_id).await.unwrap().unwrap();
+        let token = store
+            .persist_api_token(bot_id, owner_id, "hash")
+            .await
+            .unwrap();
+        let token_id = token.unwrap().id;
+
+        // act
+        let created = store
+            .persist_api_token(bot_id, other_id, "other")
+            .await
+            .unwrap();
+        let deleted_by_other = store
+            .delete_api_token(token_id, bot_id, other_id)
+            .await
+            .unwrap();
+        let deleted = store
+            .delete_api_token(token_id, bot_id, owner_id)
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(created, None);
+        assert!(!deleted_by_other);
+        assert!(deleted);
+        assert_eq!(store.get_api_token_user("hash").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    async fn can_get_bots_in_game() {
+        // arrange
+        let store = setup_store().await;
+        let owner_id = store.get_or_insert_user_id("d").await.unwrap();
+        let bot_id = store.insert_bot("bot", owner_id).await.unwrap().unwrap();
+        let user_ids = LinkedHashSet::from_iter(vec![owner_id, bot_id]);
+        let game_id = store
+            .persist_game(&Game::try_from(user_ids).unwrap())
+            .await
+            .unwrap();
+
+        // act
+        let bot_ids = store.get_bot_ids(game_id).await.unwrap();
+
+        // assert
+        assert_eq!(bot_ids, vec![bot_id]);
+    }
+}
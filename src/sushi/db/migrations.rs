@@ -0,0 +1,191 @@
+use super::{Error, Result};
+use ring::digest::{digest, SHA256};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// A schema change embedded in the binary. Migrations are applied in order of `version`,
+/// each in its own transaction, and must never be edited once released.
+#[derive(Debug)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        let mut checksum = String::with_capacity(64);
+
+        for byte in digest(&SHA256, self.sql.as_bytes()).as_ref() {
+            write!(checksum, "{:02x}", byte).expect("can write to string");
+        }
+
+        checksum
+    }
+}
+
+macro_rules! migration {
+    ($version:expr, $name:expr, $file:expr) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!($file),
+        }
+    };
+}
+
+pub static SQLITE: &[Migration] = &[
+    migration!(1, "initial", "migrations/sqlite/0001_initial.sql"),
+    migration!(
+        2,
+        "game_state_columns",
+        "migrations/sqlite/0002_game_state_columns.sql"
+    ),
+    migration!(3, "lobbies", "migrations/sqlite/0003_lobbies.sql"),
+    migration!(4, "lobby_codes", "migrations/sqlite/0004_lobby_codes.sql"),
+    migration!(5, "rematches", "migrations/sqlite/0005_rematches.sql"),
+    migration!(
+        6,
+        "chat_messages",
+        "migrations/sqlite/0006_chat_messages.sql"
+    ),
+    migration!(7, "passwords", "migrations/sqlite/0007_passwords.sql"),
+    migration!(
+        8,
+        "refresh_tokens",
+        "migrations/sqlite/0008_refresh_tokens.sql"
+    ),
+    migration!(9, "user_roles", "migrations/sqlite/0009_user_roles.sql"),
+    migration!(10, "bots", "migrations/sqlite/0010_bots.sql"),
+    migration!(
+        11,
+        "user_locales",
+        "migrations/sqlite/0011_user_locales.sql"
+    ),
+];
+
+#[cfg(feature = "postgres")]
+pub static POSTGRES: &[Migration] = &[
+    migration!(1, "initial", "migrations/postgres/0001_initial.sql"),
+    migration!(
+        2,
+        "game_state_columns",
+        "migrations/postgres/0002_game_state_columns.sql"
+    ),
+    migration!(3, "lobbies", "migrations/postgres/0003_lobbies.sql"),
+    migration!(4, "lobby_codes", "migrations/postgres/0004_lobby_codes.sql"),
+    migration!(5, "rematches", "migrations/postgres/0005_rematches.sql"),
+    migration!(
+        6,
+        "chat_messages",
+        "migrations/postgres/0006_chat_messages.sql"
+    ),
+    migration!(7, "passwords", "migrations/postgres/0007_passwords.sql"),
+    migration!(
+        8,
+        "refresh_tokens",
+        "migrations/postgres/0008_refresh_tokens.sql"
+    ),
+    migration!(9, "user_roles", "migrations/postgres/0009_user_roles.sql"),
+    migration!(10, "bots", "migrations/postgres/0010_bots.sql"),
+    migration!(
+        11,
+        "user_locales",
+        "migrations/postgres/0011_user_locales.sql"
+    ),
+];
+
+/// Verifies the checksums of the migrations that have already been applied and returns
+/// the ones that are still pending. Applied migrations without a recorded checksum are
+/// accepted, since they predate the checksum table.
+pub fn pending<'a>(
+    migrations: &'a [Migration],
+    version: u32,
+    checksums: &HashMap<u32, String>,
+) -> Result<Vec<&'a Migration>> {
+    let latest = migrations.last().map(|m| m.version).unwrap_or_default();
+
+    if version > latest {
+        return Err(Error::SchemaTooNew(version));
+    }
+
+    let mut pending = vec![];
+
+    for migration in migrations {
+        if migration.version > version {
+            pending.push(migration);
+        } else if let Some(checksum) = checksums.get(&migration.version) {
+            if *checksum != migration.checksum() {
+                return Err(Error::MigrationChanged(migration.version));
+            }
+        }
+    }
+
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksums(migrations: &[Migration]) -> HashMap<u32, String> {
+        migrations
+            .iter()
+            .map(|m| (m.version, m.checksum()))
+            .collect()
+    }
+
+    #[test]
+    fn versions_are_ordered_and_contiguous() {
+        for (index, migration) in SQLITE.iter().enumerate() {
+            assert_eq!(migration.version as usize, index + 1);
+        }
+    }
+
+    #[test]
+    fn everything_is_pending_for_empty_database() {
+        // act
+        let pending = pending(SQLITE, 0, &HashMap::new()).unwrap();
+
+        // assert
+        assert_eq!(pending.len(), SQLITE.len());
+    }
+
+    #[test]
+    fn nothing_is_pending_for_latest_database() {
+        // arrange
+        let version = SQLITE.len() as u32;
+
+        // act
+        let pending = pending(SQLITE, version, &checksums(SQLITE)).unwrap();
+
+        // assert
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn edited_migration_is_detected() {
+        // arrange
+        let version = SQLITE.len() as u32;
+        let mut checksums = checksums(SQLITE);
+        checksums.insert(1, "edited".to_string());
+
+        // act
+        let error = pending(SQLITE, version, &checksums).unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::MigrationChanged(1)));
+    }
+
+    #[test]
+    fn newer_database_is_rejected() {
+        // arrange
+        let version = SQLITE.len() as u32 + 1;
+
+        // act
+        let error = pending(SQLITE, version, &HashMap::new()).unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::SchemaTooNew(_)));
+    }
+}
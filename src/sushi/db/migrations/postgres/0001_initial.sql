@@ -0,0 +1,21 @@
+CREATE TABLE games
+( id BIGSERIAL PRIMARY KEY
+, data JSONB NOT NULL
+, version SMALLINT NOT NULL DEFAULT 0
+, created TIMESTAMPTZ NOT NULL DEFAULT now()
+, updated TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE users
+( id BIGSERIAL PRIMARY KEY
+, name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE games_users
+( id BIGSERIAL PRIMARY KEY
+, game_id BIGINT NOT NULL REFERENCES games (id)
+, user_id BIGINT NOT NULL REFERENCES users (id)
+);
+
+CREATE INDEX games_users_game_id ON games_users (game_id);
+CREATE INDEX games_users_user_id ON games_users (user_id);
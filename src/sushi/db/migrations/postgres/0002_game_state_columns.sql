@@ -0,0 +1,31 @@
+ALTER TABLE games
+    ADD COLUMN round INTEGER NOT NULL DEFAULT 1,
+    ADD COLUMN winner_id BIGINT REFERENCES users (id);
+
+ALTER TABLE games_users
+    ADD COLUMN num_points INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN num_puddings INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN ready BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE played_cards
+( game_id BIGINT NOT NULL REFERENCES games (id)
+, user_id BIGINT NOT NULL REFERENCES users (id)
+, card TEXT NOT NULL
+, count INTEGER NOT NULL
+, PRIMARY KEY (game_id, user_id, card)
+);
+
+CREATE INDEX played_cards_card ON played_cards (card);
+
+-- Backfill from the stored games. Cards played before this migration are not known.
+UPDATE games
+SET round = (data->>'round')::INTEGER
+  , winner_id = (data->>'winner')::BIGINT;
+
+UPDATE games_users gu
+SET num_points = (p->>'numPoints')::INTEGER
+  , num_puddings = (p->>'numPuddings')::INTEGER
+  , ready = jsonb_array_length(p->'selectedCards') > 0
+FROM games g, jsonb_array_elements(g.data->'players') p
+WHERE g.id = gu.game_id
+AND (p->>'id')::BIGINT = gu.user_id;
@@ -0,0 +1,15 @@
+CREATE TABLE lobbies
+( id BIGSERIAL PRIMARY KEY
+, data JSONB NOT NULL
+, version SMALLINT NOT NULL DEFAULT 0
+, game_id BIGINT REFERENCES games (id)
+, created TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE lobbies_users
+( lobby_id BIGINT NOT NULL REFERENCES lobbies (id)
+, user_id BIGINT NOT NULL REFERENCES users (id)
+, PRIMARY KEY (lobby_id, user_id)
+);
+
+CREATE INDEX lobbies_users_user_id ON lobbies_users (user_id);
@@ -0,0 +1,3 @@
+ALTER TABLE lobbies ADD COLUMN rematch_of BIGINT REFERENCES games (id);
+
+CREATE UNIQUE INDEX lobbies_rematch_of ON lobbies (rematch_of);
@@ -0,0 +1,9 @@
+CREATE TABLE chat_messages
+( id BIGSERIAL PRIMARY KEY
+, game_id BIGINT NOT NULL REFERENCES games (id)
+, user_id BIGINT NOT NULL REFERENCES users (id)
+, text TEXT NOT NULL
+, sent TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX chat_messages_game_id ON chat_messages (game_id);
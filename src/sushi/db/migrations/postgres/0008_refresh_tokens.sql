@@ -0,0 +1,8 @@
+CREATE TABLE refresh_tokens
+( token_hash TEXT PRIMARY KEY
+, user_id BIGINT NOT NULL REFERENCES users (id)
+, expires TIMESTAMPTZ NOT NULL
+, created TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX refresh_tokens_user_id ON refresh_tokens (user_id);
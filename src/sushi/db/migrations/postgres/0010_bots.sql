@@ -0,0 +1,10 @@
+ALTER TABLE users ADD COLUMN bot_owner_id BIGINT REFERENCES users (id);
+
+CREATE TABLE api_tokens
+( id BIGSERIAL PRIMARY KEY
+, token_hash TEXT NOT NULL UNIQUE
+, user_id BIGINT NOT NULL REFERENCES users (id)
+, created TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX api_tokens_user_id ON api_tokens (user_id);
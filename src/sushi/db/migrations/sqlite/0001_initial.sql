@@ -0,0 +1,44 @@
+CREATE TABLE games
+( id INTEGER PRIMARY KEY
+, data TEXT NOT NULL
+, players TEXT NOT NULL DEFAULT (json_array())
+, version INTEGER NOT NULL DEFAULT 0
+, created DATE NOT NULL DEFAULT (datetime('now'))
+, updated DATE NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE TRIGGER games_timestamp
+AFTER UPDATE OF version
+ON games
+BEGIN
+    UPDATE games
+    SET updated = datetime('now')
+    WHERE id = new.id;
+END;
+
+CREATE TABLE users
+( id INTEGER PRIMARY KEY
+, name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE games_users
+( id INTEGER PRIMARY KEY
+, game_id INTEGER NOT NULL REFERENCES games (id)
+, user_id INTEGER NOT NULL REFERENCES users (id)
+);
+
+CREATE TRIGGER games_update_players
+AFTER INSERT ON games_users
+BEGIN
+    UPDATE games
+    SET players = (
+        SELECT json_group_array(u.name)
+        FROM games_users gu
+        INNER JOIN users u ON gu.user_id = u.id
+        WHERE gu.game_id = new.game_id
+    )
+    WHERE id = new.game_id;
+END;
+
+CREATE INDEX games_users_game_id ON games_users (game_id);
+CREATE INDEX games_users_user_id ON games_users (user_id);
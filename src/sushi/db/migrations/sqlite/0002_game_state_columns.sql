@@ -0,0 +1,41 @@
+ALTER TABLE games ADD COLUMN round INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE games ADD COLUMN winner_id INTEGER REFERENCES users (id);
+
+ALTER TABLE games_users ADD COLUMN num_points INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE games_users ADD COLUMN num_puddings INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE games_users ADD COLUMN ready INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE played_cards
+( game_id INTEGER NOT NULL REFERENCES games (id)
+, user_id INTEGER NOT NULL REFERENCES users (id)
+, card TEXT NOT NULL
+, count INTEGER NOT NULL
+, PRIMARY KEY (game_id, user_id, card)
+);
+
+CREATE INDEX played_cards_card ON played_cards (card);
+
+-- Backfill from the stored games. Cards played before this migration are not known.
+UPDATE games
+SET round = json_extract(data, '$.round')
+  , winner_id = json_extract(data, '$.winner');
+
+UPDATE games_users
+SET num_points = COALESCE((
+        SELECT json_extract(p.value, '$.numPoints')
+        FROM games g, json_each(g.data, '$.players') p
+        WHERE g.id = games_users.game_id
+        AND json_extract(p.value, '$.id') = games_users.user_id
+    ), 0)
+  , num_puddings = COALESCE((
+        SELECT json_extract(p.value, '$.numPuddings')
+        FROM games g, json_each(g.data, '$.players') p
+        WHERE g.id = games_users.game_id
+        AND json_extract(p.value, '$.id') = games_users.user_id
+    ), 0)
+  , ready = COALESCE((
+        SELECT json_array_length(p.value, '$.selectedCards') > 0
+        FROM games g, json_each(g.data, '$.players') p
+        WHERE g.id = games_users.game_id
+        AND json_extract(p.value, '$.id') = games_users.user_id
+    ), 0);
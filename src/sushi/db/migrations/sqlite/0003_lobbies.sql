@@ -0,0 +1,15 @@
+CREATE TABLE lobbies
+( id INTEGER PRIMARY KEY
+, data TEXT NOT NULL
+, version INTEGER NOT NULL DEFAULT 0
+, game_id INTEGER REFERENCES games (id)
+, created DATE NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE TABLE lobbies_users
+( lobby_id INTEGER NOT NULL REFERENCES lobbies (id)
+, user_id INTEGER NOT NULL REFERENCES users (id)
+, PRIMARY KEY (lobby_id, user_id)
+);
+
+CREATE INDEX lobbies_users_user_id ON lobbies_users (user_id);
@@ -0,0 +1,3 @@
+ALTER TABLE lobbies ADD COLUMN code TEXT;
+
+CREATE UNIQUE INDEX lobbies_code ON lobbies (code);
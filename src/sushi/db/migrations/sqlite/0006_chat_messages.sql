@@ -0,0 +1,9 @@
+CREATE TABLE chat_messages
+( id INTEGER PRIMARY KEY
+, game_id INTEGER NOT NULL REFERENCES games (id)
+, user_id INTEGER NOT NULL REFERENCES users (id)
+, text TEXT NOT NULL
+, sent DATE NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX chat_messages_game_id ON chat_messages (game_id);
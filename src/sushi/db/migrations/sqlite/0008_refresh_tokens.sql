@@ -0,0 +1,8 @@
+CREATE TABLE refresh_tokens
+( token_hash TEXT PRIMARY KEY
+, user_id INTEGER NOT NULL REFERENCES users (id)
+, expires DATE NOT NULL
+, created DATE NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX refresh_tokens_user_id ON refresh_tokens (user_id);
@@ -0,0 +1,10 @@
+ALTER TABLE users ADD COLUMN bot_owner_id INTEGER REFERENCES users (id);
+
+CREATE TABLE api_tokens
+( id INTEGER PRIMARY KEY
+, token_hash TEXT NOT NULL UNIQUE
+, user_id INTEGER NOT NULL REFERENCES users (id)
+, created DATE NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX api_tokens_user_id ON api_tokens (user_id);
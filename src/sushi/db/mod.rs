@@ -0,0 +1,237 @@
+mod game_json;
+mod instrumented;
+mod memory;
+mod migrations;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod query;
+mod sqlite;
+
+#[cfg(feature = "postgres")]
+pub use self::postgres::PostgresStore;
+pub use instrumented::InstrumentedStore;
+pub use memory::MemoryStore;
+pub use migrations::Migration;
+pub use query::{GamePage, GameQuery};
+pub use sqlite::SqliteStore;
+
+use super::auth::Role;
+use super::bots::{ApiToken, Bot};
+use super::chat::ChatMessage;
+use super::domain::*;
+use super::i18n::Locale;
+use super::{GameId, GameListItem, LobbyId, LobbyView};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::sync::Arc;
+use tokio::task::JoinError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A shared handle to whichever storage backend was selected at startup.
+pub type Store = Arc<dyn GameStore>;
+
+#[derive(Debug)]
+pub enum Error {
+    Rusqlite(rusqlite::Error),
+    #[cfg(feature = "postgres")]
+    Postgres(::postgres::Error),
+    Pool(r2d2::Error),
+    Blocking(JoinError),
+    GameVersionConflict,
+    MigrationChanged(u32),
+    SchemaTooNew(u32),
+    UnreadableGame(GameId, String),
+    UnreadableLobby(LobbyId, String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            Error::Rusqlite(e) => e.fmt(f),
+            #[cfg(feature = "postgres")]
+            Error::Postgres(e) => e.fmt(f),
+            Error::Pool(e) => e.fmt(f),
+            Error::Blocking(e) => e.fmt(f),
+            Error::GameVersionConflict => write!(f, "Game version conflict"),
+            Error::MigrationChanged(v) => {
+                write!(f, "Migration {} was edited after it was applied", v)
+            }
+            Error::UnreadableGame(id, reason) => {
+                write!(f, "Game {} could not be read: {}", id, reason)
+            }
+            Error::UnreadableLobby(id, reason) => {
+                write!(f, "Lobby {} could not be read: {}", id, reason)
+            }
+            Error::SchemaTooNew(v) => {
+                write!(f, "Database schema version {} is newer than this server", v)
+            }
+        }
+    }
+}
+
+impl StdError for Error {}
+
+fn deserialize_lobby(lobby_id: LobbyId, json: serde_json::Value) -> Result<Lobby> {
+    serde_json::from_value(json).map_err(|e| Error::UnreadableLobby(lobby_id, e.to_string()))
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Error {
+        Error::Rusqlite(error)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<::postgres::Error> for Error {
+    fn from(error: ::postgres::Error) -> Error {
+        Error::Postgres(error)
+    }
+}
+
+impl From<r2d2::Error> for Error {
+    fn from(error: r2d2::Error) -> Error {
+        Error::Pool(error)
+    }
+}
+
+impl From<JoinError> for Error {
+    fn from(error: JoinError) -> Error {
+        Error::Blocking(error)
+    }
+}
+
+/// Persistence for users and games. Implementations must be safe to share between the
+/// HTTP workers and the game actors.
+#[async_trait]
+pub trait GameStore: Send + Sync {
+    async fn migrate(&self) -> Result<()>;
+
+    /// Returns the migrations that `migrate` would apply, without changing anything.
+    async fn pending_migrations(&self) -> Result<Vec<&'static Migration>>;
+
+    // Users
+
+    // Users register with a password, but tests set up users by name.
+    #[cfg_attr(not(test), allow(dead_code))]
+    async fn get_or_insert_user_id(&self, user_name: &str) -> Result<UserId>;
+
+    /// Inserts a user with a password. Returns `None` if the name is already taken.
+    async fn insert_user(&self, user_name: &str, password_hash: &str) -> Result<Option<UserId>>;
+
+    /// Returns the id and password hash of a user. Users created before passwords were
+    /// introduced have no password hash until they claim their account.
+    async fn get_credentials(&self, user_name: &str) -> Result<Option<(UserId, Option<String>)>>;
+
+    /// Sets the password of a user that does not have one yet. Returns false if the user
+    /// already has a password.
+    async fn set_password_hash(&self, user_id: UserId, password_hash: &str) -> Result<bool>;
+
+    async fn get_user_id(&self, user_name: &str) -> Result<Option<UserId>>;
+
+    async fn get_user_role(&self, user_id: UserId) -> Result<Option<Role>>;
+
+    /// Returns false if there is no such user.
+    async fn set_user_role(&self, user_id: UserId, role: Role) -> Result<bool>;
+
+    /// The language the user prefers, if they have chosen one.
+    async fn get_user_locale(&self, user_id: UserId) -> Result<Option<Locale>>;
+
+    /// Sets or clears the language the user prefers. Returns false if there is no such
+    /// user.
+    async fn set_user_locale(&self, user_id: UserId, locale: Option<Locale>) -> Result<bool>;
+
+    /// Creates a bot account owned by a user. Returns `None` if the name is taken.
+    async fn insert_bot(&self, name: &str, owner_id: UserId) -> Result<Option<UserId>>;
+
+    async fn get_bots(&self, owner_id: UserId) -> Result<Vec<Bot>>;
+
+    /// Returns the ids of the bots playing in a game.
+    async fn get_bot_ids(&self, game_id: GameId) -> Result<Vec<UserId>>;
+
+    /// Stores the hash of a new API token for a bot. Returns `None` if the bot is not
+    /// owned by `owner_id`.
+    async fn persist_api_token(
+        &self,
+        bot_id: UserId,
+        owner_id: UserId,
+        token_hash: &str,
+    ) -> Result<Option<ApiToken>>;
+
+    /// Revokes an API token. Returns false if there is no such token for a bot owned by
+    /// `owner_id`.
+    async fn delete_api_token(
+        &self,
+        token_id: i64,
+        bot_id: UserId,
+        owner_id: UserId,
+    ) -> Result<bool>;
+
+    /// Returns the id, name and role of the bot an API token belongs to.
+    async fn get_api_token_user(&self, token_hash: &str) -> Result<Option<(UserId, String, Role)>>;
+
+    async fn persist_refresh_token(
+        &self,
+        token_hash: &str,
+        user_id: UserId,
+        expires: DateTime<Utc>,
+    ) -> Result<()>;
+
+    /// Deletes a refresh token, so it can only be used once, and returns the id and name
+    /// of its user unless it has expired.
+    async fn take_refresh_token(&self, token_hash: &str) -> Result<Option<(UserId, String)>>;
+
+    async fn get_user_name(&self, user_id: UserId) -> Result<Option<String>>;
+
+    // Games
+
+    async fn get_games_for_user(&self, user_id: UserId, query: &GameQuery) -> Result<GamePage>;
+
+    async fn persist_game(&self, game: &Game) -> Result<GameId>;
+
+    async fn update_game(&self, game_id: GameId, game: &Game, expected_version: u8) -> Result<()>;
+
+    async fn read_game(&self, game_id: GameId) -> Result<Option<(Game, u8)>>;
+
+    // Chat
+
+    async fn persist_chat_message(
+        &self,
+        game_id: GameId,
+        user_id: UserId,
+        text: &str,
+    ) -> Result<ChatMessage>;
+
+    /// Returns the last `limit` messages of a game, oldest first.
+    async fn get_chat_messages(&self, game_id: GameId, limit: usize) -> Result<Vec<ChatMessage>>;
+
+    // Lobbies
+
+    /// Returns the lobbies the user is seated in that have not started yet.
+    async fn get_lobbies_for_user(&self, user_id: UserId) -> Result<Vec<LobbyView>>;
+
+    async fn persist_lobby(&self, lobby: &Lobby) -> Result<LobbyId>;
+
+    /// Saves the lobby if it is still at `expected_version`. A `game` dealt by the lobby
+    /// is persisted in the same transaction, so a lobby can never start twice.
+    async fn update_lobby(
+        &self,
+        lobby_id: LobbyId,
+        lobby: &Lobby,
+        expected_version: u8,
+        game: Option<&Game>,
+    ) -> Result<Option<GameId>>;
+
+    async fn read_lobby(&self, lobby_id: LobbyId) -> Result<Option<(LobbyView, u8)>>;
+
+    /// Finds an open table by its join code.
+    async fn find_lobby_by_code(&self, code: &str) -> Result<Option<LobbyId>>;
+
+    /// Saves a lobby proposing a rematch of `game_id`. There can only be one rematch of
+    /// each game, so this fails with `GameVersionConflict` if another one was saved first.
+    async fn persist_rematch(&self, game_id: GameId, lobby: &Lobby) -> Result<LobbyId>;
+
+    async fn find_rematch(&self, game_id: GameId) -> Result<Option<LobbyId>>;
+}
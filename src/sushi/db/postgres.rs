@@ -0,0 +1,1699 @@
+use super::*;
+use ::postgres::{error::SqlState, Client, Config, NoTls, Row, Transaction};
+use r2d2::{Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::SystemTime;
+use tokio::task::spawn_blocking;
+
+type Manager = PostgresConnectionManager<NoTls>;
+type Connection = PooledConnection<Manager>;
+
+/// Stores games as JSONB in PostgreSQL. Enabled with the `postgres` cargo feature.
+#[derive(Clone)]
+pub struct PostgresStore {
+    pool: Pool<Manager>,
+}
+
+impl PostgresStore {
+    pub fn new(url: &str) -> Result<Self> {
+        let config = Config::from_str(url)?;
+        let manager = PostgresConnectionManager::new(config, NoTls);
+        let pool = Pool::builder().build(manager)?;
+
+        Ok(Self { pool })
+    }
+
+    /// Runs `f` with a pooled connection on the blocking thread pool, so slow queries
+    /// do not stall the async executor.
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Client) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+
+        spawn_blocking(move || {
+            let mut connection: Connection = pool.get()?;
+            f(&mut connection)
+        })
+        .await?
+    }
+
+    async fn insert_lobby(&self, lobby: &Lobby, rematch_of: Option<GameId>) -> Result<LobbyId> {
+        static SQL: &str = "
+            INSERT INTO lobbies (data, code, rematch_of)
+            VALUES ($1, $2, $3)
+            RETURNING id";
+
+        let json = serde_json::to_value(lobby).expect("can serialize lobby to json");
+        let code = lobby.code().map(str::to_string);
+        let rematch_of = rematch_of.map(|game_id| game_id.0);
+        let user_ids = lobby.user_ids();
+
+        self.run(move |client| {
+            let mut tx = client.transaction()?;
+            let lobby_id = LobbyId(tx.query_one(SQL, &[&json, &code, &rematch_of])?.get(0));
+            seat_lobby_users(&mut tx, lobby_id, &user_ids)?;
+            tx.commit()?;
+
+            Ok(lobby_id)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl GameStore for PostgresStore {
+    async fn migrate(&self) -> Result<()> {
+        self.run(|client| migrate(client, migrations::POSTGRES))
+            .await
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<&'static Migration>> {
+        self.run(|client| {
+            let (schema_version, checksums) = applied_migrations(client)?;
+            migrations::pending(migrations::POSTGRES, schema_version, &checksums)
+        })
+        .await
+    }
+
+    async fn get_or_insert_user_id(&self, user_name: &str) -> Result<UserId> {
+        static GET_SQL: &str = "
+            SELECT id
+            FROM users
+            WHERE name = $1";
+
+        static INSERT_SQL: &str = "
+            INSERT INTO users (name)
+            VALUES ($1)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id";
+
+        let user_name = user_name.to_string();
+
+        self.run(move |client| {
+            let row = match client.query_opt(GET_SQL, &[&user_name])? {
+                Some(row) => row,
+                None => {
+                    info!("Inserting user {}", user_name);
+
+                    // Another request may have inserted the user in the meantime
+                    match client.query_opt(INSERT_SQL, &[&user_name])? {
+                        Some(row) => row,
+                        None => client.query_one(GET_SQL, &[&user_name])?,
+                    }
+                }
+            };
+
+            Ok(UserId(row.get(0)))
+        })
+        .await
+    }
+
+    async fn insert_user(&self, user_name: &str, password_hash: &str) -> Result<Option<UserId>> {
+        static SQL: &str = "
+            INSERT INTO users (name, password_hash)
+            VALUES ($1, $2)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id";
+
+        let user_name = user_name.to_string();
+        let password_hash = password_hash.to_string();
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&user_name, &password_hash])?;
+
+            if row.is_some() {
+                info!("Registered user {}", user_name);
+            }
+
+            Ok(row.map(|row| UserId(row.get(0))))
+        })
+        .await
+    }
+
+    async fn get_credentials(&self, user_name: &str) -> Result<Option<(UserId, Option<String>)>> {
+        static SQL: &str = "
+            SELECT id, password_hash
+            FROM users
+            WHERE name = $1";
+
+        let user_name = user_name.to_string();
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&user_name])?;
+            Ok(row.map(|row| (UserId(row.get(0)), row.get(1))))
+        })
+        .await
+    }
+
+    async fn set_password_hash(&self, user_id: UserId, password_hash: &str) -> Result<bool> {
+        static SQL: &str = "
+            UPDATE users
+            SET password_hash = $2
+            WHERE id = $1
+            AND password_hash IS NULL";
+
+        let password_hash = password_hash.to_string();
+
+        self.run(move |client| {
+            let updated = client.execute(SQL, &[&user_id.0, &password_hash])?;
+            Ok(updated == 1)
+        })
+        .await
+    }
+
+    async fn get_user_id(&self, user_name: &str) -> Result<Option<UserId>> {
+        static SQL: &str = "
+            SELECT id
+            FROM users
+            WHERE name = $1";
+
+        let user_name = user_name.to_string();
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&user_name])?;
+            Ok(row.map(|row| UserId(row.get(0))))
+        })
+        .await
+    }
+
+    async fn get_user_role(&self, user_id: UserId) -> Result<Option<Role>> {
+        static SQL: &str = "
+            SELECT role
+            FROM users
+            WHERE id = $1";
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&user_id.0])?;
+            Ok(row.map(|row| row.get::<_, &str>(0).parse().unwrap_or_default()))
+        })
+        .await
+    }
+
+    async fn set_user_role(&self, user_id: UserId, role: Role) -> Result<bool> {
+        static SQL: &str = "
+            UPDATE users
+            SET role = $2
+            WHERE id = $1";
+
+        self.run(move |client| {
+            let updated = client.execute(SQL, &[&user_id.0, &role.to_string()])?;
+            Ok(updated == 1)
+        })
+        .await
+    }
+
+    async fn get_user_locale(&self, user_id: UserId) -> Result<Option<Locale>> {
+        static SQL: &str = "
+            SELECT locale
+            FROM users
+            WHERE id = $1";
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&user_id.0])?;
+            let locale = row.and_then(|row| row.get::<_, Option<String>>(0));
+            Ok(locale.and_then(|locale| locale.parse().ok()))
+        })
+        .await
+    }
+
+    async fn set_user_locale(&self, user_id: UserId, locale: Option<Locale>) -> Result<bool> {
+        static SQL: &str = "
+            UPDATE users
+            SET locale = $2
+            WHERE id = $1";
+
+        self.run(move |client| {
+            let locale = locale.map(|locale| locale.to_string());
+            let updated = client.execute(SQL, &[&user_id.0, &locale])?;
+            Ok(updated == 1)
+        })
+        .await
+    }
+
+    async fn insert_bot(&self, name: &str, owner_id: UserId) -> Result<Option<UserId>> {
+        static SQL: &str = "
+            INSERT INTO users (name, bot_owner_id)
+            VALUES ($1, $2)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id";
+
+        let name = name.to_string();
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&name, &owner_id.0])?;
+
+            if row.is_some() {
+                info!("User {} created bot {}", owner_id, name);
+            }
+
+            Ok(row.map(|row| UserId(row.get(0))))
+        })
+        .await
+    }
+
+    async fn get_bots(&self, owner_id: UserId) -> Result<Vec<Bot>> {
+        static SQL: &str = "
+            SELECT u.id, u.name, t.id, t.created
+            FROM users u
+            LEFT JOIN api_tokens t ON t.user_id = u.id
+            WHERE u.bot_owner_id = $1
+            ORDER BY u.id, t.id";
+
+        self.run(move |client| {
+            let mut bots: Vec<Bot> = vec![];
+
+            for row in client.query(SQL, &[&owner_id.0])? {
+                let id = UserId(row.get(0));
+
+                if bots.last().is_none_or(|bot| bot.id != id) {
+                    bots.push(Bot {
+                        id,
+                        name: row.get(1),
+                        tokens: vec![],
+                    });
+                }
+
+                if let Some(token_id) = row.get(2) {
+                    let bot = bots.last_mut().expect("bot was just pushed");
+                    let created: SystemTime = row.get(3);
+
+                    bot.tokens.push(ApiToken {
+                        id: token_id,
+                        created: created.into(),
+                    });
+                }
+            }
+
+            Ok(bots)
+        })
+        .await
+    }
+
+    async fn get_bot_ids(&self, game_id: GameId) -> Result<Vec<UserId>> {
+        static SQL: &str = "
+            SELECT u.id
+            FROM games_users gu
+            INNER JOIN users u ON gu.user_id = u.id
+            WHERE gu.game_id = $1
+            AND u.bot_owner_id IS NOT NULL";
+
+        self.run(move |client| {
+            let rows = client.query(SQL, &[&game_id.0])?;
+            Ok(rows.iter().map(|row| UserId(row.get(0))).collect())
+        })
+        .await
+    }
+
+    async fn persist_api_token(
+        &self,
+        bot_id: UserId,
+        owner_id: UserId,
+        token_hash: &str,
+    ) -> Result<Option<ApiToken>> {
+        static SQL: &str = "
+            INSERT INTO api_tokens (token_hash, user_id)
+            SELECT $1, id
+            FROM users
+            WHERE id = $2
+            AND bot_owner_id = $3
+            RETURNING id, created";
+
+        let token_hash = token_hash.to_string();
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&token_hash, &bot_id.0, &owner_id.0])?;
+
+            Ok(row.map(|row| {
+                let created: SystemTime = row.get(1);
+
+                ApiToken {
+                    id: row.get(0),
+                    created: created.into(),
+                }
+            }))
+        })
+        .await
+    }
+
+    async fn delete_api_token(
+        &self,
+        token_id: i64,
+        bot_id: UserId,
+        owner_id: UserId,
+    ) -> Result<bool> {
+        static SQL: &str = "
+            DELETE FROM api_tokens
+            WHERE id = $1
+            AND user_id IN (
+                SELECT id
+                FROM users
+                WHERE id = $2
+                AND bot_owner_id = $3
+            )";
+
+        self.run(move |client| {
+            let deleted = client.execute(SQL, &[&token_id, &bot_id.0, &owner_id.0])?;
+            Ok(deleted == 1)
+        })
+        .await
+    }
+
+    async fn get_api_token_user(&self, token_hash: &str) -> Result<Option<(UserId, String, Role)>> {
+        static SQL: &str = "
+            SELECT u.id, u.name, u.role
+            FROM api_tokens t
+            INNER JOIN users u ON t.user_id = u.id
+            WHERE t.token_hash = $1";
+
+        let token_hash = token_hash.to_string();
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&token_hash])?;
+
+            Ok(row.map(|row| {
+                let role = row.get::<_, &str>(2).parse().unwrap_or_default();
+                (UserId(row.get(0)), row.get(1), role)
+            }))
+        })
+        .await
+    }
+
+    async fn persist_refresh_token(
+        &self,
+        token_hash: &str,
+        user_id: UserId,
+        expires: DateTime<Utc>,
+    ) -> Result<()> {
+        static SQL: &str = "
+            INSERT INTO refresh_tokens (token_hash, user_id, expires)
+            VALUES ($1, $2, $3)";
+
+        let token_hash = token_hash.to_string();
+        let expires = SystemTime::from(expires);
+
+        self.run(move |client| {
+            client.execute(SQL, &[&token_hash, &user_id.0, &expires])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn take_refresh_token(&self, token_hash: &str) -> Result<Option<(UserId, String)>> {
+        static SQL: &str = "
+            WITH deleted AS (
+                DELETE FROM refresh_tokens
+                WHERE token_hash = $1
+                RETURNING user_id, expires
+            )
+            SELECT d.user_id, u.name
+            FROM deleted d
+            INNER JOIN users u ON d.user_id = u.id
+            WHERE d.expires > now()";
+
+        let token_hash = token_hash.to_string();
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&token_hash])?;
+            Ok(row.map(|row| (UserId(row.get(0)), row.get(1))))
+        })
+        .await
+    }
+
+    async fn get_user_name(&self, user_id: UserId) -> Result<Option<String>> {
+        static SQL: &str = "
+            SELECT name
+            FROM users
+            WHERE id = $1";
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&user_id.0])?;
+            Ok(row.map(|row| row.get(0)))
+        })
+        .await
+    }
+
+    async fn get_games_for_user(&self, user_id: UserId, query: &GameQuery) -> Result<GamePage> {
+        static SQL: &str = "
+            SELECT *
+            FROM (
+                SELECT g.id
+                     , ( SELECT array_agg(u.name ORDER BY p.id)
+                         FROM games_users p
+                         INNER JOIN users u ON p.user_id = u.id
+                         WHERE p.game_id = g.id
+                       ) AS players
+                     , g.round
+                     , g.winner_id IS NOT NULL AS game_over
+                     , w.name AS winner
+                     , g.winner_id IS NULL AND NOT gu.ready AS your_move
+                     , CASE WHEN g.winner_id IS NULL THEN (
+                           SELECT COUNT(*)
+                           FROM games_users p
+                           WHERE p.game_id = g.id
+                           AND NOT p.ready
+                       ) ELSE 0 END AS num_pending
+                     , g.created
+                     , g.updated
+                     , CASE WHEN $7 = 'yourMove' AND g.winner_id IS NULL AND NOT gu.ready
+                            THEN 1 ELSE 0 END AS priority
+                     , CASE WHEN $7 = 'created' THEN g.created ELSE g.updated END AS sort_time
+                FROM games g
+                INNER JOIN games_users gu ON gu.game_id = g.id
+                LEFT JOIN users w ON w.id = g.winner_id
+                WHERE gu.user_id = $1
+                AND ( $2::text IS NULL
+                   OR ($2 = 'finished' AND g.winner_id IS NOT NULL)
+                   OR ($2 = 'active' AND g.winner_id IS NULL AND g.updated >= $3)
+                   OR ($2 = 'abandoned' AND g.winner_id IS NULL AND g.updated < $3)
+                    )
+                AND ( $4::text IS NULL
+                   OR EXISTS (
+                          SELECT 1
+                          FROM games_users o
+                          INNER JOIN users u ON u.id = o.user_id
+                          WHERE o.game_id = g.id
+                          AND o.user_id <> $1
+                          AND u.name = $4
+                      )
+                    )
+                AND ($5::timestamptz IS NULL OR g.updated >= $5)
+                AND ($6::timestamptz IS NULL OR g.updated <= $6)
+            ) AS page
+            WHERE $10::bigint IS NULL
+            OR (priority, sort_time, id) < ($8::int, $9::timestamptz, $10::bigint)
+            ORDER BY priority DESC, sort_time DESC, id DESC
+            LIMIT $11";
+
+        let status = query.status.map(|s| s.as_str());
+        let opponent = query.opponent.clone();
+        let abandoned_before = SystemTime::from(query.abandoned_before());
+        let from = query.from.map(SystemTime::from);
+        let to = query.to.map(SystemTime::from);
+        let sort = query.sort.as_str();
+        let after_priority = query.after.map(|c| c.priority);
+        let after_time = query.after.map(|c| SystemTime::from(c.time));
+        let after_id = query.after.map(|c| c.id.0);
+        let limit = query.limit() as i64 + 1;
+
+        let games = self
+            .run(move |client| {
+                let games = client
+                    .query(
+                        SQL,
+                        &[
+                            &user_id.0,
+                            &status,
+                            &abandoned_before,
+                            &opponent,
+                            &from,
+                            &to,
+                            &sort,
+                            &after_priority,
+                            &after_time,
+                            &after_id,
+                            &limit,
+                        ],
+                    )?
+                    .into_iter()
+                    .map(|row| GameListItem {
+                        id: GameId(row.get(0)),
+                        players: row.get(1),
+                        round: row.get::<_, i32>(2) as usize,
+                        game_over: row.get(3),
+                        winner: row.get(4),
+                        your_move: row.get(5),
+                        num_pending: row.get::<_, i64>(6) as usize,
+                        created: row.get::<_, SystemTime>(7).into(),
+                        updated: row.get::<_, SystemTime>(8).into(),
+                    })
+                    .collect();
+
+                Ok(games)
+            })
+            .await?;
+
+        Ok(GamePage::new(games, query))
+    }
+
+    async fn persist_game(&self, game: &Game) -> Result<GameId> {
+        let game = game.clone();
+
+        self.run(move |client| {
+            let mut tx = client.transaction()?;
+            let game_id = insert_game(&mut tx, &game)?;
+            tx.commit()?;
+
+            Ok(game_id)
+        })
+        .await
+    }
+
+    async fn update_game(&self, game_id: GameId, game: &Game, expected_version: u8) -> Result<()> {
+        static SQL: &str = "
+            UPDATE games
+            SET data = $2
+              , version = $4
+              , updated = now()
+            WHERE id = $1
+            AND version = $3";
+
+        info!("Updating game {} at version {}", game_id, expected_version);
+
+        let json = game_json::serialize(game);
+        let summary = game.summary();
+        let expected_version = i16::from(expected_version);
+        let new_version = expected_version + 1;
+
+        self.run(move |client| {
+            let mut tx = client.transaction()?;
+            let updated = tx.execute(SQL, &[&game_id.0, &json, &expected_version, &new_version])?;
+
+            if updated == 0 {
+                return Err(Error::GameVersionConflict);
+            }
+
+            mirror_summary(&mut tx, game_id, &summary)?;
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn read_game(&self, game_id: GameId) -> Result<Option<(Game, u8)>> {
+        static SQL: &str = "
+            SELECT data, version
+            FROM games
+            WHERE id = $1";
+
+        info!("Finding game {} for system", game_id);
+
+        self.run(move |client| match client.query_opt(SQL, &[&game_id.0])? {
+            None => Ok(None),
+            Some(row) => {
+                let json: Value = row.get(0);
+                let version: i16 = row.get(1);
+                let game = game_json::deserialize(game_id, json)?;
+                Ok(Some((game, version as u8)))
+            }
+        })
+        .await
+    }
+
+    async fn persist_chat_message(
+        &self,
+        game_id: GameId,
+        user_id: UserId,
+        text: &str,
+    ) -> Result<ChatMessage> {
+        static SQL: &str = "
+            INSERT INTO chat_messages (game_id, user_id, text)
+            VALUES ($1, $2, $3)
+            RETURNING id";
+
+        static SELECT_SQL: &str = "
+            SELECT m.user_id, u.name, m.text, m.sent
+            FROM chat_messages m
+            INNER JOIN users u ON m.user_id = u.id
+            WHERE m.id = $1";
+
+        let text = text.to_string();
+
+        self.run(move |client| {
+            let id: i64 = client
+                .query_one(SQL, &[&game_id.0, &user_id.0, &text])?
+                .get(0);
+
+            Ok(chat_message(&client.query_one(SELECT_SQL, &[&id])?))
+        })
+        .await
+    }
+
+    async fn get_chat_messages(&self, game_id: GameId, limit: usize) -> Result<Vec<ChatMessage>> {
+        static SQL: &str = "
+            SELECT *
+            FROM (
+                SELECT m.user_id, u.name, m.text, m.sent, m.id
+                FROM chat_messages m
+                INNER JOIN users u ON m.user_id = u.id
+                WHERE m.game_id = $1
+                ORDER BY m.id DESC
+                LIMIT $2
+            ) m
+            ORDER BY id";
+
+        let limit = limit as i64;
+
+        self.run(move |client| {
+            let rows = client.query(SQL, &[&game_id.0, &limit])?;
+            Ok(rows.iter().map(chat_message).collect())
+        })
+        .await
+    }
+
+    async fn get_lobbies_for_user(&self, user_id: UserId) -> Result<Vec<LobbyView>> {
+        static SQL: &str = "
+            SELECT l.id, l.data, l.game_id
+            FROM lobbies l
+            INNER JOIN lobbies_users lu ON lu.lobby_id = l.id
+            WHERE lu.user_id = $1
+            AND l.game_id IS NULL
+            ORDER BY l.id DESC";
+
+        self.run(move |client| {
+            client
+                .query(SQL, &[&user_id.0])?
+                .into_iter()
+                .map(|row| {
+                    let id = LobbyId(row.get(0));
+
+                    Ok(LobbyView {
+                        id,
+                        lobby: deserialize_lobby(id, row.get(1))?,
+                        game_id: row.get::<_, Option<i64>>(2).map(GameId),
+                    })
+                })
+                .collect()
+        })
+        .await
+    }
+
+    async fn persist_lobby(&self, lobby: &Lobby) -> Result<LobbyId> {
+        self.insert_lobby(lobby, None).await
+    }
+
+    async fn update_lobby(
+        &self,
+        lobby_id: LobbyId,
+        lobby: &Lobby,
+        expected_version: u8,
+        game: Option<&Game>,
+    ) -> Result<Option<GameId>> {
+        static SQL: &str = "
+            UPDATE lobbies
+            SET data = $2
+              , version = $4
+            WHERE id = $1
+            AND version = $3";
+
+        static GAME_SQL: &str = "
+            UPDATE lobbies
+            SET game_id = $2
+            WHERE id = $1";
+
+        info!(
+            "Updating lobby {} at version {}",
+            lobby_id, expected_version
+        );
+
+        let json = serde_json::to_value(lobby).expect("can serialize lobby to json");
+        let user_ids = lobby.user_ids();
+        let game = game.cloned();
+        let expected_version = i16::from(expected_version);
+        let new_version = expected_version + 1;
+
+        self.run(move |client| {
+            let mut tx = client.transaction()?;
+            let updated =
+                tx.execute(SQL, &[&lobby_id.0, &json, &expected_version, &new_version])?;
+
+            if updated == 0 {
+                return Err(Error::GameVersionConflict);
+            }
+
+            seat_lobby_users(&mut tx, lobby_id, &user_ids)?;
+
+            let game_id = match game {
+                None => None,
+                Some(game) => {
+                    let game_id = insert_game(&mut tx, &game)?;
+                    tx.execute(GAME_SQL, &[&lobby_id.0, &game_id.0])?;
+                    Some(game_id)
+                }
+            };
+
+            tx.commit()?;
+
+            Ok(game_id)
+        })
+        .await
+    }
+
+    async fn read_lobby(&self, lobby_id: LobbyId) -> Result<Option<(LobbyView, u8)>> {
+        static SQL: &str = "
+            SELECT data, version, game_id
+            FROM lobbies
+            WHERE id = $1";
+
+        self.run(move |client| {
+            let row = match client.query_opt(SQL, &[&lobby_id.0])? {
+                None => return Ok(None),
+                Some(row) => row,
+            };
+
+            let view = LobbyView {
+                id: lobby_id,
+                lobby: deserialize_lobby(lobby_id, row.get(0))?,
+                game_id: row.get::<_, Option<i64>>(2).map(GameId),
+            };
+
+            Ok(Some((view, row.get::<_, i16>(1) as u8)))
+        })
+        .await
+    }
+
+    async fn persist_rematch(&self, game_id: GameId, lobby: &Lobby) -> Result<LobbyId> {
+        match self.insert_lobby(lobby, Some(game_id)).await {
+            Err(Error::Postgres(e)) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+                Err(Error::GameVersionConflict)
+            }
+            result => result,
+        }
+    }
+
+    async fn find_rematch(&self, game_id: GameId) -> Result<Option<LobbyId>> {
+        static SQL: &str = "
+            SELECT id
+            FROM lobbies
+            WHERE rematch_of = $1";
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&game_id.0])?;
+            Ok(row.map(|row| LobbyId(row.get(0))))
+        })
+        .await
+    }
+
+    async fn find_lobby_by_code(&self, code: &str) -> Result<Option<LobbyId>> {
+        static SQL: &str = "
+            SELECT id
+            FROM lobbies
+            WHERE code = $1";
+
+        let code = code.to_string();
+
+        self.run(move |client| {
+            let row = client.query_opt(SQL, &[&code])?;
+            Ok(row.map(|row| LobbyId(row.get(0))))
+        })
+        .await
+    }
+}
+
+fn chat_message(row: &Row) -> ChatMessage {
+    ChatMessage {
+        user_id: UserId(row.get(0)),
+        name: row.get(1),
+        text: row.get(2),
+        sent: row.get::<_, SystemTime>(3).into(),
+    }
+}
+
+fn insert_game(tx: &mut Transaction, game: &Game) -> Result<GameId> {
+    static GAME_SQL: &str = "
+        INSERT INTO games (data)
+        VALUES ($1)
+        RETURNING id";
+
+    static PLAYER_SQL: &str = "
+        INSERT INTO games_users (game_id, user_id)
+        VALUES ($1, $2)";
+
+    let json = game_json::serialize(game);
+    let summary = game.summary();
+    let game_id = GameId(tx.query_one(GAME_SQL, &[&json])?.get(0));
+    let statement = tx.prepare(PLAYER_SQL)?;
+
+    for player in &summary.players {
+        tx.execute(&statement, &[&game_id.0, &player.id.0])?;
+    }
+
+    mirror_summary(tx, game_id, &summary)?;
+    Ok(game_id)
+}
+
+/// Replaces the lookup rows used to find the lobbies a user is seated in.
+fn seat_lobby_users(tx: &mut Transaction, lobby_id: LobbyId, user_ids: &[UserId]) -> Result<()> {
+    static DELETE_SQL: &str = "
+        DELETE FROM lobbies_users
+        WHERE lobby_id = $1";
+
+    static INSERT_SQL: &str = "
+        INSERT INTO lobbies_users (lobby_id, user_id)
+        VALUES ($1, $2)";
+
+    tx.execute(DELETE_SQL, &[&lobby_id.0])?;
+
+    for user_id in user_ids {
+        tx.execute(INSERT_SQL, &[&lobby_id.0, &user_id.0])?;
+    }
+
+    Ok(())
+}
+
+/// Copies the queryable parts of a game into their own columns and tables.
+fn mirror_summary(tx: &mut Transaction, game_id: GameId, summary: &GameSummary) -> Result<()> {
+    static GAME_SQL: &str = "
+        UPDATE games
+        SET round = $2
+          , winner_id = $3
+        WHERE id = $1";
+
+    static PLAYER_SQL: &str = "
+        UPDATE games_users
+        SET num_points = $3
+          , num_puddings = $4
+          , ready = $5
+        WHERE game_id = $1
+        AND user_id = $2";
+
+    static CARD_SQL: &str = "
+        INSERT INTO played_cards (game_id, user_id, card, count)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (game_id, user_id, card) DO UPDATE SET count = excluded.count";
+
+    let winner_id = summary.winner.map(|id| id.0);
+
+    tx.execute(GAME_SQL, &[&game_id.0, &(summary.round as i32), &winner_id])?;
+
+    for player in &summary.players {
+        tx.execute(
+            PLAYER_SQL,
+            &[
+                &game_id.0,
+                &player.id.0,
+                &(player.num_points as i32),
+                &(player.num_puddings as i32),
+                &player.ready,
+            ],
+        )?;
+
+        for (card, count) in player.played_card_counts() {
+            tx.execute(
+                CARD_SQL,
+                &[&game_id.0, &player.id.0, &card, &(count as i32)],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn migrate(client: &mut Client, migrations: &[Migration]) -> Result<()> {
+    static INSERT_SQL: &str = "
+        INSERT INTO schema_migrations (version, name, checksum)
+        VALUES ($1, $2, $3)";
+
+    client.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_version
+        ( version INTEGER NOT NULL
+        );
+
+        INSERT INTO schema_version (version)
+        SELECT 0
+        WHERE NOT EXISTS (SELECT * FROM schema_version);
+
+        CREATE TABLE IF NOT EXISTS schema_migrations
+        ( version INTEGER PRIMARY KEY
+        , name TEXT NOT NULL
+        , checksum TEXT NOT NULL
+        , applied TIMESTAMPTZ NOT NULL DEFAULT now()
+        );",
+    )?;
+
+    let (schema_version, checksums) = applied_migrations(client)?;
+
+    info!("db schema_version is {}", schema_version);
+
+    let pending = migrations::pending(migrations, schema_version, &checksums)?;
+
+    // Record checksums for migrations applied before checksums were tracked
+    for migration in migrations {
+        if migration.version <= schema_version && !checksums.contains_key(&migration.version) {
+            client.execute(
+                INSERT_SQL,
+                &[
+                    &(migration.version as i32),
+                    &migration.name,
+                    &migration.checksum(),
+                ],
+            )?;
+        }
+    }
+
+    if pending.is_empty() {
+        info!("no migration performed");
+    }
+
+    for migration in pending {
+        let mut tx = client.transaction()?;
+        tx.batch_execute(migration.sql)?;
+        tx.execute(
+            "UPDATE schema_version SET version = $1",
+            &[&(migration.version as i32)],
+        )?;
+        tx.execute(
+            INSERT_SQL,
+            &[
+                &(migration.version as i32),
+                &migration.name,
+                &migration.checksum(),
+            ],
+        )?;
+        tx.commit()?;
+
+        info!(
+            "applied migration {} ({})",
+            migration.version, migration.name
+        );
+    }
+
+    Ok(())
+}
+
+fn applied_migrations(client: &mut Client) -> Result<(u32, HashMap<u32, String>)> {
+    let row = client.query_one(
+        "SELECT to_regclass('schema_version') IS NOT NULL
+              , to_regclass('schema_migrations') IS NOT NULL",
+        &[],
+    )?;
+
+    let (has_version, has_checksums): (bool, bool) = (row.get(0), row.get(1));
+
+    let schema_version: i32 = if has_version {
+        client
+            .query_one("SELECT version FROM schema_version", &[])?
+            .get(0)
+    } else {
+        0
+    };
+
+    let mut checksums = HashMap::new();
+
+    if has_checksums {
+        for row in client.query("SELECT version, checksum FROM schema_migrations", &[])? {
+            let version: i32 = row.get(0);
+            checksums.insert(version as u32, row.get(1));
+        }
+    }
+
+    Ok((schema_version as u32, checksums))
+}
+
+// These tests need a throwaway PostgreSQL database; all tables in it are dropped.
+// Run them with `SUSHI_TEST_POSTGRES_URL=... cargo test --features postgres -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use super::query::GameStatus;
+    use super::*;
+    use chrono::Duration;
+    use linked_hash_set::LinkedHashSet;
+    use serial_test::serial;
+    use std::convert::TryFrom;
+    use std::iter::FromIterator;
+
+    lazy_static! {
+        static ref PLAYER1: UserId = UserId(1);
+        static ref PLAYER2: UserId = UserId(2);
+        static ref PLAYER3: UserId = UserId(3);
+    }
+
+    async fn setup_store() -> PostgresStore {
+        let url = std::env::var("SUSHI_TEST_POSTGRES_URL")
+            .expect("SUSHI_TEST_POSTGRES_URL must be set to run the PostgreSQL tests");
+
+        let store = PostgresStore::new(&url).unwrap();
+
+        store
+            .run(|client| {
+                client.batch_execute(
+                    "DROP TABLE IF EXISTS api_tokens, refresh_tokens, chat_messages, lobbies_users, lobbies, played_cards, games_users, games, users, schema_version, schema_migrations CASCADE",
+                )?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        store.migrate().await.unwrap();
+
+        for name in &["a", "b", "c"] {
+            store.get_or_insert_user_id(name).await.unwrap();
+        }
+
+        store
+    }
+
+    fn game() -> Game {
+        Game::try_from(LinkedHashSet::from_iter(vec![*PLAYER1, *PLAYER2, *PLAYER3])).unwrap()
+    }
+
+    fn other_game() -> Game {
+        Game::try_from(LinkedHashSet::from_iter(vec![*PLAYER2, *PLAYER3])).unwrap()
+    }
+
+    fn select_first_card(game: &mut Game, user_id: UserId) {
+        let view = game.clone().into(user_id);
+        let card = *view.player.unwrap().hand.keys().next().unwrap();
+        game.select_cards(user_id, vec![card]).unwrap();
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_migrate_twice() {
+        // arrange
+        let store = setup_store().await;
+
+        // act
+        let result = store.migrate().await;
+
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn nothing_is_pending_after_migrate() {
+        // arrange
+        let store = setup_store().await;
+
+        // act
+        let pending = store.pending_migrations().await.unwrap();
+
+        // assert
+        assert!(pending.is_empty());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn get_or_insert_user_id_will_get() {
+        // arrange
+        let store = setup_store().await;
+
+        // act
+        let actual = store.get_or_insert_user_id("b").await;
+
+        // assert
+        assert_eq!(actual.unwrap(), *PLAYER2);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_read_game() {
+        // arrange
+        let store = setup_store().await;
+        let game = game();
+        let game_id = store.persist_game(&game).await.unwrap();
+
+        // act
+        let actual = store.read_game(game_id).await.unwrap();
+
+        // assert
+        assert_eq!(actual, Some((game, 0)));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_get_games_for_user() {
+        // arrange
+        let store = setup_store().await;
+        store.persist_game(&game()).await.unwrap();
+        store.persist_game(&game()).await.unwrap();
+        store.persist_game(&other_game()).await.unwrap(); // Game without player 1
+
+        // act
+        let games = store
+            .get_games_for_user(*PLAYER1, &GameQuery::default())
+            .await
+            .unwrap()
+            .games;
+
+        // assert
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].players, vec!["a", "b", "c"]);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn update_increments_version() {
+        // arrange
+        let store = setup_store().await;
+        let game = game();
+        let game_id = store.persist_game(&game).await.unwrap();
+        store.update_game(game_id, &game, 0).await.unwrap();
+
+        // act
+        let actual = store.read_game(game_id).await.unwrap();
+
+        // assert
+        assert_eq!(actual, Some((game, 1)));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn cannot_update_if_expected_version_does_not_match() {
+        // arrange
+        let store = setup_store().await;
+        let game = game();
+        let game_id = store.persist_game(&game).await.unwrap();
+        store.update_game(game_id, &game, 0).await.unwrap();
+
+        // act
+        let error = store.update_game(game_id, &game, 0).await.unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameVersionConflict));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn games_where_it_is_your_move_come_first() {
+        // arrange
+        let store = setup_store().await;
+        let waiting = store.persist_game(&game()).await.unwrap();
+        let moved = store.persist_game(&game()).await.unwrap();
+        let mut game = game();
+        select_first_card(&mut game, *PLAYER1);
+        store.update_game(moved, &game, 0).await.unwrap();
+
+        // act
+        let games = store
+            .get_games_for_user(*PLAYER1, &GameQuery::default())
+            .await
+            .unwrap()
+            .games;
+
+        // assert
+        let ids = games.iter().map(|g| g.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![waiting, moved]);
+        assert!(games[0].your_move);
+        assert_eq!(games[0].num_pending, 3);
+        assert!(!games[1].your_move);
+        assert_eq!(games[1].num_pending, 2);
+        assert!(!games[1].game_over);
+        assert_eq!(games[1].winner, None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_page_through_games() {
+        // arrange
+        let store = setup_store().await;
+        let mut expected = vec![];
+
+        for _ in 0..5 {
+            expected.insert(0, store.persist_game(&game()).await.unwrap());
+        }
+
+        let mut query = GameQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        // act
+        let mut ids = vec![];
+
+        loop {
+            let page = store.get_games_for_user(*PLAYER1, &query).await.unwrap();
+            ids.extend(page.games.iter().map(|g| g.id));
+
+            match page.next {
+                Some(next) => query.after = Some(next),
+                None => break,
+            }
+        }
+
+        // assert
+        assert_eq!(ids, expected);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_filter_games_by_opponent() {
+        // arrange
+        let store = setup_store().await;
+        store.persist_game(&game()).await.unwrap();
+        store.persist_game(&other_game()).await.unwrap();
+
+        let query = GameQuery {
+            opponent: Some("c".to_string()),
+            ..Default::default()
+        };
+
+        // act
+        let page = store.get_games_for_user(*PLAYER2, &query).await.unwrap();
+
+        // assert
+        assert_eq!(page.games.len(), 2);
+        assert!(page.next.is_none());
+
+        let query = GameQuery {
+            opponent: Some("a".to_string()),
+            ..query
+        };
+
+        let page = store.get_games_for_user(*PLAYER2, &query).await.unwrap();
+        assert_eq!(page.games.len(), 1);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_filter_games_by_status() {
+        // arrange
+        let store = setup_store().await;
+        store.persist_game(&game()).await.unwrap();
+
+        let expected = vec![
+            (GameStatus::Active, 1),
+            (GameStatus::Finished, 0),
+            (GameStatus::Abandoned, 0),
+        ];
+
+        for (status, expected) in expected {
+            let query = GameQuery {
+                status: Some(status),
+                ..Default::default()
+            };
+
+            // act
+            let page = store.get_games_for_user(*PLAYER1, &query).await.unwrap();
+
+            // assert
+            assert_eq!(page.games.len(), expected, "{:?}", status);
+        }
+    }
+
+    fn lobby() -> Lobby {
+        let mut lobby = Lobby::new(*PLAYER1, "a".to_string());
+        lobby.invite(*PLAYER1, *PLAYER2, "b".to_string()).unwrap();
+        lobby
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_read_lobby() {
+        // arrange
+        let store = setup_store().await;
+        let lobby = lobby();
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+
+        // act
+        let (view, version) = store.read_lobby(lobby_id).await.unwrap().unwrap();
+
+        // assert
+        assert_eq!(view.lobby, lobby);
+        assert_eq!(view.game_id, None);
+        assert_eq!(version, 0);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn starting_lobby_persists_game() {
+        // arrange
+        let store = setup_store().await;
+        let mut lobby = lobby();
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+        lobby.respond(*PLAYER2, true).unwrap();
+        let game = lobby.take_game().unwrap();
+
+        // act
+        let game_id = store.update_lobby(lobby_id, &lobby, 0, Some(&game)).await;
+
+        // assert
+        let game_id = game_id.unwrap().unwrap();
+        let (view, _) = store.read_lobby(lobby_id).await.unwrap().unwrap();
+        assert_eq!(view.game_id, Some(game_id));
+        assert_eq!(store.read_game(game_id).await.unwrap(), Some((game, 0)));
+        assert!(store
+            .get_lobbies_for_user(*PLAYER2)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn cannot_update_lobby_if_expected_version_does_not_match() {
+        // arrange
+        let store = setup_store().await;
+        let lobby = lobby();
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+        store.update_lobby(lobby_id, &lobby, 0, None).await.unwrap();
+
+        // act
+        let error = store
+            .update_lobby(lobby_id, &lobby, 0, None)
+            .await
+            .unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameVersionConflict));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_get_lobbies_for_invited_user() {
+        // arrange
+        let store = setup_store().await;
+        let mut lobby = lobby();
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+        lobby.invite(*PLAYER1, *PLAYER3, "c".to_string()).unwrap();
+        lobby.remove(*PLAYER1, *PLAYER2).unwrap();
+        store.update_lobby(lobby_id, &lobby, 0, None).await.unwrap();
+
+        // act
+        let lobbies = store.get_lobbies_for_user(*PLAYER3).await.unwrap();
+
+        // assert
+        assert_eq!(lobbies.len(), 1);
+        assert_eq!(lobbies[0].id, lobby_id);
+        assert!(store
+            .get_lobbies_for_user(*PLAYER2)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_find_lobby_by_code() {
+        // arrange
+        let store = setup_store().await;
+        store.persist_lobby(&lobby()).await.unwrap();
+        let lobby = Lobby::open(*PLAYER1, "a".to_string(), 3).unwrap();
+        let lobby_id = store.persist_lobby(&lobby).await.unwrap();
+
+        // act
+        let found = store
+            .find_lobby_by_code(lobby.code().unwrap())
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(found, Some(lobby_id));
+        assert_eq!(store.find_lobby_by_code("NOPE").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_get_user_name() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+
+        // act
+        let name = store.get_user_name(user_id).await.unwrap();
+
+        // assert
+        assert_eq!(name.as_deref(), Some("d"));
+        assert_eq!(store.get_user_name(UserId(42)).await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn cannot_persist_second_rematch() {
+        // arrange
+        let store = setup_store().await;
+        let game_id = store.persist_game(&game()).await.unwrap();
+        let lobby_id = store.persist_rematch(game_id, &lobby()).await.unwrap();
+
+        // act
+        let error = store.persist_rematch(game_id, &lobby()).await.unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameVersionConflict));
+        assert_eq!(store.find_rematch(game_id).await.unwrap(), Some(lobby_id));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_get_recent_chat_messages() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+        let game_id = store.persist_game(&game()).await.unwrap();
+        let other_game_id = store.persist_game(&game()).await.unwrap();
+
+        for text in &["1", "2", "3"] {
+            store
+                .persist_chat_message(game_id, user_id, text)
+                .await
+                .unwrap();
+        }
+
+        store
+            .persist_chat_message(other_game_id, user_id, "4")
+            .await
+            .unwrap();
+
+        // act
+        let messages = store.get_chat_messages(game_id, 2).await.unwrap();
+
+        // assert
+        let texts: Vec<_> = messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, vec!["2", "3"]);
+        assert_eq!(messages[0].name, "d");
+        assert_eq!(messages[0].user_id, user_id);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn cannot_register_taken_name() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.insert_user("d", "hash").await.unwrap();
+
+        // act
+        let taken = store.insert_user("d", "other").await.unwrap();
+
+        // assert
+        assert!(user_id.is_some());
+        assert_eq!(taken, None);
+        let credentials = store.get_credentials("d").await.unwrap();
+        assert_eq!(
+            credentials,
+            Some((user_id.unwrap(), Some("hash".to_string())))
+        );
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_only_claim_account_once() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+
+        // act
+        let claimed = store.set_password_hash(user_id, "hash").await.unwrap();
+        let claimed_again = store.set_password_hash(user_id, "other").await.unwrap();
+
+        // assert
+        assert!(claimed);
+        assert!(!claimed_again);
+        let credentials = store.get_credentials("d").await.unwrap();
+        assert_eq!(credentials, Some((user_id, Some("hash".to_string()))));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn refresh_token_can_only_be_taken_once() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+        let expires = Utc::now() + Duration::days(1);
+        store
+            .persist_refresh_token("hash", user_id, expires)
+            .await
+            .unwrap();
+
+        // act
+        let taken = store.take_refresh_token("hash").await.unwrap();
+
+        // assert
+        assert_eq!(taken, Some((user_id, "d".to_string())));
+        assert_eq!(store.take_refresh_token("hash").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn cannot_take_expired_refresh_token() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+        let expires = Utc::now() - Duration::seconds(1);
+        store
+            .persist_refresh_token("hash", user_id, expires)
+            .await
+            .unwrap();
+
+        // act
+        let taken = store.take_refresh_token("hash").await.unwrap();
+
+        // assert
+        assert_eq!(taken, None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn users_are_players_until_given_another_role() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+        let before = store.get_user_role(user_id).await.unwrap();
+
+        // act
+        let updated = store.set_user_role(user_id, Role::Admin).await.unwrap();
+
+        // assert
+        assert_eq!(before, Some(Role::Player));
+        assert!(updated);
+        assert_eq!(
+            store.get_user_role(user_id).await.unwrap(),
+            Some(Role::Admin)
+        );
+        assert_eq!(store.get_user_role(UserId(42)).await.unwrap(), None);
+        assert!(!store.set_user_role(UserId(42), Role::Admin).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_set_and_clear_user_locale() {
+        // arrange
+        let store = setup_store().await;
+        let user_id = store.get_or_insert_user_id("d").await.unwrap();
+        let before = store.get_user_locale(user_id).await.unwrap();
+
+        // act
+        let updated = store
+            .set_user_locale(user_id, Some(Locale::Da))
+            .await
+            .unwrap();
+        let after = store.get_user_locale(user_id).await.unwrap();
+        store.set_user_locale(user_id, None).await.unwrap();
+
+        // assert
+        assert_eq!(before, None);
+        assert!(updated);
+        assert_eq!(after, Some(Locale::Da));
+        assert_eq!(store.get_user_locale(user_id).await.unwrap(), None);
+        assert!(!store.set_user_locale(UserId(42), None).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_create_bot_with_api_token() {
+        // arrange
+        let store = setup_store().await;
+        let owner_id = store.get_or_insert_user_id("d").await.unwrap();
+
+        // act
+        let bot_id = store.insert_bot("bot", owner_id).await.unwrap().unwrap();
+        let token = store
+            .persist_api_token(bot_id, owner_id, "hash")
+            .await
+            .unwrap();
+
+        // assert
+        let token = token.unwrap();
+        assert_eq!(store.insert_bot("bot", owner_id).await.unwrap(), None);
+        assert_eq!(
+            store.get_bots(owner_id).await.unwrap(),
+            vec![Bot {
+                id: bot_id,
+                name: "bot".to_string(),
+                tokens: vec![token],
+            }]
+        );
+        assert_eq!(
+            store.get_api_token_user("hash").await.unwrap(),
+            Some((bot_id, "bot".to_string(), Role::Player))
+        );
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn only_owner_can_manage_api_tokens() {
+        // arrange
+        let store = setup_store().await;
+        let owner_id = store.get_or_insert_user_id("d").await.unwrap();
+        let other_id = store.get_or_insert_user_id("e").await.unwrap();
+        let bot_id = store.insert_bot("bot", owner_id).await.unwrap().unwrap();
+        let token = store
+            .persist_api_token(bot_id, owner_id, "hash")
+            .await
+            .unwrap();
+        let token_id = token.unwrap().id;
+
+        // act
+        let created = store
+            .persist_api_token(bot_id, other_id, "other")
+            .await
+            .unwrap();
+        let deleted_by_other = store
+            .delete_api_token(token_id, bot_id, other_id)
+            .await
+            .unwrap();
+        let deleted = store
+            .delete_api_token(token_id, bot_id, owner_id)
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(created, None);
+        assert!(!deleted_by_other);
+        assert!(deleted);
+        assert_eq!(store.get_api_token_user("hash").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    #[ignore]
+    async fn can_get_bots_in_game() {
+        // arrange
+        let store = setup_store().await;
+        let owner_id = store.get_or_insert_user_id("d").await.unwrap();
+        let bot_id = store.insert_bot("bot", owner_id).await.unwrap().unwrap();
+        let user_ids = LinkedHashSet::from_iter(vec![owner_id, bot_id]);
+        let game_id = store
+            .persist_game(&Game::try_from(user_ids).unwrap())
+            .await
+            .unwrap();
+
+        // act
+        let bot_ids = store.get_bot_ids(game_id).await.unwrap();
+
+        // assert
+        assert_eq!(bot_ids, vec![bot_id]);
+    }
+}
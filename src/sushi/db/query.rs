@@ -0,0 +1,226 @@
+use super::GameId;
+use super::GameListItem;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Unfinished games that have not been played for this many days count as abandoned.
+const ABANDONED_AFTER_DAYS: i64 = 7;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum GameStatus {
+    Active,
+    Finished,
+    Abandoned,
+}
+
+impl GameStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GameStatus::Active => "active",
+            GameStatus::Finished => "finished",
+            GameStatus::Abandoned => "abandoned",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum GameSort {
+    /// Games where the user still has to select cards first, then most recently updated.
+    #[default]
+    YourMove,
+    Updated,
+    Created,
+}
+
+impl GameSort {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GameSort::YourMove => "yourMove",
+            GameSort::Updated => "updated",
+            GameSort::Created => "created",
+        }
+    }
+}
+
+/// Filters and a page position for listing a user's games. Games are always returned
+/// in a total order, newest first with the game id as tie-breaker, so paging with
+/// `after` never skips or repeats a game that did not change in the meantime.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GameQuery {
+    pub status: Option<GameStatus>,
+    pub opponent: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub sort: GameSort,
+    pub after: Option<Cursor>,
+    pub limit: Option<usize>,
+}
+
+impl GameQuery {
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    pub fn abandoned_before(&self) -> DateTime<Utc> {
+        Utc::now() - Duration::days(ABANDONED_AFTER_DAYS)
+    }
+}
+
+/// The position of a game in the ordering of a `GameQuery`. Clients should treat it as
+/// an opaque string.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cursor {
+    pub priority: i32,
+    pub time: DateTime<Utc>,
+    pub id: GameId,
+}
+
+impl Cursor {
+    pub fn of(item: &GameListItem, sort: GameSort) -> Self {
+        let (priority, time) = match sort {
+            GameSort::YourMove => (item.your_move as i32, item.updated),
+            GameSort::Updated => (0, item.updated),
+            GameSort::Created => (0, item.created),
+        };
+
+        Self {
+            priority,
+            time,
+            id: item.id,
+        }
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        write!(
+            f,
+            "{}.{}.{}",
+            self.priority,
+            self.time.timestamp() * 1_000_000 + i64::from(self.time.timestamp_subsec_micros()),
+            self.id
+        )
+    }
+}
+
+/// Cursors are sent as strings, so their schema is that of a string.
+impl JsonSchema for Cursor {
+    fn schema_name() -> String {
+        "Cursor".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> String {
+        cursor.to_string()
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let mut parts = s.splitn(3, '.').map(str::parse::<i64>);
+        let mut next = || parts.next().and_then(|p| p.ok()).ok_or("invalid cursor");
+        let priority = next()?;
+        let micros = next()?;
+        let id = next()?;
+
+        let secs = micros.div_euclid(1_000_000);
+        let nanos = micros.rem_euclid(1_000_000) as u32 * 1_000;
+
+        Ok(Self {
+            priority: i32::try_from(priority).map_err(|_| "invalid cursor")?,
+            time: Utc
+                .timestamp_opt(secs, nanos)
+                .single()
+                .ok_or("invalid cursor")?,
+            id: GameId(id),
+        })
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct GamePage {
+    pub games: Vec<GameListItem>,
+    pub next: Option<Cursor>,
+}
+
+impl GamePage {
+    /// Builds a page from up to `query.limit() + 1` games. The extra game, if any, is
+    /// only used to tell whether there is a next page.
+    pub fn new(mut games: Vec<GameListItem>, query: &GameQuery) -> Self {
+        let limit = query.limit();
+        let mut next = None;
+
+        if games.len() > limit {
+            games.truncate(limit);
+            next = games.last().map(|g| Cursor::of(g, query.sort));
+        }
+
+        Self { games, next }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_round_trip_cursor() {
+        // arrange
+        let cursor = Cursor {
+            priority: 1,
+            time: Utc.ymd(2020, 9, 1).and_hms_micro(12, 30, 15, 123_456),
+            id: GameId(42),
+        };
+
+        // act
+        let actual = Cursor::try_from(cursor.to_string());
+
+        // assert
+        assert_eq!(actual, Ok(cursor));
+    }
+
+    #[test]
+    fn cannot_parse_malformed_cursor() {
+        assert!(Cursor::try_from("1.abc".to_string()).is_err());
+    }
+
+    #[test]
+    fn cannot_parse_out_of_range_cursor() {
+        assert!(Cursor::try_from("0.9223372036854775807.1".to_string()).is_err());
+        assert!(Cursor::try_from("9223372036854775807.0.1".to_string()).is_err());
+    }
+
+    #[test]
+    fn limit_is_clamped() {
+        // arrange
+        let query = GameQuery {
+            limit: Some(10_000),
+            ..Default::default()
+        };
+
+        // act
+        let limit = query.limit();
+
+        // assert
+        assert_eq!(limit, MAX_PAGE_SIZE);
+    }
+}
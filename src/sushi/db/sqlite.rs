@@ -0,0 +1,2099 @@
+use super::*;
+use chrono::{DateTime, Utc};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{
+    named_params,
+    types::{FromSql, FromSqlError, ToSql, ToSqlOutput, ValueRef},
+    ErrorCode, OptionalExtension, Result as RusqliteResult, Row, Transaction, NO_PARAMS,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use tokio::task::spawn_blocking;
+
+type FromSqlResult<T> = std::result::Result<T, FromSqlError>;
+type Connection = PooledConnection<SqliteConnectionManager>;
+
+#[derive(Clone)]
+pub struct SqliteStore {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl SqliteStore {
+    pub fn new<S: ToString>(path: S) -> Result<Self> {
+        let manager = SqliteConnectionManager::file(path.to_string()).with_init(|connection| {
+            connection.pragma_update(None, "journal_mode", &"WAL")?;
+            Ok(())
+        });
+
+        let pool = Pool::builder().build(manager)?;
+
+        Ok(Self { pool })
+    }
+
+    /// Runs `f` with a pooled connection on the blocking thread pool, so slow disk access
+    /// does not stall the async executor.
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+
+        spawn_blocking(move || {
+            let mut connection = pool.get()?;
+            f(&mut connection)
+        })
+        .await?
+    }
+
+    async fn insert_lobby(&self, lobby: &Lobby, rematch_of: Option<GameId>) -> Result<LobbyId> {
+        static SQL: &str = "
+            INSERT INTO lobbies (data, code, rematch_of)
+            VALUES (:data, :code, :rematch_of)";
+
+        let json = serde_json::to_value(lobby).expect("can serialize lobby to json");
+        let code = lobby.code().map(str::to_string);
+        let user_ids = lobby.user_ids();
+
+        self.run(move |connection| {
+            let tx = connection.transaction()?;
+
+            tx.prepare_cached(SQL)?.execute_named(named_params! {
+                ":data": json,
+                ":code": code,
+                ":rematch_of": rematch_of,
+            })?;
+
+            let lobby_id = LobbyId(tx.last_insert_rowid());
+            seat_lobby_users(&tx, lobby_id, &user_ids)?;
+            tx.commit()?;
+
+            Ok(lobby_id)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl GameStore for SqliteStore {
+    async fn migrate(&self) -> Result<()> {
+        self.run(|connection| migrate(connection, migrations::SQLITE))
+            .await
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<&'static Migration>> {
+        self.run(|connection| {
+            let (user_version, checksums) = applied_migrations(connection)?;
+            migrations::pending(migrations::SQLITE, user_version, &checksums)
+        })
+        .await
+    }
+
+    async fn get_or_insert_user_id(&self, user_name: &str) -> Result<UserId> {
+        static GET_SQL: &str = "
+            SELECT id
+            FROM users
+            WHERE name = :name";
+
+        static INSERT_SQL: &str = "
+            INSERT OR IGNORE INTO users (name)
+            VALUES (:name)";
+
+        let user_name = user_name.to_string();
+
+        self.run(move |connection| {
+            let params = named_params! { ":name": user_name };
+            let value = connection
+                .prepare_cached(GET_SQL)?
+                .query_row_named(params, |row| row.get(0))
+                .optional()?;
+
+            let user_id = match value {
+                Some(user_id) => user_id,
+                None => {
+                    info!("Inserting user {}", user_name);
+                    let params = named_params! { ":name": user_name };
+                    connection
+                        .prepare_cached(INSERT_SQL)?
+                        .execute_named(params)?;
+                    UserId(connection.last_insert_rowid())
+                }
+            };
+
+            Ok(user_id)
+        })
+        .await
+    }
+
+    async fn insert_user(&self, user_name: &str, password_hash: &str) -> Result<Option<UserId>> {
+        static SQL: &str = "
+            INSERT OR IGNORE INTO users (name, password_hash)
+            VALUES (:name, :password_hash)";
+
+        let user_name = user_name.to_string();
+        let password_hash = password_hash.to_string();
+
+        self.run(move |connection| {
+            let inserted = connection
+                .prepare_cached(SQL)?
+                .execute_named(named_params! {
+                    ":name": user_name,
+                    ":password_hash": password_hash,
+                })?;
+
+            if inserted == 0 {
+                return Ok(None);
+            }
+
+            info!("Registered user {}", user_name);
+            Ok(Some(UserId(connection.last_insert_rowid())))
+        })
+        .await
+    }
+
+    async fn get_credentials(&self, user_name: &str) -> Result<Option<(UserId, Option<String>)>> {
+        static SQL: &str = "
+            SELECT id, password_hash
+            FROM users
+            WHERE name = :name";
+
+        let user_name = user_name.to_string();
+
+        self.run(move |connection| {
+            let params = named_params! { ":name": user_name };
+            let credentials = connection
+                .prepare_cached(SQL)?
+                .query_row_named(params, |row| Ok((row.get(0)?, row.get(1)?)))
+                .optional()?;
+
+            Ok(credentials)
+        })
+        .await
+    }
+
+    async fn set_password_hash(&self, user_id: UserId, password_hash: &str) -> Result<bool> {
+        static SQL: &str = "
+            UPDATE users
+            SET password_hash = :password_hash
+            WHERE id = :id
+            AND password_hash IS NULL";
+
+        let password_hash = password_hash.to_string();
+
+        self.run(move |connection| {
+            let updated = connection
+                .prepare_cached(SQL)?
+                .execute_named(named_params! {
+                    ":id": user_id,
+                    ":password_hash": password_hash,
+                })?;
+
+            Ok(updated == 1)
+        })
+        .await
+    }
+
+    async fn get_user_id(&self, user_name: &str) -> Result<Option<UserId>> {
+        static SQL: &str = "
+            SELECT id
+            FROM users
+            WHERE name = :name";
+
+        let user_name = user_name.to_string();
+
+        self.run(move |connection| {
+            let params = named_params! { ":name": user_name };
+            let user_id = connection
+                .prepare_cached(SQL)?
+                .query_row_named(params, |row| row.get(0))
+                .optional()?;
+
+            Ok(user_id)
+        })
+        .await
+    }
+
+    async fn get_user_role(&self, user_id: UserId) -> Result<Option<Role>> {
+        static SQL: &str = "
+            SELECT role
+            FROM users
+            WHERE id = :id";
+
+        self.run(move |connection| {
+            let role: Option<String> = connection
+                .prepare_cached(SQL)?
+                .query_row_named(named_params! { ":id": user_id }, |row| row.get(0))
+                .optional()?;
+
+            Ok(role.map(|role| role.parse().unwrap_or_default()))
+        })
+        .await
+    }
+
+    async fn set_user_role(&self, user_id: UserId, role: Role) -> Result<bool> {
+        static SQL: &str = "
+            UPDATE users
+            SET role = :role
+            WHERE id = :id";
+
+        self.run(move |connection| {
+            let updated = connection
+                .prepare_cached(SQL)?
+                .execute_named(named_params! {
+                    ":id": user_id,
+                    ":role": role.to_string(),
+                })?;
+
+            Ok(updated == 1)
+        })
+        .await
+    }
+
+    async fn get_user_locale(&self, user_id: UserId) -> Result<Option<Locale>> {
+        static SQL: &str = "
+            SELECT locale
+            FROM users
+            WHERE id = :id";
+
+        self.run(move |connection| {
+            let locale: Option<Option<String>> = connection
+                .prepare_cached(SQL)?
+                .query_row_named(named_params! { ":id": user_id }, |row| row.get(0))
+                .optional()?;
+
+            Ok(locale.flatten().and_then(|locale| locale.parse().ok()))
+        })
+        .await
+    }
+
+    async fn set_user_locale(&self, user_id: UserId, locale: Option<Locale>) -> Result<bool> {
+        static SQL: &str = "
+            UPDATE users
+            SET locale = :locale
+            WHERE id = :id";
+
+        self.run(move |connection| {
+            let updated = connection
+                .prepare_cached(SQL)?
+                .execute_named(named_params! {
+                    ":id": user_id,
+                    ":locale": locale.map(|locale| locale.to_string()),
+                })?;
+
+            Ok(updated == 1)
+        })
+        .await
+    }
+
+    async fn insert_bot(&self, name: &str, owner_id: UserId) -> Result<Option<UserId>> {
+        static SQL: &str = "
+            INSERT OR IGNORE INTO users (name, bot_owner_id)
+            VALUES (:name, :owner_id)";
+
+        let name = name.to_string();
+
+        self.run(move |connection| {
+            let inserted = connection
+                .prepare_cached(SQL)?
+                .execute_named(named_params! { ":name": name, ":owner_id": owner_id })?;
+
+            if inserted == 0 {
+                return Ok(None);
+            }
+
+            info!("User {} created bot {}", owner_id, name);
+            Ok(Some(UserId(connection.last_insert_rowid())))
+        })
+        .await
+    }
+
+    async fn get_bots(&self, owner_id: UserId) -> Result<Vec<Bot>> {
+        static SQL: &str = "
+            SELECT u.id, u.name, t.id, t.created
+            FROM users u
+            LEFT JOIN api_tokens t ON t.user_id = u.id
+            WHERE u.bot_owner_id = :owner_id
+            ORDER BY u.id, t.id";
+
+        self.run(move |connection| {
+            let mut statement = connection.prepare_cached(SQL)?;
+            let mut rows = statement.query_named(named_params! { ":owner_id": owner_id })?;
+            let mut bots: Vec<Bot> = vec![];
+
+            while let Some(row) = rows.next()? {
+                let id: UserId = row.get(0)?;
+
+                if bots.last().is_none_or(|bot| bot.id != id) {
+                    bots.push(Bot {
+                        id,
+                        name: row.get(1)?,
+                        tokens: vec![],
+                    });
+                }
+
+                if let Some(token_id) = row.get(2)? {
+                    let bot = bots.last_mut().expect("bot was just pushed");
+
+                    bot.tokens.push(ApiToken {
+                        id: token_id,
+                        created: row.get(3)?,
+                    });
+                }
+            }
+
+            Ok(bots)
+        })
+        .await
+    }
+
+    async fn get_bot_ids(&self, game_id: GameId) -> Result<Vec<UserId>> {
+        static SQL: &str = "
+            SELECT u.id
+            FROM games_users gu
+            INNER JOIN users u ON gu.user_id = u.id
+            WHERE gu.game_id = :game_id
+            AND u.bot_owner_id IS NOT NULL";
+
+        self.run(move |connection| {
+            let mut statement = connection.prepare_cached(SQL)?;
+            let rows = statement
+                .query_map_named(named_params! { ":game_id": game_id }, |row| row.get(0))?;
+
+            let mut user_ids = vec![];
+
+            for row in rows {
+                user_ids.push(row?);
+            }
+
+            Ok(user_ids)
+        })
+        .await
+    }
+
+    async fn persist_api_token(
+        &self,
+        bot_id: UserId,
+        owner_id: UserId,
+        token_hash: &str,
+    ) -> Result<Option<ApiToken>> {
+        static SQL: &str = "
+            INSERT INTO api_tokens (token_hash, user_id)
+            SELECT :token_hash, id
+            FROM users
+            WHERE id = :bot_id
+            AND bot_owner_id = :owner_id";
+
+        static SELECT_SQL: &str = "
+            SELECT id, created
+            FROM api_tokens
+            WHERE id = :id";
+
+        let token_hash = token_hash.to_string();
+
+        self.run(move |connection| {
+            let inserted = connection
+                .prepare_cached(SQL)?
+                .execute_named(named_params! {
+                    ":token_hash": token_hash,
+                    ":bot_id": bot_id,
+                    ":owner_id": owner_id,
+                })?;
+
+            if inserted == 0 {
+                return Ok(None);
+            }
+
+            let params = named_params! { ":id": connection.last_insert_rowid() };
+            let token = connection
+                .prepare_cached(SELECT_SQL)?
+                .query_row_named(params, |row| {
+                    Ok(ApiToken {
+                        id: row.get(0)?,
+                        created: row.get(1)?,
+                    })
+                })?;
+
+            Ok(Some(token))
+        })
+        .await
+    }
+
+    async fn delete_api_token(
+        &self,
+        token_id: i64,
+        bot_id: UserId,
+        owner_id: UserId,
+    ) -> Result<bool> {
+        static SQL: &str = "
+            DELETE FROM api_tokens
+            WHERE id = :id
+            AND user_id IN (
+                SELECT id
+                FROM users
+                WHERE id = :bot_id
+                AND bot_owner_id = :owner_id
+            )";
+
+        self.run(move |connection| {
+            let deleted = connection
+                .prepare_cached(SQL)?
+                .execute_named(named_params! {
+                    ":id": token_id,
+                    ":bot_id": bot_id,
+                    ":owner_id": owner_id,
+                })?;
+
+            Ok(deleted == 1)
+        })
+        .await
+    }
+
+    async fn get_api_token_user(&self, token_hash: &str) -> Result<Option<(UserId, String, Role)>> {
+        static SQL: &str = "
+            SELECT u.id, u.name, u.role
+            FROM api_tokens t
+            INNER JOIN users u ON t.user_id = u.id
+            WHERE t.token_hash = :token_hash";
+
+        let token_hash = token_hash.to_string();
+
+        self.run(move |connection| {
+            let params = named_params! { ":token_hash": token_hash };
+            let user = connection
+                .prepare_cached(SQL)?
+                .query_row_named(params, |row| {
+                    let role: String = row.get(2)?;
+                    Ok((row.get(0)?, row.get(1)?, role.parse().unwrap_or_default()))
+                })
+                .optional()?;
+
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn persist_refresh_token(
+        &self,
+        token_hash: &str,
+        user_id: UserId,
+        expires: DateTime<Utc>,
+    ) -> Result<()> {
+        static SQL: &str = "
+            INSERT INTO refresh_tokens (token_hash, user_id, expires)
+            VALUES (:token_hash, :user_id, :expires)";
+
+        let token_hash = token_hash.to_string();
+
+        self.run(move |connection| {
+            connection
+                .prepare_cached(SQL)?
+                .execute_named(named_params! {
+                    ":token_hash": token_hash,
+                    ":user_id": user_id,
+                    ":expires": sqlite_time(expires),
+                })?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn take_refresh_token(&self, token_hash: &str) -> Result<Option<(UserId, String)>> {
+        static SQL: &str = "
+            SELECT t.user_id, u.name, t.expires > :now
+            FROM refresh_tokens t
+            INNER JOIN users u ON t.user_id = u.id
+            WHERE t.token_hash = :token_hash";
+
+        static DELETE_SQL: &str = "
+            DELETE FROM refresh_tokens
+            WHERE token_hash = :token_hash";
+
+        let token_hash = token_hash.to_string();
+        let now = sqlite_time(Utc::now());
+
+        self.run(move |connection| {
+            let tx = connection.transaction()?;
+
+            let params = named_params! { ":token_hash": token_hash, ":now": now };
+            let row: Option<(UserId, String, bool)> = tx
+                .prepare_cached(SQL)?
+                .query_row_named(params, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .optional()?;
+
+            tx.prepare_cached(DELETE_SQL)?
+                .execute_named(named_params! { ":token_hash": token_hash })?;
+
+            tx.commit()?;
+
+            Ok(row
+                .filter(|(_, _, valid)| *valid)
+                .map(|(user_id, name, _)| (user_id, name)))
+        })
+        .await
+    }
+
+    async fn get_user_name(&self, user_id: UserId) -> Result<Option<String>> {
+        static SQL: &str = "
+            SELECT name
+            FROM users
+            WHERE id = :id";
+
+        self.run(move |connection| {
+            let params = named_params! { ":id": user_id };
+            let user_name = connection
+                .prepare_cached(SQL)?
+                .query_row_named(params, |row| row.get(0))
+                .optional()?;
+
+            Ok(user_name)
+        })
+        .await
+    }
+
+    async fn get_games_for_user(&self, user_id: UserId, query: &GameQuery) -> Result<GamePage> {
+        static SQL: &str = "
+            SELECT *
+            FROM (
+                SELECT g.id
+                     , g.players
+                     , g.round
+                     , g.winner_id IS NOT NULL AS game_over
+                     , w.name AS winner
+                     , g.winner_id IS NULL AND NOT gu.ready AS your_move
+                     , CASE WHEN g.winner_id IS NULL THEN (
+                           SELECT COUNT(*)
+                           FROM games_users p
+                           WHERE p.game_id = g.id
+                           AND NOT p.ready
+                       ) ELSE 0 END AS num_pending
+                     , g.created
+                     , g.updated
+                     , CASE WHEN :sort = 'yourMove' AND g.winner_id IS NULL AND NOT gu.ready
+                            THEN 1 ELSE 0 END AS priority
+                     , CASE WHEN :sort = 'created' THEN g.created ELSE g.updated END AS sort_time
+                FROM games g
+                INNER JOIN games_users gu ON gu.game_id = g.id
+                LEFT JOIN users w ON w.id = g.winner_id
+                WHERE gu.user_id = :user_id
+                AND ( :status IS NULL
+                   OR (:status = 'finished' AND g.winner_id IS NOT NULL)
+                   OR (:status = 'active' AND g.winner_id IS NULL AND g.updated >= :abandoned_before)
+                   OR (:status = 'abandoned' AND g.winner_id IS NULL AND g.updated < :abandoned_before)
+                    )
+                AND ( :opponent IS NULL
+                   OR EXISTS (
+                          SELECT 1
+                          FROM games_users o
+                          INNER JOIN users u ON u.id = o.user_id
+                          WHERE o.game_id = g.id
+                          AND o.user_id <> :user_id
+                          AND u.name = :opponent
+                      )
+                    )
+                AND (:from IS NULL OR g.updated >= :from)
+                AND (:to IS NULL OR g.updated <= :to)
+            )
+            WHERE :after_id IS NULL
+            OR (priority, sort_time, id) < (:after_priority, :after_time, :after_id)
+            ORDER BY priority DESC, sort_time DESC, id DESC
+            LIMIT :limit";
+
+        let status = query.status.map(|s| s.as_str());
+        let opponent = query.opponent.clone();
+        let abandoned_before = sqlite_time(query.abandoned_before());
+        let from = query.from.map(sqlite_time);
+        let to = query.to.map(sqlite_time);
+        let sort = query.sort.as_str();
+        let after_priority = query.after.map(|c| c.priority);
+        let after_time = query.after.map(|c| sqlite_time(c.time));
+        let after_id = query.after.map(|c| c.id);
+        let limit = query.limit() as i64 + 1;
+
+        let games = self
+            .run(move |connection| {
+                let mut statement = connection.prepare_cached(SQL)?;
+                let params = named_params! {
+                    ":user_id": user_id,
+                    ":status": status,
+                    ":abandoned_before": abandoned_before,
+                    ":opponent": opponent,
+                    ":from": from,
+                    ":to": to,
+                    ":sort": sort,
+                    ":after_priority": after_priority,
+                    ":after_time": after_time,
+                    ":after_id": after_id,
+                    ":limit": limit,
+                };
+                let rows = statement.query_map_named(params, |row| {
+                    Ok(GameListItem {
+                        id: row.get(0)?,
+                        players: serde_json::from_value(row.get(1)?).unwrap(),
+                        round: row.get::<_, i64>(2)? as usize,
+                        game_over: row.get(3)?,
+                        winner: row.get(4)?,
+                        your_move: row.get(5)?,
+                        num_pending: row.get::<_, i64>(6)? as usize,
+                        created: row.get(7)?,
+                        updated: row.get(8)?,
+                    })
+                })?;
+
+                let mut games = vec![];
+
+                for row in rows {
+                    games.push(row?);
+                }
+
+                Ok(games)
+            })
+            .await?;
+
+        Ok(GamePage::new(games, query))
+    }
+
+    async fn persist_game(&self, game: &Game) -> Result<GameId> {
+        let game = game.clone();
+
+        self.run(move |connection| {
+            let tx = connection.transaction()?;
+            let game_id = insert_game(&tx, &game)?;
+            tx.commit()?;
+
+            Ok(game_id)
+        })
+        .await
+    }
+
+    async fn update_game(&self, game_id: GameId, game: &Game, expected_version: u8) -> Result<()> {
+        static SQL: &str = "
+            UPDATE games
+            SET data = :data
+              , version = :new_version
+            WHERE id = :id
+            AND version = :expected_version";
+
+        info!("Updating game {} at version {}", game_id, expected_version);
+
+        let json = game_json::serialize(game).to_string();
+        let summary = game.summary();
+        let new_version = expected_version + 1;
+
+        self.run(move |connection| {
+            let tx = connection.transaction()?;
+
+            let updated = tx.prepare_cached(SQL)?.execute_named(named_params! {
+                ":id": game_id,
+                ":data": json,
+                ":expected_version": &expected_version,
+                ":new_version": &new_version,
+            })?;
+
+            if updated == 0 {
+                return Err(Error::GameVersionConflict);
+            }
+
+            mirror_summary(&tx, game_id, &summary)?;
+            tx.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn read_game(&self, game_id: GameId) -> Result<Option<(Game, u8)>> {
+        static SQL: &str = "
+            SELECT data, version
+            FROM games
+            WHERE id = :id";
+
+        info!("Finding game {} for system", game_id);
+
+        self.run(move |connection| {
+            let params = named_params! {
+                ":id": game_id
+            };
+
+            let row = connection
+                .prepare_cached(SQL)?
+                .query_row_named(params, |row| {
+                    let json: Value = row.get(0)?;
+                    let version: u8 = row.get(1)?;
+                    Ok((json, version))
+                })
+                .optional()?;
+
+            match row {
+                None => Ok(None),
+                Some((json, version)) => {
+                    Ok(Some((game_json::deserialize(game_id, json)?, version)))
+                }
+            }
+        })
+        .await
+    }
+
+    async fn persist_chat_message(
+        &self,
+        game_id: GameId,
+        user_id: UserId,
+        text: &str,
+    ) -> Result<ChatMessage> {
+        static SQL: &str = "
+            INSERT INTO chat_messages (game_id, user_id, text)
+            VALUES (:game_id, :user_id, :text)";
+
+        static SELECT_SQL: &str = "
+            SELECT m.user_id, u.name, m.text, m.sent
+            FROM chat_messages m
+            INNER JOIN users u ON m.user_id = u.id
+            WHERE m.id = :id";
+
+        let text = text.to_string();
+
+        self.run(move |connection| {
+            connection
+                .prepare_cached(SQL)?
+                .execute_named(named_params! {
+                    ":game_id": game_id,
+                    ":user_id": user_id,
+                    ":text": text,
+                })?;
+
+            let params = named_params! { ":id": connection.last_insert_rowid() };
+            let message = connection
+                .prepare_cached(SELECT_SQL)?
+                .query_row_named(params, chat_message)?;
+
+            Ok(message)
+        })
+        .await
+    }
+
+    async fn get_chat_messages(&self, game_id: GameId, limit: usize) -> Result<Vec<ChatMessage>> {
+        static SQL: &str = "
+            SELECT *
+            FROM (
+                SELECT m.user_id, u.name, m.text, m.sent, m.id
+                FROM chat_messages m
+                INNER JOIN users u ON m.user_id = u.id
+                WHERE m.game_id = :game_id
+                ORDER BY m.id DESC
+                LIMIT :limit
+            )
+            ORDER BY id";
+
+        let limit = limit as i64;
+
+        self.run(move |connection| {
+            let mut statement = connection.prepare_cached(SQL)?;
+            let params = named_params! { ":game_id": game_id, ":limit": limit };
+            let rows = statement.query_map_named(params, chat_message)?;
+
+            let mut messages = vec![];
+
+            for row in rows {
+                messages.push(row?);
+            }
+
+            Ok(messages)
+        })
+        .await
+    }
+
+    async fn get_lobbies_for_user(&self, user_id: UserId) -> Result<Vec<LobbyView>> {
+        static SQL: &str = "
+            SELECT l.id, l.data, l.game_id
+            FROM lobbies l
+            INNER JOIN lobbies_users lu ON lu.lobby_id = l.id
+            WHERE lu.user_id = :user_id
+            AND l.game_id IS NULL
+            ORDER BY l.id DESC";
+
+        self.run(move |connection| {
+            let mut statement = connection.prepare_cached(SQL)?;
+            let params = named_params! { ":user_id": user_id };
+            let rows = statement
+                .query_map_named(params, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+
+            let mut lobbies = vec![];
+
+            for row in rows {
+                let (id, json, game_id) = row?;
+
+                lobbies.push(LobbyView {
+                    id,
+                    lobby: deserialize_lobby(id, json)?,
+                    game_id,
+                });
+            }
+
+            Ok(lobbies)
+        })
+        .await
+    }
+
+    async fn persist_lobby(&self, lobby: &Lobby) -> Result<LobbyId> {
+        self.insert_lobby(lobby, None).await
+    }
+
+    async fn update_lobby(
+        &self,
+        lobby_id: LobbyId,
+        lobby: &Lobby,
+        expected_version: u8,
+        game: Option<&Game>,
+    ) -> Result<Option<GameId>> {
+        static SQL: &str = "
+            UPDATE lobbies
+            SET data = :data
+              , version = :new_version
+            WHERE id = :id
+            AND version = :expected_version";
+
+        static GAME_SQL: &str = "
+            UPDATE lobbies
+            SET game_id = :game_id
+            WHERE id = :id";
+
+        info!(
+            "Updating lobby {} at version {}",
+            lobby_id, expected_version
+        );
+
+        let json = serde_json::to_value(lobby).expect("can serialize lobby to json");
+        let user_ids = lobby.user_ids();
+        let game = game.cloned();
+        let new_version = expected_version + 1;
+
+        self.run(move |connection| {
+            let tx = connection.transaction()?;
+
+            let updated = tx.prepare_cached(SQL)?.execute_named(named_params! {
+                ":id": lobby_id,
+                ":data": json,
+                ":expected_version": &expected_version,
+                ":new_version": &new_version,
+            })?;
+
+            if updated == 0 {
+                return Err(Error::GameVersionConflict);
+            }
+
+            seat_lobby_users(&tx, lobby_id, &user_ids)?;
+
+            let game_id = match game {
+                None => None,
+                Some(game) => {
+                    let game_id = insert_game(&tx, &game)?;
+
+                    tx.prepare_cached(GAME_SQL)?.execute_named(named_params! {
+                        ":id": lobby_id,
+                        ":game_id": game_id,
+                    })?;
+
+                    Some(game_id)
+                }
+            };
+
+            tx.commit()?;
+
+            Ok(game_id)
+        })
+        .await
+    }
+
+    async fn read_lobby(&self, lobby_id: LobbyId) -> Result<Option<(LobbyView, u8)>> {
+        static SQL: &str = "
+            SELECT data, version, game_id
+            FROM lobbies
+            WHERE id = :id";
+
+        self.run(move |connection| {
+            let params = named_params! { ":id": lobby_id };
+
+            let row = connection
+                .prepare_cached(SQL)?
+                .query_row_named(params, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .optional()?;
+
+            let (json, version, game_id) = match row {
+                None => return Ok(None),
+                Some(row) => row,
+            };
+
+            let view = LobbyView {
+                id: lobby_id,
+                lobby: deserialize_lobby(lobby_id, json)?,
+                game_id,
+            };
+
+            Ok(Some((view, version)))
+        })
+        .await
+    }
+
+    async fn persist_rematch(&self, game_id: GameId, lobby: &Lobby) -> Result<LobbyId> {
+        match self.insert_lobby(lobby, Some(game_id)).await {
+            Err(Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _)))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(Error::GameVersionConflict)
+            }
+            result => result,
+        }
+    }
+
+    async fn find_rematch(&self, game_id: GameId) -> Result<Option<LobbyId>> {
+        static SQL: &str = "
+            SELECT id
+            FROM lobbies
+            WHERE rematch_of = :game_id";
+
+        self.run(move |connection| {
+            let lobby_id = connection
+                .prepare_cached(SQL)?
+                .query_row_named(named_params! { ":game_id": game_id }, |row| row.get(0))
+                .optional()?;
+
+            Ok(lobby_id)
+        })
+        .await
+    }
+
+    async fn find_lobby_by_code(&self, code: &str) -> Result<Option<LobbyId>> {
+        static SQL: &str = "
+            SELECT id
+            FROM lobbies
+            WHERE code = :code";
+
+        let code = code.to_string();
+
+        self.run(move |connection| {
+            let lobby_id = connection
+                .prepare_cached(SQL)?
+                .query_row_named(named_params! { ":code": code }, |row| row.get(0))
+                .optional()?;
+
+            Ok(lobby_id)
+        })
+        .await
+    }
+}
+
+fn chat_message(row: &Row) -> RusqliteResult<ChatMessage> {
+    Ok(ChatMessage {
+        user_id: row.get(0)?,
+        name: row.get(1)?,
+        text: row.get(2)?,
+        sent: row.get(3)?,
+    })
+}
+
+fn insert_game(tx: &Transaction, game: &Game) -> Result<GameId> {
+    static GAME_SQL: &str = "
+        INSERT INTO games (data)
+        VALUES (:data)";
+
+    static PLAYER_SQL: &str = "
+        INSERT INTO games_users (game_id, user_id)
+        VALUES (:game_id, :user_id)";
+
+    let json = game_json::serialize(game).to_string();
+    let summary = game.summary();
+
+    tx.prepare_cached(GAME_SQL)?.execute_named(named_params! {
+        ":data": json
+    })?;
+
+    let game_id = GameId(tx.last_insert_rowid());
+    let mut statement = tx.prepare_cached(PLAYER_SQL)?;
+
+    for player in &summary.players {
+        statement.execute_named(named_params! {
+            ":game_id": game_id,
+            ":user_id": player.id,
+        })?;
+    }
+
+    mirror_summary(tx, game_id, &summary)?;
+    Ok(game_id)
+}
+
+/// Replaces the lookup rows used to find the lobbies a user is seated in.
+fn seat_lobby_users(tx: &Transaction, lobby_id: LobbyId, user_ids: &[UserId]) -> Result<()> {
+    static DELETE_SQL: &str = "
+        DELETE FROM lobbies_users
+        WHERE lobby_id = :lobby_id";
+
+    static INSERT_SQL: &str = "
+        INSERT INTO lobbies_users (lobby_id, user_id)
+        VALUES (:lobby_id, :user_id)";
+
+    tx.prepare_cached(DELETE_SQL)?
+        .execute_named(named_params! { ":lobby_id": lobby_id })?;
+
+    let mut statement = tx.prepare_cached(INSERT_SQL)?;
+
+    for user_id in user_ids {
+        statement.execute_named(named_params! {
+            ":lobby_id": lobby_id,
+            ":user_id": user_id,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Copies the queryable parts of a game into their own columns and tables.
+fn mirror_summary(tx: &Transaction, game_id: GameId, summary: &GameSummary) -> Result<()> {
+    static GAME_SQL: &str = "
+        UPDATE games
+        SET round = :round
+          , winner_id = :winner_id
+        WHERE id = :id";
+
+    static PLAYER_SQL: &str = "
+        UPDATE games_users
+        SET num_points = :num_points
+          , num_puddings = :num_puddings
+          , ready = :ready
+        WHERE game_id = :game_id
+        AND user_id = :user_id";
+
+    static CARD_SQL: &str = "
+        INSERT OR REPLACE INTO played_cards (game_id, user_id, card, count)
+        VALUES (:game_id, :user_id, :card, :count)";
+
+    tx.prepare_cached(GAME_SQL)?.execute_named(named_params! {
+        ":id": game_id,
+        ":round": summary.round as i64,
+        ":winner_id": summary.winner,
+    })?;
+
+    for player in &summary.players {
+        tx.prepare_cached(PLAYER_SQL)?
+            .execute_named(named_params! {
+                ":game_id": game_id,
+                ":user_id": player.id,
+                ":num_points": player.num_points as i64,
+                ":num_puddings": player.num_puddings as i64,
+                ":ready": player.ready,
+            })?;
+
+        for (card, count) in player.played_card_counts() {
+            tx.prepare_cached(CARD_SQL)?.execute_named(named_params! {
+                ":game_id": game_id,
+                ":user_id": player.id,
+                ":card": card,
+                ":count": count as i64,
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Formats a timestamp like SQLite's `datetime()`, so it compares correctly with the
+/// stored timestamps.
+fn sqlite_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S%.f").to_string()
+}
+
+fn migrate(connection: &mut Connection, migrations: &[Migration]) -> Result<()> {
+    static INSERT_SQL: &str = "
+        INSERT INTO schema_migrations (version, name, checksum)
+        VALUES (:version, :name, :checksum)";
+
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_migrations
+        ( version INTEGER PRIMARY KEY
+        , name TEXT NOT NULL
+        , checksum TEXT NOT NULL
+        , applied DATE NOT NULL DEFAULT (datetime('now'))
+        );",
+    )?;
+
+    let (user_version, checksums) = applied_migrations(connection)?;
+
+    info!("db user_version is {}", user_version);
+
+    let pending = migrations::pending(migrations, user_version, &checksums)?;
+
+    // Record checksums for migrations applied before checksums were tracked
+    for migration in migrations {
+        if migration.version <= user_version && !checksums.contains_key(&migration.version) {
+            connection.execute_named(
+                INSERT_SQL,
+                named_params! {
+                    ":version": migration.version,
+                    ":name": migration.name,
+                    ":checksum": migration.checksum(),
+                },
+            )?;
+        }
+    }
+
+    if pending.is_empty() {
+        info!("no migration performed");
+    }
+
+    for migration in pending {
+        let tx = connection.transaction()?;
+        tx.execute_batch(migration.sql)?;
+        tx.pragma_update(None, "user_version", &migration.version)?;
+        tx.execute_named(
+            INSERT_SQL,
+            named_params! {
+                ":version": migration.version,
+                ":name": migration.name,
+                ":checksum": migration.checksum(),
+            },
+        )?;
+        tx.commit()?;
+
+        info!(
+            "applied migration {} ({})",
+            migration.version, migration.name
+        );
+    }
+
+    Ok(())
+}
+
+fn applied_migrations(connection: &Connection) -> Result<(u32, HashMap<u32, String>)> {
+    let user_version = connection.query_row(
+        "SELECT user_version FROM pragma_user_version;",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    let has_checksums: bool = connection.query_row(
+        "SELECT EXISTS (
+            SELECT * FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'
+        )",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    let mut checksums = HashMap::new();
+
+    if has_checksums {
+        let mut statement =
+            connection.prepare("SELECT version, checksum FROM schema_migrations")?;
+        let rows = statement.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        for row in rows {
+            let (version, checksum) = row?;
+            checksums.insert(version, checksum);
+        }
+    }
+
+    Ok((user_version, checksums))
+}
+
+impl ToSql for GameId {
+    fn to_sql(&self) -> RusqliteResult<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+impl FromSql for GameId {
+    fn column_result(value: ValueRef) -> FromSqlResult<Self> {
+        Ok(Self(i64::column_result(value)?))
+    }
+}
+
+impl ToSql for LobbyId {
+    fn to_sql(&self) -> RusqliteResult<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+impl FromSql for LobbyId {
+    fn column_result(value: ValueRef) -> FromSqlResult<Self> {
+        Ok(Self(i64::column_result(value)?))
+    }
+}
+
+impl ToSql for UserId {
+    fn to_sql(&self) -> RusqliteResult<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+impl FromSql for UserId {
+    fn column_result(value: ValueRef) -> FromSqlResult<Self> {
+        Ok(Self(i64::column_result(value)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::query::GameStatus;
+    use super::*;
+    use chrono::Duration;
+    use linked_hash_set::LinkedHashSet;
+    use rusqlite::params;
+    use serial_test::serial;
+    use std::convert::TryFrom;
+    use std::iter::FromIterator;
+    use std::ops::Deref;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Db {
+        db: SqliteStore,
+        connection: Connection,
+    }
+
+    impl Deref for Db {
+        type Target = SqliteStore;
+
+        fn deref(&self) -> &Self::Target {
+            &self.db
+        }
+    }
+
+    lazy_static! {
+        static ref PLAYER1: UserId = UserId(1);
+        static ref PLAYER2: UserId = UserId(2);
+        static ref PLAYER3: UserId = UserId(3);
+    }
+
+    // Every store gets its own database, since connections from a dropped pool may
+    // still be open when the next test starts.
+    fn in_memory() -> Db {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = format!("file:sushi{}?mode=memory&cache=shared", id);
+        let db = SqliteStore::new(path).unwrap();
+        let connection = db.pool.get().unwrap();
+        Db { db, connection }
+    }
+
+    async fn setup_db() -> Db {
+        let db = in_memory();
+        db.migrate().await.unwrap();
+        db
+    }
+
+    fn count_migrations(db: &Db) -> usize {
+        db.connection
+            .query_row("SELECT COUNT(*) FROM schema_migrations", NO_PARAMS, |row| {
+                row.get::<_, i64>(0)
+            })
+            .unwrap() as usize
+    }
+
+    fn game() -> Game {
+        Game::try_from(LinkedHashSet::from_iter(vec![*PLAYER1, *PLAYER2, *PLAYER3])).unwrap()
+    }
+
+    fn other_game() -> Game {
+        Game::try_from(LinkedHashSet::from_iter(vec![*PLAYER2, *PLAYER3])).unwrap()
+    }
+
+    fn select_first_card(game: &mut Game, user_id: UserId) {
+        let view = game.clone().into(user_id);
+        let card = *view.player.unwrap().hand.keys().next().unwrap();
+        game.select_cards(user_id, vec![card]).unwrap();
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_migrate() {
+        // arrange
+        let db = in_memory();
+
+        // act
+        let result = db.migrate().await;
+
+        // assert
+        assert!(result.is_ok());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_migrate_from_every_version() {
+        for version in 0..=migrations::SQLITE.len() {
+            // arrange
+            let db = in_memory();
+            let mut connection = db.pool.get().unwrap();
+            migrate(&mut connection, &migrations::SQLITE[..version]).unwrap();
+
+            // act
+            let result = db.migrate().await;
+
+            // assert
+            assert!(result.is_ok(), "failed to migrate from version {}", version);
+            assert_eq!(count_migrations(&db), migrations::SQLITE.len());
+        }
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_migrate_database_without_checksums() {
+        // arrange
+        let db = setup_db().await;
+        db.connection
+            .execute_batch("DROP TABLE schema_migrations")
+            .unwrap();
+
+        // act
+        let result = db.migrate().await;
+
+        // assert
+        assert!(result.is_ok());
+        assert_eq!(count_migrations(&db), migrations::SQLITE.len());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn cannot_migrate_if_migration_was_edited() {
+        // arrange
+        let db = setup_db().await;
+        db.connection
+            .execute_batch("UPDATE schema_migrations SET checksum = 'edited' WHERE version = 1")
+            .unwrap();
+
+        // act
+        let error = db.migrate().await.unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::MigrationChanged(1)));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn pending_migrations_does_not_migrate() {
+        // arrange
+        let db = in_memory();
+
+        // act
+        let pending = db.pending_migrations().await.unwrap();
+
+        // assert
+        assert_eq!(pending.len(), migrations::SQLITE.len());
+        assert_eq!(db.pending_migrations().await.unwrap().len(), pending.len());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn get_or_insert_user_id_will_insert() {
+        // arrange
+        let db = setup_db().await;
+        let user_name = "test";
+
+        // act
+        let result = db.get_or_insert_user_id(user_name).await;
+
+        // assert
+        let user_id = result.unwrap();
+
+        let count: u32 = db
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM users WHERE id = ?1",
+                params![user_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(count, 1);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn get_or_insert_user_id_will_get() {
+        // arrange
+        let db = setup_db().await;
+        let user_name = "test";
+        let expected = db.get_or_insert_user_id(user_name).await;
+
+        // act
+        let actual = db.get_or_insert_user_id(user_name).await;
+
+        // assert
+        assert!(expected.is_ok());
+        assert!(actual.is_ok());
+        assert_eq!(actual.unwrap(), expected.unwrap());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_insert_game() {
+        // arrange
+        let db = setup_db().await;
+        let game = game();
+
+        // act
+        let game_id = db.persist_game(&game).await.unwrap();
+
+        // assert
+        let count: u32 = db
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM games WHERE id = ?1",
+                params![game_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(count, 1);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_read_game() {
+        // arrange
+        let db = setup_db().await;
+        let game = game();
+        let game_id = db.persist_game(&game).await.unwrap();
+
+        // act
+        let actual = db.read_game(game_id).await.unwrap();
+
+        // assert
+        assert_eq!(actual, Some((game, 0)));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn cannot_read_unreadable_game() {
+        // arrange
+        let db = setup_db().await;
+        let game_id = db.persist_game(&game()).await.unwrap();
+        db.connection
+            .execute(
+                "UPDATE games SET data = '{}' WHERE id = ?1",
+                params![game_id],
+            )
+            .unwrap();
+
+        // act
+        let error = db.read_game(game_id).await.unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::UnreadableGame(..)));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_get_games_for_user() {
+        // arrange
+        let db = setup_db().await;
+        let num_games = 3;
+        let user_id = *PLAYER1;
+
+        for _ in 0..num_games {
+            db.persist_game(&game()).await.unwrap();
+        }
+
+        db.persist_game(&other_game()).await.unwrap(); // Game without player 1
+
+        // act
+        let games = db
+            .get_games_for_user(user_id, &GameQuery::default())
+            .await
+            .unwrap()
+            .games;
+
+        // assert
+        assert_eq!(games.len(), num_games);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_update_game() {
+        // arrange
+        let db = setup_db().await;
+        let game = game();
+        let game_id = db.persist_game(&game).await.unwrap();
+
+        // act
+        let actual = db.update_game(game_id, &game, 0).await;
+
+        // assert
+        assert!(actual.is_ok());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn update_increments_version() {
+        // arrange
+        let db = setup_db().await;
+        let game = game();
+        let game_id = db.persist_game(&game).await.unwrap();
+        db.update_game(game_id, &game, 0).await.unwrap();
+
+        // act
+        let actual = db.read_game(game_id).await.unwrap();
+
+        // assert
+        assert_eq!(actual, Some((game, 1)));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn cannot_update_if_expected_version_does_not_match() {
+        // arrange
+        let db = setup_db().await;
+        let game = game();
+        let game_id = db.persist_game(&game).await.unwrap();
+        db.update_game(game_id, &game, 0).await.unwrap();
+
+        // act
+        let error = db.update_game(game_id, &game, 0).await.unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameVersionConflict));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn update_mirrors_game_state() {
+        // arrange
+        let db = setup_db().await;
+        let mut game = game();
+        let game_id = db.persist_game(&game).await.unwrap();
+
+        for user_id in game.user_ids() {
+            select_first_card(&mut game, user_id);
+        }
+
+        game.end_turn();
+
+        // act
+        db.update_game(game_id, &game, 0).await.unwrap();
+
+        // assert
+        let num_played: i64 = db
+            .connection
+            .query_row(
+                "SELECT SUM(count) FROM played_cards WHERE game_id = ?1 AND user_id = ?2",
+                params![game_id, *PLAYER1],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        let round: i64 = db
+            .connection
+            .query_row(
+                "SELECT round FROM games WHERE id = ?1",
+                params![game_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(num_played, 1);
+        assert_eq!(round as usize, game.summary().round);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn games_where_it_is_your_move_come_first() {
+        // arrange
+        let db = setup_db().await;
+        let waiting = db.persist_game(&game()).await.unwrap();
+        let moved = db.persist_game(&game()).await.unwrap();
+        let mut game = game();
+        select_first_card(&mut game, *PLAYER1);
+        db.update_game(moved, &game, 0).await.unwrap();
+
+        // act
+        let games = db
+            .get_games_for_user(*PLAYER1, &GameQuery::default())
+            .await
+            .unwrap()
+            .games;
+
+        // assert
+        let ids = games.iter().map(|g| g.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![waiting, moved]);
+        assert!(games[0].your_move);
+        assert_eq!(games[0].num_pending, 3);
+        assert!(!games[1].your_move);
+        assert_eq!(games[1].num_pending, 2);
+        assert!(!games[1].game_over);
+        assert_eq!(games[1].winner, None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_page_through_games() {
+        // arrange
+        let db = setup_db().await;
+        let mut expected = vec![];
+
+        for _ in 0..5 {
+            expected.insert(0, db.persist_game(&game()).await.unwrap());
+        }
+
+        let mut query = GameQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        // act
+        let mut ids = vec![];
+
+        loop {
+            let page = db.get_games_for_user(*PLAYER1, &query).await.unwrap();
+            ids.extend(page.games.iter().map(|g| g.id));
+
+            match page.next {
+                Some(next) => query.after = Some(next),
+                None => break,
+            }
+        }
+
+        // assert
+        assert_eq!(ids, expected);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_filter_games_by_opponent() {
+        // arrange
+        let db = setup_db().await;
+
+        for name in &["a", "b", "c"] {
+            db.get_or_insert_user_id(name).await.unwrap();
+        }
+
+        db.persist_game(&game()).await.unwrap();
+        db.persist_game(&other_game()).await.unwrap();
+
+        let query = GameQuery {
+            opponent: Some("c".to_string()),
+            ..Default::default()
+        };
+
+        // act
+        let page = db.get_games_for_user(*PLAYER2, &query).await.unwrap();
+
+        // assert
+        assert_eq!(page.games.len(), 2);
+        assert!(page.next.is_none());
+
+        let query = GameQuery {
+            opponent: Some("a".to_string()),
+            ..query
+        };
+
+        let page = db.get_games_for_user(*PLAYER2, &query).await.unwrap();
+        assert_eq!(page.games.len(), 1);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_filter_games_by_status() {
+        // arrange
+        let db = setup_db().await;
+        db.persist_game(&game()).await.unwrap();
+
+        let expected = vec![
+            (GameStatus::Active, 1),
+            (GameStatus::Finished, 0),
+            (GameStatus::Abandoned, 0),
+        ];
+
+        for (status, expected) in expected {
+            let query = GameQuery {
+                status: Some(status),
+                ..Default::default()
+            };
+
+            // act
+            let page = db.get_games_for_user(*PLAYER1, &query).await.unwrap();
+
+            // assert
+            assert_eq!(page.games.len(), expected, "{:?}", status);
+        }
+    }
+
+    fn lobby() -> Lobby {
+        let mut lobby = Lobby::new(*PLAYER1, "a".to_string());
+        lobby.invite(*PLAYER1, *PLAYER2, "b".to_string()).unwrap();
+        lobby
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_read_lobby() {
+        // arrange
+        let db = setup_db().await;
+        let lobby = lobby();
+        let lobby_id = db.persist_lobby(&lobby).await.unwrap();
+
+        // act
+        let (view, version) = db.read_lobby(lobby_id).await.unwrap().unwrap();
+
+        // assert
+        assert_eq!(view.lobby, lobby);
+        assert_eq!(view.game_id, None);
+        assert_eq!(version, 0);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn cannot_read_unreadable_lobby() {
+        // arrange
+        let db = setup_db().await;
+        let lobby_id = db.persist_lobby(&lobby()).await.unwrap();
+        db.connection
+            .execute(
+                "UPDATE lobbies SET data = '{}' WHERE id = ?1",
+                params![lobby_id],
+            )
+            .unwrap();
+
+        // act
+        let read_error = db.read_lobby(lobby_id).await.unwrap_err();
+        let list_error = db.get_lobbies_for_user(*PLAYER1).await.unwrap_err();
+
+        // assert
+        assert!(matches!(read_error, Error::UnreadableLobby(..)));
+        assert!(matches!(list_error, Error::UnreadableLobby(..)));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn starting_lobby_persists_game() {
+        // arrange
+        let db = setup_db().await;
+        let mut lobby = lobby();
+        let lobby_id = db.persist_lobby(&lobby).await.unwrap();
+        lobby.respond(*PLAYER2, true).unwrap();
+        let game = lobby.take_game().unwrap();
+
+        // act
+        let game_id = db.update_lobby(lobby_id, &lobby, 0, Some(&game)).await;
+
+        // assert
+        let game_id = game_id.unwrap().unwrap();
+        let (view, _) = db.read_lobby(lobby_id).await.unwrap().unwrap();
+        assert_eq!(view.game_id, Some(game_id));
+        assert_eq!(db.read_game(game_id).await.unwrap(), Some((game, 0)));
+        assert!(db.get_lobbies_for_user(*PLAYER2).await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn cannot_update_lobby_if_expected_version_does_not_match() {
+        // arrange
+        let db = setup_db().await;
+        let lobby = lobby();
+        let lobby_id = db.persist_lobby(&lobby).await.unwrap();
+        db.update_lobby(lobby_id, &lobby, 0, None).await.unwrap();
+
+        // act
+        let error = db
+            .update_lobby(lobby_id, &lobby, 0, None)
+            .await
+            .unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameVersionConflict));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_get_lobbies_for_invited_user() {
+        // arrange
+        let db = setup_db().await;
+        let mut lobby = lobby();
+        let lobby_id = db.persist_lobby(&lobby).await.unwrap();
+        lobby.invite(*PLAYER1, *PLAYER3, "c".to_string()).unwrap();
+        lobby.remove(*PLAYER1, *PLAYER2).unwrap();
+        db.update_lobby(lobby_id, &lobby, 0, None).await.unwrap();
+
+        // act
+        let lobbies = db.get_lobbies_for_user(*PLAYER3).await.unwrap();
+
+        // assert
+        assert_eq!(lobbies.len(), 1);
+        assert_eq!(lobbies[0].id, lobby_id);
+        assert!(db.get_lobbies_for_user(*PLAYER2).await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_find_lobby_by_code() {
+        // arrange
+        let db = setup_db().await;
+        db.persist_lobby(&lobby()).await.unwrap();
+        let lobby = Lobby::open(*PLAYER1, "a".to_string(), 3).unwrap();
+        let lobby_id = db.persist_lobby(&lobby).await.unwrap();
+
+        // act
+        let found = db.find_lobby_by_code(lobby.code().unwrap()).await.unwrap();
+
+        // assert
+        assert_eq!(found, Some(lobby_id));
+        assert_eq!(db.find_lobby_by_code("NOPE").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_get_user_name() {
+        // arrange
+        let db = setup_db().await;
+        let user_id = db.get_or_insert_user_id("d").await.unwrap();
+
+        // act
+        let name = db.get_user_name(user_id).await.unwrap();
+
+        // assert
+        assert_eq!(name.as_deref(), Some("d"));
+        assert_eq!(db.get_user_name(UserId(42)).await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn cannot_persist_second_rematch() {
+        // arrange
+        let db = setup_db().await;
+        let game_id = db.persist_game(&game()).await.unwrap();
+        let lobby_id = db.persist_rematch(game_id, &lobby()).await.unwrap();
+
+        // act
+        let error = db.persist_rematch(game_id, &lobby()).await.unwrap_err();
+
+        // assert
+        assert!(matches!(error, Error::GameVersionConflict));
+        assert_eq!(db.find_rematch(game_id).await.unwrap(), Some(lobby_id));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_get_recent_chat_messages() {
+        // arrange
+        let db = setup_db().await;
+        let user_id = db.get_or_insert_user_id("d").await.unwrap();
+        let game_id = db.persist_game(&game()).await.unwrap();
+        let other_game_id = db.persist_game(&game()).await.unwrap();
+
+        for text in &["1", "2", "3"] {
+            db.persist_chat_message(game_id, user_id, text)
+                .await
+                .unwrap();
+        }
+
+        db.persist_chat_message(other_game_id, user_id, "4")
+            .await
+            .unwrap();
+
+        // act
+        let messages = db.get_chat_messages(game_id, 2).await.unwrap();
+
+        // assert
+        let texts: Vec<_> = messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, vec!["2", "3"]);
+        assert_eq!(messages[0].name, "d");
+        assert_eq!(messages[0].user_id, user_id);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn cannot_register_taken_name() {
+        // arrange
+        let db = setup_db().await;
+        let user_id = db.insert_user("d", "hash").await.unwrap();
+
+        // act
+        let taken = db.insert_user("d", "other").await.unwrap();
+
+        // assert
+        assert!(user_id.is_some());
+        assert_eq!(taken, None);
+        let credentials = db.get_credentials("d").await.unwrap();
+        assert_eq!(
+            credentials,
+            Some((user_id.unwrap(), Some("hash".to_string())))
+        );
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_only_claim_account_once() {
+        // arrange
+        let db = setup_db().await;
+        let user_id = db.get_or_insert_user_id("d").await.unwrap();
+
+        // act
+        let claimed = db.set_password_hash(user_id, "hash").await.unwrap();
+        let claimed_again = db.set_password_hash(user_id, "other").await.unwrap();
+
+        // assert
+        assert!(claimed);
+        assert!(!claimed_again);
+        let credentials = db.get_credentials("d").await.unwrap();
+        assert_eq!(credentials, Some((user_id, Some("hash".to_string()))));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn refresh_token_can_only_be_taken_once() {
+        // arrange
+        let db = setup_db().await;
+        let user_id = db.get_or_insert_user_id("d").await.unwrap();
+        let expires = Utc::now() + Duration::days(1);
+        db.persist_refresh_token("hash", user_id, expires)
+            .await
+            .unwrap();
+
+        // act
+        let taken = db.take_refresh_token("hash").await.unwrap();
+
+        // assert
+        assert_eq!(taken, Some((user_id, "d".to_string())));
+        assert_eq!(db.take_refresh_token("hash").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn cannot_take_expired_refresh_token() {
+        // arrange
+        let db = setup_db().await;
+        let user_id = db.get_or_insert_user_id("d").await.unwrap();
+        let expires = Utc::now() - Duration::seconds(1);
+        db.persist_refresh_token("hash", user_id, expires)
+            .await
+            .unwrap();
+
+        // act
+        let taken = db.take_refresh_token("hash").await.unwrap();
+
+        // assert
+        assert_eq!(taken, None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn users_are_players_until_given_another_role() {
+        // arrange
+        let db = setup_db().await;
+        let user_id = db.get_or_insert_user_id("d").await.unwrap();
+        let before = db.get_user_role(user_id).await.unwrap();
+
+        // act
+        let updated = db.set_user_role(user_id, Role::Admin).await.unwrap();
+
+        // assert
+        assert_eq!(before, Some(Role::Player));
+        assert!(updated);
+        assert_eq!(db.get_user_role(user_id).await.unwrap(), Some(Role::Admin));
+        assert_eq!(db.get_user_role(UserId(42)).await.unwrap(), None);
+        assert!(!db.set_user_role(UserId(42), Role::Admin).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_set_and_clear_user_locale() {
+        // arrange
+        let db = setup_db().await;
+        let user_id = db.get_or_insert_user_id("d").await.unwrap();
+        let before = db.get_user_locale(user_id).await.unwrap();
+
+        // act
+        let updated = db.set_user_locale(user_id, Some(Locale::Da)).await.unwrap();
+        let after = db.get_user_locale(user_id).await.unwrap();
+        db.set_user_locale(user_id, None).await.unwrap();
+
+        // assert
+        assert_eq!(before, None);
+        assert!(updated);
+        assert_eq!(after, Some(Locale::Da));
+        assert_eq!(db.get_user_locale(user_id).await.unwrap(), None);
+        assert!(!db.set_user_locale(UserId(42), None).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_create_bot_with_api_token() {
+        // arrange
+        let db = setup_db().await;
+        let owner_id = db.get_or_insert_user_id("d").await.unwrap();
+
+        // act
+        let bot_id = db.insert_bot("bot", owner_id).await.unwrap().unwrap();
+        let token = db
+            .persist_api_token(bot_id, owner_id, "hash")
+            .await
+            .unwrap();
+
+        // assert
+        let token = token.unwrap();
+        assert_eq!(db.insert_bot("bot", owner_id).await.unwrap(), None);
+        assert_eq!(
+            db.get_bots(owner_id).await.unwrap(),
+            vec![Bot {
+                id: bot_id,
+                name: "bot".to_string(),
+                tokens: vec![token],
+            }]
+        );
+        assert_eq!(
+            db.get_api_token_user("hash").await.unwrap(),
+            Some((bot_id, "bot".to_string(), Role::Player))
+        );
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn only_owner_can_manage_api_tokens() {
+        // arrange
+        let db = setup_db().await;
+        let owner_id = db.get_or_insert_user_id("d").await.unwrap();
+        let other_id = db.get_or_insert_user_id("e").await.unwrap();
+        let bot_id = db.insert_bot("bot", owner_id).await.unwrap().unwrap();
+        let token = db
+            .persist_api_token(bot_id, owner_id, "hash")
+            .await
+            .unwrap();
+        let token_id = token.unwrap().id;
+
+        // act
+        let created = db
+            .persist_api_token(bot_id, other_id, "other")
+            .await
+            .unwrap();
+        let deleted_by_other = db
+            .delete_api_token(token_id, bot_id, other_id)
+            .await
+            .unwrap();
+        let deleted = db
+            .delete_api_token(token_id, bot_id, owner_id)
+            .await
+            .unwrap();
+
+        // assert
+        assert_eq!(created, None);
+        assert!(!deleted_by_other);
+        assert!(deleted);
+        assert_eq!(db.get_api_token_user("hash").await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn can_get_bots_in_game() {
+        // arrange
+        let db = setup_db().await;
+        let owner_id = db.get_or_insert_user_id("d").await.unwrap();
+        let bot_id = db.insert_bot("bot", owner_id).await.unwrap().unwrap();
+        let user_ids = LinkedHashSet::from_iter(vec![owner_id, bot_id]);
+        let game_id = db
+            .persist_game(&Game::try_from(user_ids).unwrap())
+            .await
+            .unwrap();
+
+        // act
+        let bot_ids = db.get_bot_ids(game_id).await.unwrap();
+
+        // assert
+        assert_eq!(bot_ids, vec![bot_id]);
+    }
+}
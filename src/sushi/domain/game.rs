@@ -1,3 +1,5 @@
+#![allow(clippy::useless_vec)]
+
 use super::player::*;
 use super::scoring::*;
 use super::types::*;
@@ -11,11 +13,11 @@ use std::fmt::{Display, Error as FormatError, Formatter};
 use std::iter::empty;
 use std::iter::FromIterator;
 
-const MIN_GAME_SIZE: usize = 2;
-const MAX_GAME_SIZE: usize = 5;
+pub const MIN_GAME_SIZE: usize = 2;
+pub const MAX_GAME_SIZE: usize = 5;
 const NUM_ROUNDS: usize = 3;
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Game {
     round: usize,
@@ -41,6 +43,18 @@ impl Game {
             .select_cards(cards)
     }
 
+    pub fn summary(&self) -> GameSummary {
+        GameSummary {
+            round: self.round,
+            winner: self.winner,
+            players: self.players.iter().map(|p| p.summary()).collect(),
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.winner.is_some()
+    }
+
     pub fn ready_to_end_turn(&self) -> bool {
         self.players.iter().all(|p| p.is_ready())
     }
@@ -221,7 +235,16 @@ impl Display for CreateGameError {
         use CreateGameError::*;
 
         match self {
-            TooFewPlayers(1) => write!(f, "You must add at least 1 more player to the game."),
+            TooFewPlayers(n) => {
+                let to_add = MIN_GAME_SIZE - n;
+                let suffix = if to_add == 1 { "" } else { "s" };
+
+                write!(
+                    f,
+                    "You must add at least {} more player{} to the game.",
+                    to_add, suffix
+                )
+            }
 
             TooManyPlayers(n) => {
                 let to_remove = n - MAX_GAME_SIZE;
@@ -233,8 +256,6 @@ impl Display for CreateGameError {
                     to_remove, suffix
                 )
             }
-
-            _ => unreachable!(),
         }
     }
 }
@@ -0,0 +1,587 @@
+use super::game::{CreateGameError, Game, MAX_GAME_SIZE, MIN_GAME_SIZE};
+use super::types::*;
+use linked_hash_set::LinkedHashSet;
+use rand::{seq::SliceRandom, thread_rng};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{Display, Error as FormatError, Formatter};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Invitation {
+    Pending,
+    Accepted,
+    Declined,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Seat {
+    pub user_id: UserId,
+    pub name: String,
+    pub invitation: Invitation,
+}
+
+const CODE_LENGTH: usize = 6;
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// A game that is still being set up. The host either invites players by name, in which
+/// case the cards are dealt once every invited player has accepted, or opens a table with
+/// a join code that anyone can use until it is full or the host starts it.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lobby {
+    host: UserId,
+    seats: Vec<Seat>,
+    started: bool,
+    #[serde(default)]
+    capacity: Option<usize>,
+    #[serde(default)]
+    code: Option<String>,
+    // Dealt but not yet persisted.
+    #[serde(skip)]
+    game: Option<Game>,
+}
+
+impl Lobby {
+    pub fn new(host: UserId, name: String) -> Self {
+        Self {
+            host,
+            seats: vec![Seat {
+                user_id: host,
+                name,
+                invitation: Invitation::Accepted,
+            }],
+            started: false,
+            capacity: None,
+            code: None,
+            game: None,
+        }
+    }
+
+    /// Opens a table for `capacity` players, including the host.
+    pub fn open(host: UserId, name: String, capacity: usize) -> Result<Self, CreateGameError> {
+        if capacity < MIN_GAME_SIZE {
+            return Err(CreateGameError::TooFewPlayers(capacity));
+        }
+
+        if capacity > MAX_GAME_SIZE {
+            return Err(CreateGameError::TooManyPlayers(capacity));
+        }
+
+        let mut rng = thread_rng();
+        let code = (0..CODE_LENGTH)
+            .map(|_| *CODE_ALPHABET.choose(&mut rng).unwrap() as char)
+            .collect();
+
+        Ok(Self {
+            capacity: Some(capacity),
+            code: Some(code),
+            ..Self::new(host, name)
+        })
+    }
+
+    /// Proposes a new game with the same players in the same seats as a finished game.
+    /// Everyone but the player proposing it has to accept.
+    pub fn rematch(
+        game: &Game,
+        by: UserId,
+        mut names: HashMap<UserId, String>,
+    ) -> Result<Self, LobbyError> {
+        if !game.is_over() {
+            return Err(LobbyError::GameNotOver);
+        }
+
+        let user_ids = game.user_ids();
+
+        if !user_ids.contains(&by) {
+            return Err(LobbyError::NotSeated);
+        }
+
+        let seats = user_ids
+            .into_iter()
+            .map(|user_id| Seat {
+                user_id,
+                name: names.remove(&user_id).unwrap_or_default(),
+                invitation: if user_id == by {
+                    Invitation::Accepted
+                } else {
+                    Invitation::Pending
+                },
+            })
+            .collect();
+
+        Ok(Self {
+            host: by,
+            seats,
+            started: false,
+            capacity: None,
+            code: None,
+            game: None,
+        })
+    }
+
+    pub fn is_host(&self, user_id: UserId) -> bool {
+        self.host == user_id
+    }
+
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    pub fn user_ids(&self) -> Vec<UserId> {
+        self.seats.iter().map(|s| s.user_id).collect()
+    }
+
+    pub fn is_seated(&self, user_id: UserId) -> bool {
+        self.seats.iter().any(|s| s.user_id == user_id)
+    }
+
+    pub fn invite(&mut self, by: UserId, user_id: UserId, name: String) -> Result<(), LobbyError> {
+        self.check_host(by)?;
+
+        if self.is_seated(user_id) {
+            return Err(LobbyError::AlreadySeated(name));
+        }
+
+        self.check_room()?;
+
+        self.seats.push(Seat {
+            user_id,
+            name,
+            invitation: Invitation::Pending,
+        });
+
+        Ok(())
+    }
+
+    /// Takes a seat at an open table. The cards are dealt when the last seat is taken.
+    pub fn join(&mut self, user_id: UserId, name: String) -> Result<(), LobbyError> {
+        if self.started {
+            return Err(LobbyError::AlreadyStarted);
+        }
+
+        if self.code.is_none() {
+            return Err(LobbyError::NotOpen);
+        }
+
+        if self.is_seated(user_id) {
+            return Err(LobbyError::AlreadySeated(name));
+        }
+
+        self.check_room()?;
+
+        self.seats.push(Seat {
+            user_id,
+            name,
+            invitation: Invitation::Accepted,
+        });
+
+        self.deal_if_ready();
+        Ok(())
+    }
+
+    pub fn remove(&mut self, by: UserId, user_id: UserId) -> Result<(), LobbyError> {
+        self.check_host(by)?;
+
+        if user_id == self.host {
+            return Err(LobbyError::CannotRemoveHost);
+        }
+
+        let index = self
+            .seats
+            .iter()
+            .position(|s| s.user_id == user_id)
+            .ok_or(LobbyError::NotSeated)?;
+
+        self.seats.remove(index);
+        self.deal_if_ready();
+        Ok(())
+    }
+
+    /// Accepts or declines an invitation. A player may change their mind until the game
+    /// has started.
+    pub fn respond(&mut self, user_id: UserId, accept: bool) -> Result<(), LobbyError> {
+        if self.started {
+            return Err(LobbyError::AlreadyStarted);
+        }
+
+        if user_id == self.host {
+            return Err(LobbyError::NotInvited);
+        }
+
+        let seat = self
+            .seats
+            .iter_mut()
+            .find(|s| s.user_id == user_id)
+            .ok_or(LobbyError::NotInvited)?;
+
+        seat.invitation = if accept {
+            Invitation::Accepted
+        } else {
+            Invitation::Declined
+        };
+
+        self.deal_if_ready();
+        Ok(())
+    }
+
+    /// Lets the host deal the cards before an open table is full.
+    pub fn start(&mut self, by: UserId) -> Result<(), LobbyError> {
+        self.check_host(by)?;
+
+        if !self.everyone_accepted() {
+            return Err(LobbyError::NotEveryoneAccepted);
+        }
+
+        self.deal()?;
+        Ok(())
+    }
+
+    /// Returns the game if the last change dealt the cards. It must be persisted together
+    /// with the lobby.
+    pub fn take_game(&mut self) -> Option<Game> {
+        self.game.take()
+    }
+
+    fn everyone_accepted(&self) -> bool {
+        self.seats
+            .iter()
+            .all(|s| s.invitation == Invitation::Accepted)
+    }
+
+    fn deal_if_ready(&mut self) {
+        let full = self.capacity.is_none_or(|c| self.seats.len() >= c);
+
+        if self.everyone_accepted() && full {
+            // A host left alone simply keeps waiting for players.
+            let _ = self.deal();
+        }
+    }
+
+    fn deal(&mut self) -> Result<(), CreateGameError> {
+        let user_ids: LinkedHashSet<_> = self.seats.iter().map(|s| s.user_id).collect();
+        self.game = Some(Game::try_from(user_ids)?);
+        self.started = true;
+        Ok(())
+    }
+
+    fn check_room(&self) -> Result<(), LobbyError> {
+        let capacity = self.capacity.unwrap_or(MAX_GAME_SIZE);
+
+        if self.seats.len() >= capacity {
+            return Err(LobbyError::Full { capacity });
+        }
+
+        Ok(())
+    }
+
+    fn check_host(&self, user_id: UserId) -> Result<(), LobbyError> {
+        if self.started {
+            Err(LobbyError::AlreadyStarted)
+        } else if user_id != self.host {
+            Err(LobbyError::NotHost)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum LobbyError {
+    NotHost,
+    NotInvited,
+    NotSeated,
+    AlreadySeated(String),
+    CannotRemoveHost,
+    AlreadyStarted,
+    NotOpen,
+    Full { capacity: usize },
+    NotEveryoneAccepted,
+    GameNotOver,
+    CreateGame(CreateGameError),
+}
+
+impl Display for LobbyError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FormatError> {
+        use LobbyError::*;
+
+        match self {
+            NotHost => write!(f, "Only the host can change the seats"),
+            NotInvited => write!(f, "You have not been invited to this game"),
+            NotSeated => write!(f, "That player is not in the game"),
+            AlreadySeated(name) => write!(f, "Player '{}' is already in the game", name),
+            CannotRemoveHost => write!(f, "The host cannot be removed"),
+            AlreadyStarted => write!(f, "The game has already started"),
+            NotOpen => write!(f, "This game can only be joined by invitation"),
+            Full { capacity } => write!(f, "The game is full with {} players", capacity),
+            NotEveryoneAccepted => write!(f, "Not everyone has accepted the invitation yet"),
+            GameNotOver => write!(f, "The game is not over yet"),
+            CreateGame(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for LobbyError {}
+
+impl From<CreateGameError> for LobbyError {
+    fn from(error: CreateGameError) -> LobbyError {
+        LobbyError::CreateGame(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter::FromIterator;
+
+    lazy_static! {
+        static ref HOST: UserId = UserId(1);
+        static ref PLAYER2: UserId = UserId(2);
+        static ref PLAYER3: UserId = UserId(3);
+    }
+
+    fn lobby() -> Lobby {
+        let mut lobby = Lobby::new(*HOST, "a".to_string());
+        lobby.invite(*HOST, *PLAYER2, "b".to_string()).unwrap();
+        lobby.invite(*HOST, *PLAYER3, "c".to_string()).unwrap();
+        lobby
+    }
+
+    #[test]
+    fn does_not_start_until_everyone_accepted() {
+        // arrange
+        let mut lobby = lobby();
+
+        // act
+        lobby.respond(*PLAYER2, true).unwrap();
+
+        // assert
+        assert!(lobby.take_game().is_none());
+    }
+
+    #[test]
+    fn starts_when_everyone_accepted() {
+        // arrange
+        let mut lobby = lobby();
+        lobby.respond(*PLAYER2, true).unwrap();
+
+        // act
+        lobby.respond(*PLAYER3, true).unwrap();
+
+        // assert
+        let game = lobby.take_game().unwrap();
+        assert_eq!(game.user_ids(), vec![*HOST, *PLAYER2, *PLAYER3]);
+        assert_eq!(
+            lobby.respond(*PLAYER3, true),
+            Err(LobbyError::AlreadyStarted)
+        );
+    }
+
+    #[test]
+    fn declined_seat_blocks_start_until_removed() {
+        // arrange
+        let mut lobby = lobby();
+        lobby.respond(*PLAYER2, true).unwrap();
+        lobby.respond(*PLAYER3, false).unwrap();
+        assert!(lobby.take_game().is_none());
+
+        // act
+        lobby.remove(*HOST, *PLAYER3).unwrap();
+
+        // assert
+        assert!(lobby.take_game().is_some());
+    }
+
+    #[test]
+    fn host_alone_cannot_start() {
+        // arrange
+        let mut lobby = Lobby::open(*HOST, "a".to_string(), 3).unwrap();
+
+        // act
+        let error = lobby.start(*HOST).unwrap_err();
+
+        // assert
+        assert_eq!(
+            error,
+            LobbyError::CreateGame(CreateGameError::TooFewPlayers(1))
+        );
+    }
+
+    #[test]
+    fn only_host_can_change_seats() {
+        // arrange
+        let mut lobby = lobby();
+
+        // act
+        let error = lobby.remove(*PLAYER2, *PLAYER3).unwrap_err();
+
+        // assert
+        assert_eq!(error, LobbyError::NotHost);
+    }
+
+    #[test]
+    fn cannot_invite_more_than_max_players() {
+        // arrange
+        let mut lobby = lobby();
+        lobby.invite(*HOST, UserId(4), "d".to_string()).unwrap();
+        lobby.invite(*HOST, UserId(5), "e".to_string()).unwrap();
+
+        // act
+        let error = lobby.invite(*HOST, UserId(6), "f".to_string()).unwrap_err();
+
+        // assert
+        assert_eq!(error, LobbyError::Full { capacity: 5 });
+    }
+
+    #[test]
+    fn cannot_invite_same_player_twice() {
+        // arrange
+        let mut lobby = lobby();
+
+        // act
+        let error = lobby.invite(*HOST, *PLAYER2, "b".to_string()).unwrap_err();
+
+        // assert
+        assert_eq!(error, LobbyError::AlreadySeated("b".to_string()));
+    }
+
+    #[test]
+    fn open_table_starts_when_full() {
+        // arrange
+        let mut lobby = Lobby::open(*HOST, "a".to_string(), 3).unwrap();
+        lobby.join(*PLAYER2, "b".to_string()).unwrap();
+        assert!(lobby.take_game().is_none());
+
+        // act
+        lobby.join(*PLAYER3, "c".to_string()).unwrap();
+
+        // assert
+        assert!(lobby.take_game().is_some());
+    }
+
+    #[test]
+    fn cannot_join_full_table() {
+        // arrange
+        let mut lobby = Lobby::open(*HOST, "a".to_string(), 3).unwrap();
+        lobby.invite(*HOST, *PLAYER2, "b".to_string()).unwrap();
+        lobby.invite(*HOST, *PLAYER3, "c".to_string()).unwrap();
+
+        // act
+        let error = lobby.join(UserId(4), "d".to_string()).unwrap_err();
+
+        // assert
+        assert_eq!(error, LobbyError::Full { capacity: 3 });
+        assert!(lobby.take_game().is_none());
+    }
+
+    #[test]
+    fn host_can_start_open_table_before_it_is_full() {
+        // arrange
+        let mut lobby = Lobby::open(*HOST, "a".to_string(), 5).unwrap();
+        lobby.join(*PLAYER2, "b".to_string()).unwrap();
+
+        // act
+        lobby.start(*HOST).unwrap();
+
+        // assert
+        assert_eq!(lobby.take_game().unwrap().user_ids(), vec![*HOST, *PLAYER2]);
+        assert_eq!(
+            lobby.join(*PLAYER3, "c".to_string()),
+            Err(LobbyError::AlreadyStarted)
+        );
+    }
+
+    #[test]
+    fn cannot_open_table_with_invalid_capacity() {
+        assert_eq!(
+            Lobby::open(*HOST, "a".to_string(), 1).unwrap_err(),
+            CreateGameError::TooFewPlayers(1)
+        );
+
+        assert_eq!(
+            Lobby::open(*HOST, "a".to_string(), 6).unwrap_err(),
+            CreateGameError::TooManyPlayers(6)
+        );
+    }
+
+    #[test]
+    fn cannot_join_invitation_only_lobby() {
+        // arrange
+        let mut lobby = lobby();
+
+        // act
+        let error = lobby.join(UserId(4), "d".to_string()).unwrap_err();
+
+        // assert
+        assert_eq!(error, LobbyError::NotOpen);
+    }
+
+    #[test]
+    fn code_has_expected_format() {
+        // act
+        let lobby = Lobby::open(*HOST, "a".to_string(), 2).unwrap();
+
+        // assert
+        let code = lobby.code().unwrap();
+        assert_eq!(code.len(), CODE_LENGTH);
+        assert!(code.bytes().all(|b| CODE_ALPHABET.contains(&b)));
+    }
+
+    fn finished_game() -> Game {
+        let user_ids = LinkedHashSet::from_iter(vec![*HOST, *PLAYER2, *PLAYER3]);
+        let mut game = Game::try_from(user_ids).unwrap();
+
+        while !game.is_over() {
+            for user_id in game.user_ids() {
+                let view = game.clone().into(user_id);
+                let card = *view.player.unwrap().hand.keys().next().unwrap();
+                game.select_cards(user_id, vec![card]).unwrap();
+            }
+
+            game.end_turn();
+        }
+
+        game
+    }
+
+    #[test]
+    fn rematch_keeps_seats_and_starts_when_everyone_accepted() {
+        // arrange
+        let game = finished_game();
+        let names = vec![(*HOST, "a"), (*PLAYER2, "b"), (*PLAYER3, "c")]
+            .into_iter()
+            .map(|(id, name)| (id, name.to_string()))
+            .collect();
+
+        // act
+        let mut lobby = Lobby::rematch(&game, *PLAYER2, names).unwrap();
+
+        // assert
+        assert!(lobby.is_host(*PLAYER2));
+        assert_eq!(lobby.user_ids(), vec![*HOST, *PLAYER2, *PLAYER3]);
+        lobby.respond(*HOST, true).unwrap();
+        assert!(lobby.take_game().is_none());
+        lobby.respond(*PLAYER3, true).unwrap();
+        assert_eq!(
+            lobby.take_game().unwrap().user_ids(),
+            vec![*HOST, *PLAYER2, *PLAYER3]
+        );
+    }
+
+    #[test]
+    fn cannot_rematch_unfinished_game() {
+        // arrange
+        let user_ids = LinkedHashSet::from_iter(vec![*HOST, *PLAYER2]);
+        let game = Game::try_from(user_ids).unwrap();
+
+        // act
+        let error = Lobby::rematch(&game, *HOST, HashMap::new()).unwrap_err();
+
+        // assert
+        assert_eq!(error, LobbyError::GameNotOver);
+    }
+}
@@ -1,7 +1,13 @@
 mod game;
+mod lobby;
 mod player;
 mod scoring;
 mod types;
 
-pub use game::Game;
-pub use types::{GameEvent, UserId};
+pub use game::{CreateGameError, Game, MAX_GAME_SIZE, MIN_GAME_SIZE};
+pub use lobby::{Lobby, LobbyError};
+pub use player::SelectCardsError;
+pub use types::{
+    Card, FaceUpCard, GameEvent, GameSummary, GameView, MakiRolls, Nigiri, OpponentView,
+    PlayerView, RoundOver, UserId,
+};
@@ -1,10 +1,16 @@
+#![allow(
+    clippy::from_over_into,
+    clippy::match_like_matches_macro,
+    clippy::mem_replace_with_default
+)]
+
 use super::types::*;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::error::Error;
 use std::fmt::{Display, Error as FormatError, Formatter};
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Player {
     face_up_cards: Vec<FaceUpCard>,
@@ -12,6 +18,7 @@ pub struct Player {
     id: UserId,
     num_points: usize,
     num_puddings: usize,
+    played_cards: Vec<Card>,
     selected_cards: Vec<usize>,
 }
 
@@ -23,6 +30,7 @@ impl Player {
             id,
             num_points: 0,
             num_puddings: 0,
+            played_cards: Vec::new(),
             selected_cards: Vec::new(),
         }
     }
@@ -114,6 +122,8 @@ impl Player {
 
         // Play the cards:
         for (id, card) in played_cards {
+            self.played_cards.push(card);
+
             if let Card::Pudding = card {
                 self.num_puddings += 1;
             }
@@ -163,6 +173,16 @@ impl Player {
     pub fn add_points(&mut self, score: usize) {
         self.num_points += score;
     }
+
+    pub fn summary(&self) -> PlayerSummary {
+        PlayerSummary {
+            id: self.id,
+            num_points: self.num_points,
+            num_puddings: self.num_puddings,
+            ready: self.is_ready(),
+            played_cards: self.played_cards.clone(),
+        }
+    }
 }
 
 impl PartialOrd for Player {
@@ -198,6 +218,7 @@ impl Into<OpponentView> for Player {
         OpponentView {
             face_up_cards: self.face_up_cards,
             id: self.id,
+            is_bot: false,
             num_cards: self.hand.len(),
             num_points: self.num_points,
             num_puddings: self.num_puddings,
@@ -1,9 +1,11 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::{Display, Error as FormatError, Formatter};
 use std::time::Duration;
+use ts_rs::TS;
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize, TS)]
 #[serde(rename_all = "camelCase", tag = "makiRolls")]
 pub enum MakiRolls {
     One = 1,
@@ -11,7 +13,7 @@ pub enum MakiRolls {
     Three = 3,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize, TS)]
 #[serde(rename_all = "camelCase", tag = "nigiri")]
 pub enum Nigiri {
     Egg = 1,
@@ -19,7 +21,7 @@ pub enum Nigiri {
     Squid = 3,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize, TS)]
 #[serde(rename_all = "camelCase", tag = "kind")]
 pub enum Card {
     Chopsticks,
@@ -32,7 +34,27 @@ pub enum Card {
     Wasabi,
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+impl Card {
+    /// A stable name for the card, e.g. `sashimi` or `nigiri:salmon`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Card::Chopsticks => "chopsticks",
+            Card::Dumpling => "dumpling",
+            Card::MakiRolls(MakiRolls::One) => "makiRolls:one",
+            Card::MakiRolls(MakiRolls::Two) => "makiRolls:two",
+            Card::MakiRolls(MakiRolls::Three) => "makiRolls:three",
+            Card::Nigiri(Nigiri::Egg) => "nigiri:egg",
+            Card::Nigiri(Nigiri::Salmon) => "nigiri:salmon",
+            Card::Nigiri(Nigiri::Squid) => "nigiri:squid",
+            Card::Pudding => "pudding",
+            Card::Sashimi => "sashimi",
+            Card::Tempura => "tempura",
+            Card::Wasabi => "wasabi",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize, TS)]
 #[serde(rename_all = "camelCase", tag = "kind")]
 pub enum FaceUpCard {
     Card { id: usize, card: Card },
@@ -41,8 +63,8 @@ pub enum FaceUpCard {
 
 pub type Hand = HashMap<usize, Card>;
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
-pub struct UserId(pub i64);
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize, TS)]
+pub struct UserId(#[ts(type = "number")] pub i64);
 
 impl Display for UserId {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FormatError> {
@@ -50,7 +72,7 @@ impl Display for UserId {
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, JsonSchema, Serialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct RoundOver {
     pub round: usize,
@@ -67,7 +89,38 @@ pub enum GameEvent {
     GameOver(UserId),
 }
 
-#[derive(Debug, Serialize)]
+/// The queryable parts of a game's state, which storage backends keep in their own
+/// columns next to the serialized game.
+#[derive(Debug)]
+pub struct GameSummary {
+    pub round: usize,
+    pub winner: Option<UserId>,
+    pub players: Vec<PlayerSummary>,
+}
+
+#[derive(Debug)]
+pub struct PlayerSummary {
+    pub id: UserId,
+    pub num_points: usize,
+    pub num_puddings: usize,
+    pub ready: bool,
+    pub played_cards: Vec<Card>,
+}
+
+impl PlayerSummary {
+    /// Counts how many of each card the player has played, keyed by `Card::name`.
+    pub fn played_card_counts(&self) -> HashMap<&'static str, usize> {
+        let mut counts = HashMap::new();
+
+        for card in &self.played_cards {
+            *counts.entry(card.name()).or_insert(0) += 1;
+        }
+
+        counts
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct GameView {
     pub round: usize,
@@ -76,7 +129,7 @@ pub struct GameView {
     pub winner: Option<UserId>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, JsonSchema, Serialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct PlayerView {
     pub face_up_cards: Vec<FaceUpCard>,
@@ -86,11 +139,12 @@ pub struct PlayerView {
     pub selected_cards: Vec<usize>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, JsonSchema, Serialize, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct OpponentView {
     pub face_up_cards: Vec<FaceUpCard>,
     pub id: UserId,
+    pub is_bot: bool,
     pub num_cards: usize,
     pub num_points: usize,
     pub num_puddings: usize,
@@ -0,0 +1,364 @@
+use super::actor::Error as ActorError;
+use super::chat::{ChatError, MAX_MESSAGE_LENGTH};
+use super::db::Error as DbError;
+use super::domain::*;
+use super::i18n::{translate, Locale};
+use actix_web::{
+    dev::{Body, ResponseBody, ServiceResponse},
+    http::{header, StatusCode},
+    middleware::errhandlers::{ErrorHandlerResponse, ErrorHandlers},
+    HttpResponse, ResponseError, Result as ActixResult,
+};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+
+/// An error as sent to clients. The code is stable and, together with the parameters, is
+/// meant for handling the error; the message is looked up in the catalog for the
+/// client's locale.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    params: Map<String, Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str) -> Self {
+        Self {
+            status,
+            code,
+            params: Map::new(),
+        }
+    }
+
+    /// Adds a parameter, e.g. how many players to remove.
+    pub fn with<V: Serialize>(mut self, name: &str, value: V) -> Self {
+        let value = serde_json::to_value(value).expect("failed to serialize error parameter");
+        self.params.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "unauthorized")
+    }
+
+    pub fn forbidden() -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden")
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found")
+    }
+
+    /// Hides the details of an unexpected error from clients, but logs them.
+    pub fn internal<E: Display>(error: E) -> Self {
+        error!("Request failed because {}", error);
+
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal")
+    }
+
+    pub fn message(&self, locale: Locale) -> String {
+        translate(locale, self.code, &self.params)
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        write!(f, "{}", self.message(Locale::En))
+    }
+}
+
+impl Error for ApiError {}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Renders the error in English; `error_handlers` translates it for the client.
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(body(self, Locale::En))
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    params: &'a Map<String, Value>,
+}
+
+/// The body of every failed response, shaped like a successful response without a
+/// payload.
+fn body(error: &ApiError, locale: Locale) -> Value {
+    let error = ErrorBody {
+        code: error.code,
+        message: error.message(locale),
+        params: &error.params,
+    };
+
+    json!({
+        "success": false,
+        "payload": null,
+        "error": error,
+    })
+}
+
+impl From<DbError> for ApiError {
+    fn from(error: DbError) -> Self {
+        match error {
+            DbError::GameVersionConflict => Self::new(StatusCode::CONFLICT, "conflict"),
+            e => Self::internal(e),
+        }
+    }
+}
+
+impl From<ActorError> for ApiError {
+    fn from(error: ActorError) -> Self {
+        match error {
+            ActorError::DbError(e) => e.into(),
+            ActorError::GameNotFound => Self::not_found(),
+            e => Self::internal(e),
+        }
+    }
+}
+
+impl From<CreateGameError> for ApiError {
+    fn from(error: CreateGameError) -> Self {
+        let status = StatusCode::BAD_REQUEST;
+
+        match error {
+            CreateGameError::TooFewPlayers(n) => Self::new(status, "too_few_players")
+                .with("toAdd", MIN_GAME_SIZE - n)
+                .with("min", MIN_GAME_SIZE),
+            CreateGameError::TooManyPlayers(n) => Self::new(status, "too_many_players")
+                .with("toRemove", n - MAX_GAME_SIZE)
+                .with("max", MAX_GAME_SIZE),
+        }
+    }
+}
+
+impl From<LobbyError> for ApiError {
+    fn from(error: LobbyError) -> Self {
+        use LobbyError::*;
+
+        let (status, code) = match error {
+            NotHost => (StatusCode::FORBIDDEN, "not_host"),
+            NotInvited => (StatusCode::FORBIDDEN, "not_invited"),
+            NotSeated => (StatusCode::NOT_FOUND, "not_seated"),
+            AlreadySeated(name) => {
+                return Self::new(StatusCode::CONFLICT, "already_seated").with("name", name)
+            }
+            CannotRemoveHost => (StatusCode::BAD_REQUEST, "cannot_remove_host"),
+            AlreadyStarted => (StatusCode::CONFLICT, "already_started"),
+            NotOpen => (StatusCode::FORBIDDEN, "not_open"),
+            Full { capacity } => {
+                return Self::new(StatusCode::CONFLICT, "lobby_full").with("capacity", capacity)
+            }
+            NotEveryoneAccepted => (StatusCode::CONFLICT, "not_everyone_accepted"),
+            GameNotOver => (StatusCode::CONFLICT, "game_not_over"),
+            CreateGame(e) => return e.into(),
+        };
+
+        Self::new(status, code)
+    }
+}
+
+impl From<SelectCardsError> for ApiError {
+    fn from(error: SelectCardsError) -> Self {
+        let code = match error {
+            SelectCardsError::TooManyCards => "too_many_cards",
+            SelectCardsError::NoChopsticks => "no_chopsticks",
+            SelectCardsError::DoesNotHaveCard => "does_not_have_card",
+        };
+
+        Self::new(StatusCode::BAD_REQUEST, code)
+    }
+}
+
+impl From<ChatError> for ApiError {
+    fn from(error: ChatError) -> Self {
+        match error {
+            ChatError::Empty => Self::new(StatusCode::BAD_REQUEST, "message_empty"),
+            ChatError::TooLong => Self::new(StatusCode::BAD_REQUEST, "message_too_long")
+                .with("max", MAX_MESSAGE_LENGTH),
+            ChatError::TooFast => Self::new(StatusCode::TOO_MANY_REQUESTS, "too_many_messages"),
+        }
+    }
+}
+
+/// Translates errors into the client's locale, and gives errors produced by actix
+/// itself, such as malformed JSON or unknown routes, the same body as errors from
+/// handlers.
+pub fn error_handlers<B: 'static>() -> ErrorHandlers<B> {
+    let statuses = &[
+        (StatusCode::BAD_REQUEST, "bad_request"),
+        (StatusCode::UNAUTHORIZED, "unauthorized"),
+        (StatusCode::FORBIDDEN, "forbidden"),
+        (StatusCode::NOT_FOUND, "not_found"),
+        (StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed"),
+        (StatusCode::CONFLICT, "conflict"),
+        (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large"),
+        (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type"),
+        (StatusCode::TOO_MANY_REQUESTS, "too_many_requests"),
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    ];
+
+    statuses
+        .iter()
+        .fold(ErrorHandlers::new(), |handlers, (status, code)| {
+            handlers.handler(*status, move |response| structure(response, code))
+        })
+}
+
+fn structure<B>(
+    mut response: ServiceResponse<B>,
+    code: &'static str,
+) -> ActixResult<ErrorHandlerResponse<B>> {
+    let locale = Locale::of(response.request());
+    let error = response.response().error();
+
+    let body = match error.and_then(|e| e.as_error::<ApiError>()) {
+        Some(error) => body(error, locale),
+        None => {
+            let is_json = response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .is_some_and(|value| value == "application/json");
+
+            if is_json {
+                return Ok(ErrorHandlerResponse::Response(response));
+            }
+
+            body(&ApiError::new(response.status(), code), locale)
+        }
+    }
+    .to_string();
+
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+
+    let response = response.map_body(|_, _| ResponseBody::Other(Body::from(body)));
+    Ok(ErrorHandlerResponse::Response(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    #[test]
+    fn errors_have_code_and_params() {
+        // act
+        let error = ApiError::from(CreateGameError::TooManyPlayers(7));
+
+        // assert
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body(&error, Locale::En),
+            json!({
+                "success": false,
+                "payload": null,
+                "error": {
+                    "code": "too_many_players",
+                    "message": "Remove at least 2 players from the game.",
+                    "params": { "toRemove": 2, "max": 5 },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn errors_without_params_have_no_params() {
+        // act
+        let error = ApiError::from(LobbyError::NotHost);
+
+        // assert
+        assert_eq!(error.status_code(), StatusCode::FORBIDDEN);
+        assert!(body(&error, Locale::En)["error"].get("params").is_none());
+    }
+
+    #[test]
+    fn lobby_errors_wrap_create_game_errors() {
+        // act
+        let error = ApiError::from(LobbyError::CreateGame(CreateGameError::TooFewPlayers(1)));
+
+        // assert
+        assert_eq!(error.code, "too_few_players");
+        assert_eq!(error.params["toAdd"], 1);
+    }
+
+    #[test]
+    fn full_lobby_reports_its_capacity() {
+        // act
+        let error = ApiError::from(LobbyError::Full { capacity: 3 });
+
+        // assert
+        assert_eq!(error.status_code(), StatusCode::CONFLICT);
+        assert_eq!(
+            body(&error, Locale::En)["error"]["message"],
+            "The game is full with 3 players"
+        );
+    }
+
+    #[test]
+    fn internal_errors_hide_details() {
+        // act
+        let error = ApiError::from(DbError::SchemaTooNew(42));
+
+        // assert
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.to_string(), "Something went wrong");
+    }
+
+    #[actix_rt::test]
+    async fn actix_errors_get_the_same_body() {
+        // arrange
+        let app = App::new().wrap(error_handlers()).route(
+            "/",
+            web::post().to(|n: web::Json<u32>| async move { n.to_string() }),
+        );
+        let mut app = test::init_service(app).await;
+        let request = test::TestRequest::post()
+            .uri("/")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload("nope")
+            .to_request();
+
+        // act
+        let response: Value = test::read_response_json(&mut app, request).await;
+
+        // assert
+        assert_eq!(response["success"], false);
+        assert_eq!(response["error"]["code"], "bad_request");
+    }
+
+    #[actix_rt::test]
+    async fn errors_are_translated_for_client() {
+        // arrange
+        let app = App::new().wrap(error_handlers()).route(
+            "/",
+            web::get().to(|| async { Err::<String, _>(ApiError::from(LobbyError::NotHost)) }),
+        );
+        let mut app = test::init_service(app).await;
+        let request = test::TestRequest::get()
+            .uri("/")
+            .header(header::ACCEPT_LANGUAGE, "da-DK, en;q=0.5")
+            .to_request();
+
+        // act
+        let response: Value = test::read_response_json(&mut app, request).await;
+
+        // assert
+        assert_eq!(response["error"]["code"], "not_host");
+        assert_eq!(
+            response["error"]["message"],
+            "Kun værten kan ændre pladserne"
+        );
+    }
+}
@@ -1,216 +1,800 @@
+use super::actor::GameActors;
+use super::auth::{AuthenticatedUser, GameMember, GamePlayer};
+use super::bots::{Bot, NewApiToken};
+use super::chat;
 use super::db::{Error as DbError, *};
 use super::domain::*;
+use super::errors::ApiError;
+use super::i18n::Locale;
+use super::matchmaker::Matchmaker;
+use super::openapi;
+use super::password;
 use super::pubsub::*;
-use super::GameId;
+use super::tokens::{hash_token, new_api_token, Tokens};
+use super::{GameDetails, LobbyId, LobbyView};
 use actix_web::{
+    delete,
     dev::HttpResponseBuilder,
     error, get,
     http::StatusCode,
     post, put,
-    web::{Data, HttpResponse, Json, Path, ServiceConfig},
-    HttpRequest, ResponseError, Result as ActixResult,
+    web::{scope, Data, HttpResponse, Json, Path, Query, ServiceConfig},
 };
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use linked_hash_set::LinkedHashSet;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::convert::TryFrom;
-use std::fmt::Display;
-use std::time::Duration;
-use tokio::{stream::StreamExt, sync::mpsc::UnboundedSender};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+use tokio::{stream::StreamExt, task::spawn_blocking};
+
+pub(super) mod v2;
+
+type AppResult = std::result::Result<HttpResponse, ApiError>;
+
+/// A version of the API, served under `/api/v1` and `/api/v2`. Routes whose request
+/// bodies are the same in both versions are shared, and read the version from the data
+/// of their scope to shape the response.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ApiVersion {
+    /// Bare JSON values as request bodies, and the `{ success, payload, error }` envelope
+    /// around most responses.
+    V1,
+    /// Objects as request bodies, and payloads sent as they are.
+    V2,
+}
 
-const JWT_ALGORITHM: Algorithm = Algorithm::HS256;
+impl ApiVersion {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "/api/v1",
+            ApiVersion::V2 => "/api/v2",
+        }
+    }
+
+    fn respond<T: Serialize>(self, payload: T) -> HttpResponse {
+        match self {
+            ApiVersion::V1 => success(payload),
+            ApiVersion::V2 => HttpResponseBuilder::new(StatusCode::OK).json(payload),
+        }
+    }
+
+    /// Responds without a payload, which version 2 does with `204 No Content`.
+    fn done(self) -> HttpResponse {
+        match self {
+            ApiVersion::V1 => success(()),
+            ApiVersion::V2 => HttpResponse::NoContent().finish(),
+        }
+    }
+}
 
-lazy_static! {
-    static ref ENCODING_KEY: EncodingKey = EncodingKey::from_secret(b"secret");
-    static ref DECODING_KEY: DecodingKey<'static> = DecodingKey::from_secret(b"secret");
+impl Display for ApiVersion {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            ApiVersion::V1 => write!(f, "v1"),
+            ApiVersion::V2 => write!(f, "v2"),
+        }
+    }
 }
 
-type AppResult = ActixResult<HttpResponse>;
+impl FromStr for ApiVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(ApiVersion::V1),
+            "v2" => Ok(ApiVersion::V2),
+            _ => Err(format!("Unknown API version '{}'", s)),
+        }
+    }
+}
 
 pub fn app(
-    db: Database,
+    db: Store,
     broadcaster: Broadcaster,
-    tx: UnboundedSender<Countdown>,
+    actors: GameActors,
+    matchmaker: Matchmaker,
+    tokens: Tokens,
     cfg: &mut ServiceConfig,
 ) {
     cfg.data(db)
         .data(broadcaster)
-        .data(tx)
+        .data(actors)
+        .data(matchmaker)
+        .data(tokens)
+        .service(scope("/api/v1").data(ApiVersion::V1).configure(v1))
+        .service(scope("/api/v2").data(ApiVersion::V2).configure(v2::routes))
+        // The paths from before versioning stay as aliases of version 1
+        .service(scope("/api").data(ApiVersion::V1).configure(v1));
+}
+
+fn v1(cfg: &mut ServiceConfig) {
+    cfg.service(register)
         .service(login)
+        .service(refresh)
+        .service(logout)
+        .service(claim_account)
+        .service(set_locale)
+        .service(get_bots)
+        .service(create_bot)
+        .service(create_api_token)
+        .service(revoke_api_token)
         .service(get_games)
         .service(create_game)
         .service(get_game)
         .service(select_cards)
-        .service(stream);
+        .service(send_chat)
+        .service(stream)
+        .service(rematch)
+        .service(get_lobbies)
+        .service(create_lobby)
+        .service(open_lobby)
+        .service(join_lobby)
+        .service(get_lobby)
+        .service(invite_player)
+        .service(remove_player)
+        .service(accept_invitation)
+        .service(decline_invitation)
+        .service(start_lobby)
+        .service(enqueue)
+        .service(leave_queue)
+        .service(user_stream)
+        .service(get_openapi);
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(super) struct Credentials {
+    name: String,
+    password: String,
+}
+
+#[post("/register")]
+async fn register(
+    db: Data<Store>,
+    tokens: Data<Tokens>,
+    credentials: Json<Credentials>,
+) -> AppResult {
+    let Credentials { name, password } = credentials.into_inner();
+    let name = name.trim();
+
+    if name.is_empty() {
+        return Err(missing_name());
+    }
+
+    check_password(&password)?;
+    let password_hash = blocking(move || password::hash(&password)).await?;
+
+    match db.insert_user(name, &password_hash).await? {
+        None => Err(name_taken(name)),
+        Some(user_id) => issue_tokens(&db, &tokens, user_id, name.to_string()).await,
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Claims {
-    pub sub: UserId,
-    pub name: String,
+#[post("/login")]
+async fn login(db: Data<Store>, tokens: Data<Tokens>, credentials: Json<Credentials>) -> AppResult {
+    let Credentials { name, password } = credentials.into_inner();
+    let name = name.trim().to_string();
+
+    // Accounts without a password fail like unknown names, so neither can be told apart
+    let (user_id, password_hash) = match db.get_credentials(&name).await? {
+        Some((user_id, Some(password_hash))) => (user_id, password_hash),
+        _ => return Err(wrong_credentials()),
+    };
+
+    if !blocking(move || password::verify(&password, &password_hash)).await? {
+        info!("Wrong password for {}", name);
+        return Err(wrong_credentials());
+    }
+
+    info!("Log in for {}; id is {}", name, user_id);
+    issue_tokens(&db, &tokens, user_id, name).await
 }
 
-#[post("/api/login")]
-async fn login(db: Data<Database>, user_name: Json<String>) -> AppResult {
-    let user_id = db.get_or_insert_user_id(&user_name)?;
+/// Exchanges a refresh token for a new pair of tokens. Each refresh token can only be
+/// used once.
+#[post("/token/refresh")]
+async fn refresh(db: Data<Store>, tokens: Data<Tokens>, refresh_token: Json<String>) -> AppResult {
+    refresh_tokens(&db, &tokens, &refresh_token).await
+}
 
-    info!("Log in for {}; id is {}", user_name, user_id);
+async fn refresh_tokens(db: &Store, tokens: &Tokens, refresh_token: &str) -> AppResult {
+    let token_hash = hash_token(refresh_token);
 
-    let jwt = jsonwebtoken::encode(
-        &Header::new(JWT_ALGORITHM),
-        &Claims {
-            sub: user_id,
-            name: user_name.0,
-        },
-        &ENCODING_KEY,
-    )
-    .expect("failed to encode jwt");
+    match db.take_refresh_token(&token_hash).await? {
+        None => Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "invalid_refresh_token",
+        )),
+        Some((user_id, name)) => issue_tokens(db, tokens, user_id, name).await,
+    }
+}
 
-    ok(jwt)
+/// Revokes the refresh token. The access token stays valid until it expires.
+#[post("/logout")]
+async fn logout(db: Data<Store>, refresh_token: Json<String>) -> AppResult {
+    revoke_refresh_token(&db, &refresh_token).await?;
+    Ok(success(()))
 }
 
-#[get("/api/games")]
-async fn get_games(db: Data<Database>, request: HttpRequest) -> AppResult {
-    let user_id = extract_user_id(&request)?;
-    info!("Getting games for {}", user_id);
-    let game_ids = db.get_games_for_user(user_id)?;
-    ok(game_ids)
+async fn revoke_refresh_token(
+    db: &Store,
+    refresh_token: &str,
+) -> std::result::Result<(), ApiError> {
+    let token_hash = hash_token(refresh_token);
+    db.take_refresh_token(&token_hash).await?;
+    Ok(())
 }
 
-#[post("/api/games")]
-async fn create_game(
-    db: Data<Database>,
-    request: HttpRequest,
-    opponents: Json<Vec<String>>,
+/// Lets a user from before passwords were introduced claim their account by setting a
+/// password, using the token they are still logged in with, or one from a claim code
+/// given by an admin (see `--issue-claim-code`).
+#[post("/account/password")]
+async fn claim_account(
+    db: Data<Store>,
+    user: AuthenticatedUser,
+    password: Json<String>,
 ) -> AppResult {
-    let user_id = extract_user_id(&request)?;
-    let mut user_ids = LinkedHashSet::new();
-    user_ids.insert(user_id);
+    set_password(&db, &user, password.into_inner()).await?;
+    Ok(success(()))
+}
 
-    for opponent in &opponents.0 {
-        let trimmed = opponent.trim();
+async fn set_password(
+    db: &Store,
+    user: &AuthenticatedUser,
+    password: String,
+) -> std::result::Result<(), ApiError> {
+    if user.is_bot {
+        return Err(bot_not_allowed());
+    }
 
-        if trimmed.is_empty() {
-            return Ok(failure("Missing opponent name"));
-        }
+    check_password(&password)?;
+    let password_hash = blocking(move || password::hash(&password)).await?;
 
-        let user_id = db.get_or_insert_user_id(trimmed)?;
+    if db.set_password_hash(user.id, &password_hash).await? {
+        Ok(())
+    } else {
+        Err(ApiError::new(StatusCode::CONFLICT, "password_already_set"))
+    }
+}
 
-        if !user_ids.insert(user_id) {
-            return Ok(failure(format!(
-                "Player '{}' is already in the game",
-                trimmed
-            )));
-        }
+/// Sets or clears the language the user prefers for messages, overriding
+/// `Accept-Language`. Returns new tokens, since the preference is part of them.
+#[put("/account/locale")]
+async fn set_locale(
+    db: Data<Store>,
+    tokens: Data<Tokens>,
+    user: AuthenticatedUser,
+    locale: Json<Option<Locale>>,
+) -> AppResult {
+    change_locale(&db, &tokens, user, *locale).await
+}
+
+async fn change_locale(
+    db: &Store,
+    tokens: &Tokens,
+    user: AuthenticatedUser,
+    locale: Option<Locale>,
+) -> AppResult {
+    if user.is_bot {
+        return Err(bot_not_allowed());
     }
 
-    match Game::try_from(user_ids) {
-        Err(e) => Ok(failure(e)),
+    db.set_user_locale(user.id, locale).await?;
+    issue_tokens(db, tokens, user.id, user.name).await
+}
 
-        Ok(game) => {
-            let game_id = db.persist_game(&game)?;
-            Ok(success(game_id))
-        }
+#[get("/bots")]
+async fn get_bots(db: Data<Store>, user: AuthenticatedUser) -> AppResult {
+    let bots = db.get_bots(user.id).await?;
+    ok(bots)
+}
+
+#[post("/bots")]
+async fn create_bot(db: Data<Store>, user: AuthenticatedUser, name: Json<String>) -> AppResult {
+    let bot = add_bot(&db, &user, &name).await?;
+    Ok(success(bot))
+}
+
+async fn add_bot(
+    db: &Store,
+    user: &AuthenticatedUser,
+    name: &str,
+) -> std::result::Result<Bot, ApiError> {
+    if user.is_bot {
+        return Err(bot_not_allowed());
+    }
+
+    let name = name.trim();
+
+    if name.is_empty() {
+        return Err(missing_name());
+    }
+
+    match db.insert_bot(name, user.id).await? {
+        None => Err(name_taken(name)),
+        Some(id) => Ok(Bot {
+            id,
+            name: name.to_string(),
+            tokens: vec![],
+        }),
     }
 }
 
-#[get("/api/games/{game_id}")]
-async fn get_game(db: Data<Database>, request: HttpRequest, game_id: Path<GameId>) -> AppResult {
-    let user_id = extract_user_id(&request)?;
-    let (game, _) = get_game_for_user(&db, *game_id, user_id)?;
-    let view = game.into(user_id);
-    ok(view)
+/// Creates an API token for one of the user's bots. The token is only returned here;
+/// only its hash is stored.
+#[post("/bots/{bot_id}/tokens")]
+async fn create_api_token(
+    db: Data<Store>,
+    version: Data<ApiVersion>,
+    user: AuthenticatedUser,
+    bot_id: Path<UserId>,
+) -> AppResult {
+    let (token, hash) = new_api_token();
+
+    match db.persist_api_token(*bot_id, user.id, &hash).await? {
+        None => Err(ApiError::not_found()),
+        Some(info) => Ok(version.respond(NewApiToken { info, token })),
+    }
 }
 
-#[put("/api/games/{game_id}")]
-async fn select_cards(
-    db: Data<Database>,
-    countdown_tx: Data<UnboundedSender<Countdown>>,
-    broadcaster: Data<Broadcaster>,
-    game_id: Path<GameId>,
-    selected_cards: Json<Vec<usize>>,
-    request: HttpRequest,
+#[delete("/bots/{bot_id}/tokens/{token_id}")]
+async fn revoke_api_token(
+    db: Data<Store>,
+    version: Data<ApiVersion>,
+    user: AuthenticatedUser,
+    path: Path<(UserId, i64)>,
 ) -> AppResult {
-    let user_id = extract_user_id(&request)?;
-    let game_id = *game_id;
-    let (mut game, version) = get_game_for_user(&db, game_id, user_id)?;
+    let (bot_id, token_id) = path.into_inner();
 
-    info!("Found game at version {}", version);
+    if db.delete_api_token(token_id, bot_id, user.id).await? {
+        Ok(version.done())
+    } else {
+        Err(ApiError::not_found())
+    }
+}
 
-    match game.select_cards(user_id, selected_cards.0) {
-        Err(e) => Ok(failure(e)),
-        Ok(events) => {
-            if !events.is_empty() {
-                countdown_tx
-                    .send(Countdown::Cancel { game_id })
-                    .expect("failed to send countdown cancel event");
+fn check_password(password: &str) -> std::result::Result<(), ApiError> {
+    let min = password::MIN_PASSWORD_LENGTH;
 
-                db.update_game(game_id, &game, version)?;
+    if password.chars().count() < min {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "password_too_short").with("min", min));
+    }
 
-                if game.ready_to_end_turn() {
-                    const COUNTDOWN_MS: u64 = 3000;
+    Ok(())
+}
 
-                    countdown_tx
-                        .send(Countdown::Start {
-                            game_id,
-                            duration: Duration::from_millis(COUNTDOWN_MS),
-                        })
-                        .expect("failed to send countdown started event");
-                }
+fn missing_name() -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, "missing_name")
+}
 
-                for event in &events {
-                    broadcaster.send(game_id, event).await;
-                }
-            }
+fn name_taken(name: &str) -> ApiError {
+    ApiError::new(StatusCode::CONFLICT, "name_taken").with("name", name)
+}
 
-            Ok(success(()))
-        }
+fn wrong_credentials() -> ApiError {
+    ApiError::new(StatusCode::UNAUTHORIZED, "wrong_credentials")
+}
+
+fn bot_not_allowed() -> ApiError {
+    ApiError::new(StatusCode::FORBIDDEN, "bot_not_allowed")
+}
+
+async fn issue_tokens(db: &Store, tokens: &Tokens, user_id: UserId, name: String) -> AppResult {
+    let role = db.get_user_role(user_id).await?.unwrap_or_default();
+    let locale = db.get_user_locale(user_id).await?;
+    let refresh_token = tokens.new_refresh_token();
+
+    db.persist_refresh_token(&refresh_token.hash, user_id, refresh_token.expires)
+        .await?;
+
+    ok(tokens.issue(user_id, name, role, locale, &refresh_token))
+}
+
+/// Runs slow password hashing on the blocking thread pool.
+async fn blocking<T, F>(f: F) -> std::result::Result<T, ApiError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_blocking(f).await.map_err(ApiError::internal)
+}
+
+#[get("/games")]
+async fn get_games(db: Data<Store>, user: AuthenticatedUser, query: Query<GameQuery>) -> AppResult {
+    info!("Getting games for {}", user.id);
+    let page = db.get_games_for_user(user.id, &query).await?;
+    ok(page)
+}
+
+#[get("/games/{game_id}")]
+async fn get_game(db: Data<Store>, actors: Data<GameActors>, member: GameMember) -> AppResult {
+    let mut view = actors.view(member.game_id, member.user.id).await?;
+    let bot_ids = db.get_bot_ids(member.game_id).await?;
+
+    for opponent in &mut view.opponents {
+        opponent.is_bot = bot_ids.contains(&opponent.id);
     }
+
+    let chat = db
+        .get_chat_messages(member.game_id, chat::HISTORY_LENGTH)
+        .await?;
+    ok(GameDetails { view, chat })
+}
+
+#[put("/games/{game_id}")]
+async fn select_cards(
+    actors: Data<GameActors>,
+    player: GamePlayer,
+    selected_cards: Json<Vec<usize>>,
+) -> AppResult {
+    actors
+        .select_cards(player.game_id, player.user_id, selected_cards.0)
+        .await??;
+
+    Ok(success(()))
+}
+
+#[post("/games/{game_id}/chat")]
+async fn send_chat(actors: Data<GameActors>, player: GamePlayer, text: Json<String>) -> AppResult {
+    let message = actors
+        .chat(player.game_id, player.user_id, text.0)
+        .await??;
+    Ok(success(message))
 }
 
-#[get("/api/games/{game_id}/stream")]
-async fn stream(game_id: Path<GameId>, broadcaster: Data<Broadcaster>) -> HttpResponse {
+#[get("/games/{game_id}/stream")]
+async fn stream(broadcaster: Data<Broadcaster>, member: GameMember) -> AppResult {
     let rx = broadcaster
-        .subscribe(*game_id)
+        .subscribe(member.game_id)
         .await
         .into_stream()
         .map(|r| r.map_err(|_| error::ErrorInternalServerError("")));
 
-    HttpResponseBuilder::new(StatusCode::OK)
+    Ok(HttpResponseBuilder::new(StatusCode::OK)
         .content_type("text/event-stream")
         .header("Cache-Control", "no-transform")
         .keep_alive()
         .no_chunking()
-        .streaming(rx)
+        .streaming(rx))
 }
 
-fn extract_user_id(request: &HttpRequest) -> ActixResult<UserId> {
-    let header = request
-        .headers()
-        .get("Authorization")
-        .ok_or_else(|| error::ErrorUnauthorized(""))?
-        .to_str()
-        .map_err(error::ErrorBadRequest)?;
+/// Proposes a rematch of a finished game, or accepts it if another player proposed it
+/// first. The new game starts once every player has accepted.
+#[post("/games/{game_id}/rematch")]
+async fn rematch(
+    db: Data<Store>,
+    broadcaster: Data<Broadcaster>,
+    version: Data<ApiVersion>,
+    player: GamePlayer,
+) -> AppResult {
+    let GamePlayer { user_id, game_id } = player;
+
+    loop {
+        if let Some(lobby_id) = db.find_rematch(game_id).await? {
+            let view = change_lobby(&db, lobby_id, Some(user_id), |lobby| {
+                if lobby.is_host(user_id) {
+                    Ok(())
+                } else {
+                    lobby.respond(user_id, true)
+                }
+            })
+            .await?;
 
-    let parts = header.split_whitespace().collect::<Vec<_>>();
+            return Ok(version.respond(view));
+        }
+
+        let game = match db.read_game(game_id).await? {
+            Some((game, _)) => game,
+            None => return Err(ApiError::not_found()),
+        };
 
-    match parts.as_slice() {
-        ["Bearer", jwt] => {
-            let mut validation = Validation::new(JWT_ALGORITHM);
-            validation.validate_exp = false;
+        let mut names = HashMap::new();
 
-            jsonwebtoken::decode::<Claims>(jwt, &DECODING_KEY, &validation)
-                .map(|c| c.claims.sub)
-                .map_err(error::ErrorUnauthorized)
+        for id in game.user_ids() {
+            if let Some(name) = db.get_user_name(id).await? {
+                names.insert(id, name);
+            }
         }
 
-        _ => Err(error::ErrorUnauthorized("")),
+        let lobby = Lobby::rematch(&game, user_id, names)?;
+
+        match db.persist_rematch(game_id, &lobby).await {
+            Ok(id) => {
+                let view = LobbyView {
+                    id,
+                    lobby,
+                    game_id: None,
+                };
+
+                broadcaster.send_rematch_offer(game_id, &view).await;
+                return Ok(version.respond(view));
+            }
+            Err(DbError::GameVersionConflict) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[get("/stream")]
+async fn user_stream(broadcaster: Data<Broadcaster>, user: AuthenticatedUser) -> AppResult {
+    let (missed, rx) = broadcaster.subscribe_user(user.id).await;
+    let rx = tokio::stream::iter(missed.into_iter().map(Ok)).chain(
+        rx.into_stream()
+            .map(|r| r.map_err(|_| error::ErrorInternalServerError(""))),
+    );
+
+    Ok(HttpResponseBuilder::new(StatusCode::OK)
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-transform")
+        .keep_alive()
+        .no_chunking()
+        .streaming(rx))
+}
+
+#[get("/openapi.json")]
+async fn get_openapi(version: Data<ApiVersion>) -> HttpResponse {
+    let spec = match **version {
+        ApiVersion::V1 => &*openapi::V1,
+        ApiVersion::V2 => &*openapi::V2,
+    };
+
+    HttpResponseBuilder::new(StatusCode::OK).json(spec)
+}
+
+#[post("/queue")]
+async fn enqueue(
+    matchmaker: Data<Matchmaker>,
+    user: AuthenticatedUser,
+    size: Json<usize>,
+) -> AppResult {
+    matchmaker.enqueue(user.id, *size).await?;
+    Ok(success(()))
+}
+
+#[delete("/queue")]
+async fn leave_queue(
+    matchmaker: Data<Matchmaker>,
+    version: Data<ApiVersion>,
+    user: AuthenticatedUser,
+) -> AppResult {
+    let left = matchmaker.leave(user.id).await;
+    Ok(version.respond(left))
+}
+
+#[get("/lobbies")]
+async fn get_lobbies(db: Data<Store>, user: AuthenticatedUser) -> AppResult {
+    let lobbies = db.get_lobbies_for_user(user.id).await?;
+    ok(lobbies)
+}
+
+#[post("/lobbies")]
+async fn create_lobby(
+    db: Data<Store>,
+    user: AuthenticatedUser,
+    opponents: Json<Vec<String>>,
+) -> AppResult {
+    let view = new_lobby(&db, user, &opponents).await?;
+    Ok(success(view.id))
+}
+
+/// Games used to be created directly, before invitees had to accept. Kept for clients
+/// from then; the id returned is of the lobby the game starts from.
+#[post("/games")]
+async fn create_game(
+    db: Data<Store>,
+    user: AuthenticatedUser,
+    opponents: Json<Vec<String>>,
+) -> AppResult {
+    let view = new_lobby(&db, user, &opponents).await?;
+    Ok(success(view.id))
+}
+
+async fn new_lobby(
+    db: &Store,
+    user: AuthenticatedUser,
+    opponents: &[String],
+) -> std::result::Result<LobbyView, ApiError> {
+    let mut lobby = Lobby::new(user.id, user.name);
+
+    for opponent in opponents {
+        let (user_id, name) = find_player(db, opponent).await?;
+        lobby.invite(user.id, user_id, name)?;
+    }
+
+    let id = db.persist_lobby(&lobby).await?;
+
+    Ok(LobbyView {
+        id,
+        lobby,
+        game_id: None,
+    })
+}
+
+#[post("/lobbies/open")]
+async fn open_lobby(db: Data<Store>, user: AuthenticatedUser, capacity: Json<usize>) -> AppResult {
+    let view = new_open_lobby(&db, user, *capacity).await?;
+    Ok(success(view))
+}
+
+async fn new_open_lobby(
+    db: &Store,
+    user: AuthenticatedUser,
+    capacity: usize,
+) -> std::result::Result<LobbyView, ApiError> {
+    let lobby = Lobby::open(user.id, user.name, capacity)?;
+
+    let id = db.persist_lobby(&lobby).await?;
+
+    Ok(LobbyView {
+        id,
+        lobby,
+        game_id: None,
+    })
+}
+
+#[post("/lobbies/join")]
+async fn join_lobby(db: Data<Store>, user: AuthenticatedUser, code: Json<String>) -> AppResult {
+    let view = join_by_code(&db, user, &code).await?;
+    Ok(success(view))
+}
+
+async fn join_by_code(
+    db: &Store,
+    user: AuthenticatedUser,
+    code: &str,
+) -> std::result::Result<LobbyView, ApiError> {
+    let code = code.trim().to_uppercase();
+
+    let lobby_id = match db.find_lobby_by_code(&code).await? {
+        None => return Err(ApiError::not_found()),
+        Some(lobby_id) => lobby_id,
+    };
+
+    change_lobby(db, lobby_id, None, |lobby| {
+        lobby.join(user.id, user.name.clone())
+    })
+    .await
+}
+
+#[get("/lobbies/{lobby_id}")]
+async fn get_lobby(db: Data<Store>, user: AuthenticatedUser, lobby_id: Path<LobbyId>) -> AppResult {
+    match db.read_lobby(*lobby_id).await? {
+        Some((view, _)) if view.lobby.is_seated(user.id) => ok(view),
+        _ => Err(ApiError::not_found()),
     }
 }
 
-fn get_game_for_user(db: &Database, game_id: GameId, user_id: UserId) -> ActixResult<(Game, u8)> {
-    db.read_game_for_user(game_id, user_id)?
-        .ok_or_else(|| error::ErrorNotFound(""))
+#[post("/lobbies/{lobby_id}/seats")]
+async fn invite_player(
+    db: Data<Store>,
+    host: AuthenticatedUser,
+    lobby_id: Path<LobbyId>,
+    name: Json<String>,
+) -> AppResult {
+    let view = invite_by_name(&db, host.id, *lobby_id, &name).await?;
+    Ok(success(view))
+}
+
+async fn invite_by_name(
+    db: &Store,
+    host: UserId,
+    lobby_id: LobbyId,
+    name: &str,
+) -> std::result::Result<LobbyView, ApiError> {
+    let (user_id, name) = find_player(db, name).await?;
+
+    change_lobby(db, lobby_id, Some(host), |lobby| {
+        lobby.invite(host, user_id, name.clone())
+    })
+    .await
+}
+
+#[delete("/lobbies/{lobby_id}/seats/{user_id}")]
+async fn remove_player(
+    db: Data<Store>,
+    version: Data<ApiVersion>,
+    host: AuthenticatedUser,
+    path: Path<(LobbyId, UserId)>,
+) -> AppResult {
+    let host = host.id;
+    let (lobby_id, user_id) = path.into_inner();
+    let view = change_lobby(&db, lobby_id, Some(host), |lobby| {
+        lobby.remove(host, user_id)
+    })
+    .await?;
+    Ok(version.respond(view))
+}
+
+#[post("/lobbies/{lobby_id}/accept")]
+async fn accept_invitation(
+    db: Data<Store>,
+    version: Data<ApiVersion>,
+    user: AuthenticatedUser,
+    lobby_id: Path<LobbyId>,
+) -> AppResult {
+    let view = change_lobby(&db, *lobby_id, Some(user.id), |lobby| {
+        lobby.respond(user.id, true)
+    })
+    .await?;
+    Ok(version.respond(view))
+}
+
+#[post("/lobbies/{lobby_id}/decline")]
+async fn decline_invitation(
+    db: Data<Store>,
+    version: Data<ApiVersion>,
+    user: AuthenticatedUser,
+    lobby_id: Path<LobbyId>,
+) -> AppResult {
+    let view = change_lobby(&db, *lobby_id, Some(user.id), |lobby| {
+        lobby.respond(user.id, false)
+    })
+    .await?;
+    Ok(version.respond(view))
+}
+
+#[post("/lobbies/{lobby_id}/start")]
+async fn start_lobby(
+    db: Data<Store>,
+    version: Data<ApiVersion>,
+    host: AuthenticatedUser,
+    lobby_id: Path<LobbyId>,
+) -> AppResult {
+    let host = host.id;
+    let view = change_lobby(&db, *lobby_id, Some(host), |lobby| lobby.start(host)).await?;
+    Ok(version.respond(view))
+}
+
+/// Looks up an existing player by name. Players are never created here, so a typo is
+/// reported instead of inviting someone who does not exist.
+async fn find_player(db: &Store, name: &str) -> std::result::Result<(UserId, String), ApiError> {
+    let trimmed = name.trim();
+
+    if trimmed.is_empty() {
+        return Err(missing_name());
+    }
+
+    match db.get_user_id(trimmed).await? {
+        None => Err(ApiError::new(StatusCode::NOT_FOUND, "unknown_player").with("name", trimmed)),
+        Some(user_id) => Ok((user_id, trimmed.to_string())),
+    }
+}
+
+/// Applies `change` to a lobby and persists the game if that dealt the cards. Lobbies are
+/// only found by users `seated` in them, unless joined by code. Concurrent changes are
+/// retried against the latest lobby.
+async fn change_lobby<F>(
+    db: &Store,
+    lobby_id: LobbyId,
+    seated: Option<UserId>,
+    change: F,
+) -> std::result::Result<LobbyView, ApiError>
+where
+    F: Fn(&mut Lobby) -> std::result::Result<(), LobbyError>,
+{
+    loop {
+        let (mut view, version) = match db.read_lobby(lobby_id).await? {
+            Some((view, version)) if seated.is_none_or(|u| view.lobby.is_seated(u)) => {
+                (view, version)
+            }
+            _ => return Err(ApiError::not_found()),
+        };
+
+        change(&mut view.lobby)?;
+
+        let game = view.lobby.take_game();
+
+        match db
+            .update_lobby(lobby_id, &view.lobby, version, game.as_ref())
+            .await
+        {
+            Ok(game_id) => {
+                view.game_id = view.game_id.or(game_id);
+                return Ok(view);
+            }
+            Err(DbError::GameVersionConflict) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
 }
 
 fn ok<T: Serialize>(payload: T) -> AppResult {
@@ -221,7 +805,7 @@ fn ok<T: Serialize>(payload: T) -> AppResult {
 struct ResponseBody<T> {
     success: bool,
     payload: Option<T>,
-    error: Option<String>,
+    error: Option<()>,
 }
 
 fn success<T: Serialize>(payload: T) -> HttpResponse {
@@ -231,20 +815,3 @@ fn success<T: Serialize>(payload: T) -> HttpResponse {
         error: None,
     })
 }
-
-fn failure<E: Display>(error: E) -> HttpResponse {
-    HttpResponseBuilder::new(StatusCode::OK).json(ResponseBody {
-        success: false,
-        payload: Option::<()>::None,
-        error: Some(error.to_string()),
-    })
-}
-
-impl ResponseError for DbError {
-    fn status_code(&self) -> StatusCode {
-        match self {
-            DbError::RusqliteError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            DbError::GameVersionConflict => StatusCode::CONFLICT,
-        }
-    }
-}
@@ -0,0 +1,424 @@
+use super::*;
+
+/// Registers version 2, which takes objects rather than bare JSON values as request
+/// bodies, so fields can be added without breaking clients. Only the routes whose bodies
+/// differ are defined here; the rest are shared with version 1.
+pub(super) fn routes(cfg: &mut ServiceConfig) {
+    cfg.service(super::register)
+        .service(super::login)
+        .service(refresh)
+        .service(logout)
+        .service(claim_account)
+        .service(set_locale)
+        .service(super::get_bots)
+        .service(create_bot)
+        .service(super::create_api_token)
+        .service(super::revoke_api_token)
+        .service(super::get_games)
+        .service(create_game)
+        .service(super::get_game)
+        .service(select_cards)
+        .service(send_chat)
+        .service(super::stream)
+        .service(super::rematch)
+        .service(super::get_lobbies)
+        .service(create_lobby)
+        .service(open_lobby)
+        .service(join_lobby)
+        .service(super::get_lobby)
+        .service(invite_player)
+        .service(super::remove_player)
+        .service(super::accept_invitation)
+        .service(super::decline_invitation)
+        .service(super::start_lobby)
+        .service(enqueue)
+        .service(super::leave_queue)
+        .service(super::user_stream)
+        .service(super::get_openapi);
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshToken {
+    refresh_token: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct Password {
+    password: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct LocalePreference {
+    locale: Option<Locale>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct Name {
+    name: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SelectedCards {
+    cards: Vec<usize>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ChatText {
+    text: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct GameSize {
+    size: usize,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct Opponents {
+    opponents: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct Capacity {
+    capacity: usize,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct LobbyCode {
+    code: String,
+}
+
+#[post("/token/refresh")]
+async fn refresh(db: Data<Store>, tokens: Data<Tokens>, body: Json<RefreshToken>) -> AppResult {
+    refresh_tokens(&db, &tokens, &body.refresh_token).await
+}
+
+#[post("/logout")]
+async fn logout(db: Data<Store>, body: Json<RefreshToken>) -> AppResult {
+    revoke_refresh_token(&db, &body.refresh_token).await?;
+    Ok(ApiVersion::V2.done())
+}
+
+#[post("/account/password")]
+async fn claim_account(
+    db: Data<Store>,
+    user: AuthenticatedUser,
+    body: Json<Password>,
+) -> AppResult {
+    set_password(&db, &user, body.into_inner().password).await?;
+    Ok(ApiVersion::V2.done())
+}
+
+#[put("/account/locale")]
+async fn set_locale(
+    db: Data<Store>,
+    tokens: Data<Tokens>,
+    user: AuthenticatedUser,
+    body: Json<LocalePreference>,
+) -> AppResult {
+    change_locale(&db, &tokens, user, body.locale).await
+}
+
+#[post("/bots")]
+async fn create_bot(db: Data<Store>, user: AuthenticatedUser, body: Json<Name>) -> AppResult {
+    let bot = add_bot(&db, &user, &body.name).await?;
+    ok(bot)
+}
+
+#[put("/games/{game_id}")]
+async fn select_cards(
+    actors: Data<GameActors>,
+    player: GamePlayer,
+    body: Json<SelectedCards>,
+) -> AppResult {
+    actors
+        .select_cards(player.game_id, player.user_id, body.into_inner().cards)
+        .await??;
+
+    Ok(ApiVersion::V2.done())
+}
+
+#[post("/games/{game_id}/chat")]
+async fn send_chat(
+    actors: Data<GameActors>,
+    player: GamePlayer,
+    body: Json<ChatText>,
+) -> AppResult {
+    let message = actors
+        .chat(player.game_id, player.user_id, body.into_inner().text)
+        .await??;
+    ok(message)
+}
+
+#[post("/queue")]
+async fn enqueue(
+    matchmaker: Data<Matchmaker>,
+    user: AuthenticatedUser,
+    body: Json<GameSize>,
+) -> AppResult {
+    matchmaker.enqueue(user.id, body.size).await?;
+    Ok(ApiVersion::V2.done())
+}
+
+/// Unlike version 1, which only returns the id, returns the lobby like every other
+/// lobby route.
+#[post("/lobbies")]
+async fn create_lobby(
+    db: Data<Store>,
+    user: AuthenticatedUser,
+    body: Json<Opponents>,
+) -> AppResult {
+    let view = new_lobby(&db, user, &body.opponents).await?;
+    ok(view)
+}
+
+#[post("/games")]
+async fn create_game(db: Data<Store>, user: AuthenticatedUser, body: Json<Opponents>) -> AppResult {
+    let view = new_lobby(&db, user, &body.opponents).await?;
+    ok(view.id)
+}
+
+#[post("/lobbies/open")]
+async fn open_lobby(db: Data<Store>, user: AuthenticatedUser, body: Json<Capacity>) -> AppResult {
+    let view = new_open_lobby(&db, user, body.capacity).await?;
+    ok(view)
+}
+
+#[post("/lobbies/join")]
+async fn join_lobby(db: Data<Store>, user: AuthenticatedUser, body: Json<LobbyCode>) -> AppResult {
+    let view = join_by_code(&db, user, &body.code).await?;
+    ok(view)
+}
+
+#[post("/lobbies/{lobby_id}/seats")]
+async fn invite_player(
+    db: Data<Store>,
+    host: AuthenticatedUser,
+    lobby_id: Path<LobbyId>,
+    body: Json<Name>,
+) -> AppResult {
+    let view = invite_by_name(&db, host.id, *lobby_id, &body.name).await?;
+    ok(view)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::tokens::new_claim_code;
+    use super::*;
+    use actix_web::{test, App};
+    use chrono::Duration;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn configure(cfg: &mut ServiceConfig) {
+        configure_with(Arc::new(MemoryStore::new()), cfg);
+    }
+
+    fn configure_with(db: Store, cfg: &mut ServiceConfig) {
+        let broadcaster = Broadcaster::new();
+        let countdown = std::time::Duration::from_secs(1);
+        let actors = GameActors::new(db.clone(), broadcaster.clone(), countdown);
+        let matchmaker = Matchmaker::new(db.clone(), broadcaster.clone());
+        let keys = vec![("a".to_string(), b"secret".to_vec())];
+        let tokens = Tokens::new(keys, Duration::minutes(15), Duration::days(30));
+
+        app(db, broadcaster, actors, matchmaker, tokens, cfg);
+    }
+
+    fn register(prefix: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(&format!("{}/register", prefix))
+            .set_json(&json!({ "name": "a", "password": "password" }))
+    }
+
+    #[actix_rt::test]
+    async fn version_2_takes_objects() {
+        // arrange
+        let mut app = test::init_service(App::new().configure(configure)).await;
+        let tokens: Value =
+            test::read_response_json(&mut app, register("/api/v2").to_request()).await;
+        let request = test::TestRequest::post()
+            .uri("/api/v2/logout")
+            .set_json(&json!({ "refreshToken": tokens["refreshToken"] }))
+            .to_request();
+
+        // act
+        let response = test::call_service(&mut app, request).await;
+
+        // assert
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[actix_rt::test]
+    async fn version_2_rejects_bare_values() {
+        // arrange
+        let mut app = test::init_service(App::new().configure(configure)).await;
+        let tokens: Value =
+            test::read_response_json(&mut app, register("/api/v2").to_request()).await;
+        let request = test::TestRequest::post()
+            .uri("/api/v2/logout")
+            .set_json(&tokens["refreshToken"])
+            .to_request();
+
+        // act
+        let response = test::call_service(&mut app, request).await;
+
+        // assert
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn claim_code_can_claim_account() {
+        for prefix in &["/api/v1", "/api/v2"] {
+            // arrange
+            let db: Store = Arc::new(MemoryStore::new());
+            let user_id = db.get_or_insert_user_id("a").await.unwrap();
+            let code = new_claim_code();
+            db.persist_refresh_token(&code.hash, user_id, code.expires)
+                .await
+                .unwrap();
+            let db_for_app = db.clone();
+            let mut app =
+                test::init_service(App::new().configure(|cfg| configure_with(db_for_app, cfg)))
+                    .await;
+            let (refresh_body, password_body) = match *prefix {
+                "/api/v1" => (json!(code.token), json!("password")),
+                _ => (
+                    json!({ "refreshToken": code.token }),
+                    json!({ "password": "password" }),
+                ),
+            };
+            let request = test::TestRequest::post()
+                .uri(&format!("{}/token/refresh", prefix))
+                .set_json(&refresh_body)
+                .to_request();
+            let tokens: Value = test::read_response_json(&mut app, request).await;
+            let request = test::TestRequest::post()
+                .uri(&format!("{}/account/password", prefix))
+                .header(
+                    "Authorization",
+                    format!("Bearer {}", tokens["accessToken"].as_str().unwrap()),
+                )
+                .set_json(&password_body)
+                .to_request();
+
+            // act
+            let response = test::call_service(&mut app, request).await;
+
+            // assert
+            assert!(response.status().is_success());
+            let (_, password_hash) = db.get_credentials("a").await.unwrap().unwrap();
+            assert!(password_hash.is_some());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn token_signed_with_old_built_in_secret_cannot_claim_account() {
+        // arrange
+        let db: Store = Arc::new(MemoryStore::new());
+        let user_id = db.get_or_insert_user_id("a").await.unwrap();
+        let mut app = test::init_service(App::new().configure(|cfg| configure_with(db, cfg))).await;
+        let jwt = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &json!({ "sub": user_id, "name": "a" }),
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        let request = test::TestRequest::post()
+            .uri("/api/v2/account/password")
+            .header("Authorization", format!("Bearer {}", jwt))
+            .set_json(&json!({ "password": "password" }))
+            .to_request();
+
+        // act
+        let response = test::call_service(&mut app, request).await;
+
+        // assert
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn login_does_not_tell_accounts_without_password_apart() {
+        // arrange
+        let db: Store = Arc::new(MemoryStore::new());
+        db.get_or_insert_user_id("a").await.unwrap();
+        let mut app = test::init_service(App::new().configure(|cfg| configure_with(db, cfg))).await;
+        let log_in = |name: &str| {
+            test::TestRequest::post()
+                .uri("/api/v2/login")
+                .set_json(&json!({ "name": name, "password": "password" }))
+                .to_request()
+        };
+
+        // act
+        let without_password: Value = test::read_response_json(&mut app, log_in("a")).await;
+        let unknown: Value = test::read_response_json(&mut app, log_in("b")).await;
+
+        // assert
+        assert_eq!(without_password["error"]["code"], "wrong_credentials");
+        assert_eq!(without_password, unknown);
+    }
+
+    #[actix_rt::test]
+    async fn game_stream_requires_a_token() {
+        // arrange
+        let mut app = test::init_service(App::new().configure(configure)).await;
+        let request = test::TestRequest::get()
+            .uri("/api/v2/games/1/stream")
+            .header("Accept", "text/event-stream")
+            .to_request();
+
+        // act
+        let response = test::call_service(&mut app, request).await;
+
+        // assert
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn unversioned_paths_are_version_1() {
+        for prefix in &["/api/v1", "/api"] {
+            // arrange
+            let mut app = test::init_service(App::new().configure(configure)).await;
+            let tokens: Value =
+                test::read_response_json(&mut app, register(prefix).to_request()).await;
+            let request = test::TestRequest::post()
+                .uri(&format!("{}/logout", prefix))
+                .set_json(&tokens["refreshToken"])
+                .to_request();
+
+            // act
+            let response: Value = test::read_response_json(&mut app, request).await;
+
+            // assert
+            assert_eq!(
+                response,
+                json!({ "success": true, "payload": null, "error": null })
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn creating_a_game_creates_a_lobby() {
+        // arrange
+        let mut app = test::init_service(App::new().configure(configure)).await;
+        let tokens: Value = test::read_response_json(&mut app, register("/api").to_request()).await;
+        let bearer = format!("Bearer {}", tokens["accessToken"].as_str().unwrap());
+        let request = test::TestRequest::post()
+            .uri("/api/games")
+            .header("Authorization", bearer.as_str())
+            .set_json(&json!([]))
+            .to_request();
+
+        // act
+        let response: Value = test::read_response_json(&mut app, request).await;
+
+        // assert
+        let request = test::TestRequest::get()
+            .uri(&format!("/api/lobbies/{}", response["payload"]))
+            .header("Authorization", bearer.as_str())
+            .to_request();
+        let lobby: Value = test::read_response_json(&mut app, request).await;
+        assert_eq!(lobby["id"], response["payload"]);
+    }
+}
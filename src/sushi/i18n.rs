@@ -0,0 +1,365 @@
+use actix_web::{http::header, HttpRequest};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::str::FromStr;
+
+/// A language that error messages are translated into. Users can store a preference;
+/// otherwise it is negotiated from the `Accept-Language` header.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Da,
+}
+
+impl Locale {
+    /// Picks the supported language the client prefers most, ignoring regions, e.g.
+    /// `da` for `da-DK,da;q=0.9,en;q=0.8`.
+    pub fn negotiate(accept_language: &str) -> Option<Locale> {
+        let mut best: Option<(Locale, f32)> = None;
+
+        for range in accept_language.split(',') {
+            let mut parts = range.split(';').map(str::trim);
+            let tag = parts.next().unwrap_or_default();
+            let language = tag.split('-').next().unwrap_or_default();
+
+            let quality = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse().ok())
+                .unwrap_or(0.0);
+
+            if let Ok(locale) = language.to_lowercase().parse() {
+                if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+                    best = Some((locale, quality));
+                }
+            }
+        }
+
+        best.map(|(locale, _)| locale)
+    }
+
+    /// The locale of the logged in user's preference if they have one, otherwise the one
+    /// negotiated from the request.
+    pub fn of(request: &HttpRequest) -> Locale {
+        if let Some(locale) = request.extensions().get::<Locale>() {
+            return *locale;
+        }
+
+        request
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Locale::negotiate)
+            .unwrap_or_default()
+    }
+
+    fn plural(self, n: u64) -> Plural {
+        match self {
+            Locale::En | Locale::Da if n == 1 => Plural::One,
+            Locale::En | Locale::Da => Plural::Other,
+        }
+    }
+}
+
+impl Display for Locale {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            Locale::En => write!(f, "en"),
+            Locale::Da => write!(f, "da"),
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Locale::En),
+            "da" => Ok(Locale::Da),
+            _ => Err(format!("Unsupported locale '{}'", s)),
+        }
+    }
+}
+
+enum Plural {
+    One,
+    Other,
+}
+
+/// A message in the catalog. `{name}` is replaced by the parameter of that name; plural
+/// messages are chosen by the number in the `count` parameter.
+enum Text {
+    Plain(&'static str),
+    Plural {
+        count: &'static str,
+        one: &'static str,
+        other: &'static str,
+    },
+}
+
+/// Translates the message with the given code, falling back to English and then to the
+/// code itself.
+///
+/// The catalog only holds error messages. Stream events, such as a match being found or
+/// a rematch being offered, carry data and no text, so clients word those themselves.
+pub fn translate(locale: Locale, code: &str, params: &Map<String, Value>) -> String {
+    let text = match locale {
+        Locale::En => english(code),
+        Locale::Da => danish(code).or_else(|| english(code)),
+    };
+
+    let template = match text {
+        None => return code.to_string(),
+        Some(Text::Plain(template)) => template,
+        Some(Text::Plural { count, one, other }) => {
+            let n = params
+                .get(count)
+                .and_then(Value::as_u64)
+                .unwrap_or_default();
+
+            match locale.plural(n) {
+                Plural::One => one,
+                Plural::Other => other,
+            }
+        }
+    };
+
+    params
+        .iter()
+        .fold(template.to_string(), |message, (name, value)| {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+
+            message.replace(&format!("{{{}}}", name), &value)
+        })
+}
+
+fn english(code: &str) -> Option<Text> {
+    use Text::*;
+
+    let text = match code {
+        "unauthorized" => Plain("You must be logged in"),
+        "forbidden" => Plain("You do not have access to this"),
+        "not_found" => Plain("Not found"),
+        "internal" => Plain("Something went wrong"),
+        "conflict" => Plain("Someone else changed this at the same time; try again"),
+        "bad_request" => Plain("The request is invalid"),
+        "method_not_allowed" => Plain("Method not allowed"),
+        "payload_too_large" => Plain("The request is too large"),
+        "unsupported_media_type" => Plain("Unsupported media type"),
+        "too_many_requests" => Plain("Too many requests; try again later"),
+        "too_few_players" => Plural {
+            count: "toAdd",
+            one: "You must add at least {toAdd} more player to the game.",
+            other: "You must add at least {toAdd} more players to the game.",
+        },
+        "too_many_players" => Plural {
+            count: "toRemove",
+            one: "Remove at least {toRemove} player from the game.",
+            other: "Remove at least {toRemove} players from the game.",
+        },
+        "not_host" => Plain("Only the host can change the seats"),
+        "not_invited" => Plain("You have not been invited to this game"),
+        "not_seated" => Plain("That player is not in the game"),
+        "already_seated" => Plain("Player '{name}' is already in the game"),
+        "cannot_remove_host" => Plain("The host cannot be removed"),
+        "already_started" => Plain("The game has already started"),
+        "not_open" => Plain("This game can only be joined by invitation"),
+        "lobby_full" => Plain("The game is full with {capacity} players"),
+        "not_everyone_accepted" => Plain("Not everyone has accepted the invitation yet"),
+        "game_not_over" => Plain("The game is not over yet"),
+        "too_many_cards" => Plain("Too many cards selected"),
+        "no_chopsticks" => Plain("Only one card can be played without chopsticks"),
+        "does_not_have_card" => Plain("You do not have that card"),
+        "message_empty" => Plain("The message is empty"),
+        "message_too_long" => Plural {
+            count: "max",
+            one: "Messages can be at most {max} character long",
+            other: "Messages can be at most {max} characters long",
+        },
+        "too_many_messages" => Plain("You are sending messages too quickly"),
+        "missing_name" => Plain("Enter a name"),
+        "password_too_short" => Plural {
+            count: "min",
+            one: "Passwords must be at least {min} character long",
+            other: "Passwords must be at least {min} characters long",
+        },
+        "name_taken" => Plain("The name '{name}' is already taken"),
+        "wrong_credentials" => Plain("Wrong user name or password"),
+        "invalid_refresh_token" => Plain("The refresh token is invalid or has expired"),
+        "password_already_set" => Plain("Your account already has a password"),
+        "bot_not_allowed" => Plain("Bots cannot do this"),
+        "unknown_player" => Plain("There is no player named '{name}'"),
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+fn danish(code: &str) -> Option<Text> {
+    use Text::*;
+
+    let text = match code {
+        "unauthorized" => Plain("Du skal være logget ind"),
+        "forbidden" => Plain("Du har ikke adgang til dette"),
+        "not_found" => Plain("Ikke fundet"),
+        "internal" => Plain("Noget gik galt"),
+        "conflict" => Plain("En anden ændrede dette samtidig; prøv igen"),
+        "bad_request" => Plain("Forespørgslen er ugyldig"),
+        "method_not_allowed" => Plain("Metoden er ikke tilladt"),
+        "payload_too_large" => Plain("Forespørgslen er for stor"),
+        "unsupported_media_type" => Plain("Medietypen understøttes ikke"),
+        "too_many_requests" => Plain("For mange forespørgsler; prøv igen senere"),
+        "too_few_players" => Plural {
+            count: "toAdd",
+            one: "Du skal tilføje mindst {toAdd} spiller mere til spillet.",
+            other: "Du skal tilføje mindst {toAdd} spillere mere til spillet.",
+        },
+        "too_many_players" => Plural {
+            count: "toRemove",
+            one: "Fjern mindst {toRemove} spiller fra spillet.",
+            other: "Fjern mindst {toRemove} spillere fra spillet.",
+        },
+        "not_host" => Plain("Kun værten kan ændre pladserne"),
+        "not_invited" => Plain("Du er ikke inviteret til dette spil"),
+        "not_seated" => Plain("Den spiller er ikke med i spillet"),
+        "already_seated" => Plain("Spilleren '{name}' er allerede med i spillet"),
+        "cannot_remove_host" => Plain("Værten kan ikke fjernes"),
+        "already_started" => Plain("Spillet er allerede begyndt"),
+        "not_open" => Plain("Man kan kun deltage i dette spil med en invitation"),
+        "lobby_full" => Plain("Spillet er fuldt med {capacity} spillere"),
+        "not_everyone_accepted" => Plain("Ikke alle har accepteret invitationen endnu"),
+        "game_not_over" => Plain("Spillet er ikke slut endnu"),
+        "too_many_cards" => Plain("Der er valgt for mange kort"),
+        "no_chopsticks" => Plain("Uden spisepinde kan der kun spilles ét kort"),
+        "does_not_have_card" => Plain("Du har ikke det kort"),
+        "message_empty" => Plain("Beskeden er tom"),
+        "message_too_long" => Plain("Beskeder må højst være {max} tegn lange"),
+        "too_many_messages" => Plain("Du sender beskeder for hurtigt"),
+        "missing_name" => Plain("Indtast et navn"),
+        "password_too_short" => Plain("Adgangskoder skal være mindst {min} tegn lange"),
+        "name_taken" => Plain("Navnet '{name}' er allerede taget"),
+        "wrong_credentials" => Plain("Forkert brugernavn eller adgangskode"),
+        "invalid_refresh_token" => Plain("Fornyelsestokenet er ugyldigt eller udløbet"),
+        "password_already_set" => Plain("Din konto har allerede en adgangskode"),
+        "bot_not_allowed" => Plain("Bots kan ikke gøre dette"),
+        "unknown_player" => Plain("Der er ingen spiller ved navn '{name}'"),
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn params(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap_or_default()
+    }
+
+    #[test]
+    fn every_english_message_is_translated() {
+        let codes = &[
+            "unauthorized",
+            "forbidden",
+            "not_found",
+            "internal",
+            "conflict",
+            "bad_request",
+            "method_not_allowed",
+            "payload_too_large",
+            "unsupported_media_type",
+            "too_many_requests",
+            "too_few_players",
+            "too_many_players",
+            "not_host",
+            "not_invited",
+            "not_seated",
+            "already_seated",
+            "cannot_remove_host",
+            "already_started",
+            "not_open",
+            "lobby_full",
+            "not_everyone_accepted",
+            "game_not_over",
+            "too_many_cards",
+            "no_chopsticks",
+            "does_not_have_card",
+            "message_empty",
+            "message_too_long",
+            "too_many_messages",
+            "missing_name",
+            "password_too_short",
+            "name_taken",
+            "wrong_credentials",
+            "invalid_refresh_token",
+            "password_already_set",
+            "bot_not_allowed",
+            "unknown_player",
+        ];
+
+        for code in codes {
+            assert!(english(code).is_some(), "{} is not in English", code);
+            assert!(danish(code).is_some(), "{} is not in Danish", code);
+        }
+    }
+
+    #[test]
+    fn plural_messages_depend_on_count() {
+        // arrange
+        let one = params(json!({ "toRemove": 1, "max": 5 }));
+        let two = params(json!({ "toRemove": 2, "max": 5 }));
+
+        // act
+        let english_one = translate(Locale::En, "too_many_players", &one);
+        let english_two = translate(Locale::En, "too_many_players", &two);
+        let danish_two = translate(Locale::Da, "too_many_players", &two);
+
+        // assert
+        assert_eq!(english_one, "Remove at least 1 player from the game.");
+        assert_eq!(english_two, "Remove at least 2 players from the game.");
+        assert_eq!(danish_two, "Fjern mindst 2 spillere fra spillet.");
+    }
+
+    #[test]
+    fn string_params_are_inserted_without_quotes() {
+        // act
+        let message = translate(
+            Locale::Da,
+            "name_taken",
+            &params(json!({ "name": "alice" })),
+        );
+
+        // assert
+        assert_eq!(message, "Navnet 'alice' er allerede taget");
+    }
+
+    #[test]
+    fn unknown_code_is_used_as_message() {
+        assert_eq!(translate(Locale::Da, "nope", &Map::new()), "nope");
+    }
+
+    #[test]
+    fn negotiates_preferred_supported_language() {
+        assert_eq!(
+            Locale::negotiate("da-DK,da;q=0.9,en;q=0.8"),
+            Some(Locale::Da)
+        );
+        assert_eq!(
+            Locale::negotiate("de, en;q=0.5, da;q=0.7"),
+            Some(Locale::Da)
+        );
+        assert_eq!(Locale::negotiate("EN-us"), Some(Locale::En));
+        assert_eq!(Locale::negotiate("da;q=0, en;q=0.1"), Some(Locale::En));
+        assert_eq!(Locale::negotiate("de, fr"), None);
+        assert_eq!(Locale::negotiate(""), None);
+    }
+}
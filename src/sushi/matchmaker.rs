@@ -0,0 +1,261 @@
+use super::db::Store;
+use super::domain::*;
+use super::pubsub::{Broadcaster, UserEvent};
+use linked_hash_set::LinkedHashSet;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    Mutex,
+};
+
+type Queues = Arc<Mutex<HashMap<usize, LinkedHashSet<UserId>>>>;
+
+/// Queues users who want to play a game of a given size against anyone. A background
+/// matcher groups queued users into games in the order they joined the queue and
+/// notifies each of them when their game is ready.
+#[derive(Clone)]
+pub struct Matchmaker {
+    queues: Queues,
+    tx: UnboundedSender<usize>,
+}
+
+impl Matchmaker {
+    pub fn new(db: Store, broadcaster: Broadcaster) -> Self {
+        let queues = Queues::default();
+        let (tx, rx) = unbounded_channel();
+
+        let matcher = Matcher {
+            db,
+            broadcaster,
+            queues: queues.clone(),
+        };
+
+        tokio::spawn(matcher.run(rx));
+        Self { queues, tx }
+    }
+
+    /// Queues the user for a game of `size` players. A user waits in one queue at a time,
+    /// so queueing again replaces any earlier request.
+    pub async fn enqueue(&self, user_id: UserId, size: usize) -> Result<(), CreateGameError> {
+        if size < MIN_GAME_SIZE {
+            return Err(CreateGameError::TooFewPlayers(size));
+        }
+
+        if size > MAX_GAME_SIZE {
+            return Err(CreateGameError::TooManyPlayers(size));
+        }
+
+        {
+            let mut guard = self.queues.lock().await;
+
+            for queue in guard.values_mut() {
+                queue.remove(&user_id);
+            }
+
+            guard.entry(size).or_default().insert(user_id);
+        }
+
+        info!("User {} queued for a game of {}", user_id, size);
+
+        if self.tx.send(size).is_err() {
+            error!("Matcher stopped; user {} will not be matched", user_id);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the user from the queue. Returns false if the user was not queued.
+    pub async fn leave(&self, user_id: UserId) -> bool {
+        let mut guard = self.queues.lock().await;
+        guard.values_mut().any(|queue| queue.remove(&user_id))
+    }
+}
+
+struct Matcher {
+    db: Store,
+    broadcaster: Broadcaster,
+    queues: Queues,
+}
+
+impl Matcher {
+    async fn run(self, mut rx: UnboundedReceiver<usize>) {
+        while let Some(size) = rx.recv().await {
+            self.match_players(size).await;
+        }
+    }
+
+    async fn match_players(&self, size: usize) {
+        loop {
+            let user_ids: LinkedHashSet<_> = {
+                let mut guard = self.queues.lock().await;
+
+                match guard.get_mut(&size) {
+                    Some(queue) if queue.len() >= size => {
+                        (0..size).filter_map(|_| queue.pop_front()).collect()
+                    }
+                    _ => return,
+                }
+            };
+
+            let game = match Game::try_from(user_ids.clone()) {
+                Err(e) => {
+                    error!("failed to create matched game because {}", e);
+                    return;
+                }
+                Ok(game) => game,
+            };
+
+            let game_id = match self.db.persist_game(&game).await {
+                Err(e) => {
+                    error!("failed to persist matched game because {}", e);
+                    self.requeue(size, user_ids).await;
+                    return;
+                }
+                Ok(game_id) => game_id,
+            };
+
+            info!("Matched {:?} into game {}", user_ids, game_id);
+
+            for user_id in user_ids {
+                self.broadcaster
+                    .send_to_user(user_id, &UserEvent::MatchFound(game_id))
+                    .await;
+            }
+        }
+    }
+
+    /// Puts users back at the front of the queue, ahead of anyone who joined since.
+    async fn requeue(&self, size: usize, mut user_ids: LinkedHashSet<UserId>) {
+        let mut guard = self.queues.lock().await;
+        let queue = guard.entry(size).or_default();
+        user_ids.extend(std::mem::take(queue));
+        *queue = user_ids;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::db::{GameQuery, MemoryStore};
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    lazy_static! {
+        static ref PLAYER1: UserId = UserId(1);
+        static ref PLAYER2: UserId = UserId(2);
+        static ref PLAYER3: UserId = UserId(3);
+    }
+
+    async fn setup() -> (Matchmaker, Store, Broadcaster) {
+        let db: Store = Arc::new(MemoryStore::new());
+
+        for name in &["a", "b", "c"] {
+            db.get_or_insert_user_id(name).await.unwrap();
+        }
+
+        let broadcaster = Broadcaster::new();
+        let matchmaker = Matchmaker::new(db.clone(), broadcaster.clone());
+        (matchmaker, db, broadcaster)
+    }
+
+    #[actix_rt::test]
+    async fn queued_users_are_matched_into_game() {
+        // arrange
+        let (matchmaker, db, broadcaster) = setup().await;
+        let (_, mut rx) = broadcaster.subscribe_user(*PLAYER2).await;
+        matchmaker.enqueue(*PLAYER1, 2).await.unwrap();
+
+        // act
+        matchmaker.enqueue(*PLAYER2, 2).await.unwrap();
+
+        // assert
+        let msg = timeout(Duration::from_secs(1), rx.recv()).await.unwrap();
+        assert_eq!(&msg.unwrap()[..], &b"event: matchfound\ndata: 1\n\n"[..]);
+        let page = db
+            .get_games_for_user(*PLAYER1, &GameQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(page.games.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn match_is_kept_for_user_without_stream() {
+        // arrange
+        let (matchmaker, _, broadcaster) = setup().await;
+        let (_, mut rx) = broadcaster.subscribe_user(*PLAYER2).await;
+        matchmaker.enqueue(*PLAYER1, 2).await.unwrap();
+        matchmaker.enqueue(*PLAYER2, 2).await.unwrap();
+
+        // Player 1 was queued first, so is told first
+        let msg = timeout(Duration::from_secs(1), rx.recv()).await.unwrap();
+        assert!(msg.is_ok());
+
+        // act
+        let (missed, _) = broadcaster.subscribe_user(*PLAYER1).await;
+
+        // assert
+        assert_eq!(missed.len(), 1);
+        assert_eq!(&missed[0][..], &b"event: matchfound\ndata: 1\n\n"[..]);
+    }
+
+    #[actix_rt::test]
+    async fn users_are_only_matched_with_same_size() {
+        // arrange
+        let (matchmaker, _, broadcaster) = setup().await;
+        let (_, mut rx) = broadcaster.subscribe_user(*PLAYER1).await;
+        matchmaker.enqueue(*PLAYER1, 3).await.unwrap();
+
+        // act
+        matchmaker.enqueue(*PLAYER2, 2).await.unwrap();
+        matchmaker.enqueue(*PLAYER3, 3).await.unwrap();
+
+        // assert
+        let msg = timeout(Duration::from_millis(100), rx.recv()).await;
+        assert!(msg.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn queueing_again_replaces_earlier_request() {
+        // arrange
+        let (matchmaker, _, _) = setup().await;
+        matchmaker.enqueue(*PLAYER1, 3).await.unwrap();
+
+        // act
+        matchmaker.enqueue(*PLAYER1, 4).await.unwrap();
+
+        // assert
+        let guard = matchmaker.queues.lock().await;
+        assert!(guard[&3].is_empty());
+        assert!(guard[&4].contains(&*PLAYER1));
+    }
+
+    #[actix_rt::test]
+    async fn can_leave_queue() {
+        // arrange
+        let (matchmaker, _, _) = setup().await;
+        matchmaker.enqueue(*PLAYER1, 2).await.unwrap();
+
+        // act
+        let left = matchmaker.leave(*PLAYER1).await;
+
+        // assert
+        assert!(left);
+        assert!(!matchmaker.leave(*PLAYER1).await);
+    }
+
+    #[actix_rt::test]
+    async fn cannot_queue_for_invalid_size() {
+        // arrange
+        let (matchmaker, _, _) = setup().await;
+
+        // act
+        let too_few = matchmaker.enqueue(*PLAYER1, 1).await;
+        let too_many = matchmaker.enqueue(*PLAYER1, 6).await;
+
+        // assert
+        assert_eq!(too_few, Err(CreateGameError::TooFewPlayers(1)));
+        assert_eq!(too_many, Err(CreateGameError::TooManyPlayers(6)));
+    }
+}
@@ -0,0 +1,266 @@
+use super::actor::GameActors;
+use super::errors::ApiError;
+use super::pubsub::Broadcaster;
+use actix_web::{
+    dev::ServiceResponse,
+    get,
+    http::header,
+    web::{Data, ServiceConfig},
+    Error as ActixError, HttpRequest, HttpResponse,
+};
+use prometheus::{
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use ring::constant_time::verify_slices_are_equal;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    static ref ACTIVE_GAMES: IntGauge = register_int_gauge!(
+        "sushi_active_games",
+        "Games with a running actor, i.e. played in the last few minutes"
+    )
+    .unwrap();
+    static ref GAME_SUBSCRIBERS: IntGaugeVec = register_int_gauge_vec!(
+        "sushi_game_subscribers",
+        "Clients streaming the events of a game",
+        &["game_id"]
+    )
+    .unwrap();
+    static ref PENDING_COUNTDOWNS: IntGauge = register_int_gauge!(
+        "sushi_pending_countdowns",
+        "Games waiting for a countdown to end the turn"
+    )
+    .unwrap();
+    static ref GAMES_COMPLETED: IntCounter =
+        register_int_counter!("sushi_games_completed_total", "Games played to the end").unwrap();
+    static ref REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "sushi_request_duration_seconds",
+        "Time taken to respond to HTTP requests",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    static ref DB_CALL_DURATION: HistogramVec = register_histogram_vec!(
+        "sushi_db_call_duration_seconds",
+        "Time taken by calls to the store",
+        &["operation"]
+    )
+    .unwrap();
+    static ref VERSION_CONFLICTS: IntCounterVec = register_int_counter_vec!(
+        "sushi_version_conflicts_total",
+        "Writes rejected because a game or lobby was changed concurrently",
+        &["operation"]
+    )
+    .unwrap();
+}
+
+/// The token scrapers send as `Authorization: Bearer <token>`.
+struct MetricsToken(String);
+
+/// Serves the metrics at `/metrics`, but only if a token is configured, since they reveal
+/// which games are being played.
+pub fn configure(token: Option<String>, cfg: &mut ServiceConfig) {
+    if let Some(token) = token {
+        cfg.data(MetricsToken(token)).service(get_metrics);
+    }
+}
+
+#[get("/metrics")]
+async fn get_metrics(
+    request: HttpRequest,
+    token: Data<MetricsToken>,
+    actors: Data<GameActors>,
+    broadcaster: Data<Broadcaster>,
+) -> Result<HttpResponse, ApiError> {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| verify_slices_are_equal(given.as_bytes(), token.0.as_bytes()).is_ok());
+
+    if !authorized {
+        return Err(ApiError::unauthorized());
+    }
+
+    ACTIVE_GAMES.set(actors.active_games().await as i64);
+
+    // Games nobody watches any more are dropped rather than reported as zero
+    GAME_SUBSCRIBERS.reset();
+
+    for (game_id, subscribers) in broadcaster.subscriber_counts().await {
+        GAME_SUBSCRIBERS
+            .with_label_values(&[&game_id.to_string()])
+            .set(subscribers as i64);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(ApiError::internal)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer))
+}
+
+pub fn countdown_started() {
+    PENDING_COUNTDOWNS.inc();
+}
+
+pub fn countdown_ended() {
+    PENDING_COUNTDOWNS.dec();
+}
+
+pub fn game_completed() {
+    GAMES_COMPLETED.inc();
+}
+
+pub fn observe_db_call(operation: &str, duration: Duration, conflict: bool) {
+    DB_CALL_DURATION
+        .with_label_values(&[operation])
+        .observe(duration.as_secs_f64());
+
+    if conflict {
+        VERSION_CONFLICTS.with_label_values(&[operation]).inc();
+    }
+}
+
+/// Times a request, for use with `App::wrap_fn`. Call before the service is called, so the
+/// time spent in inner middleware is included.
+pub fn time_request<B, F>(
+    response: F,
+) -> impl Future<Output = Result<ServiceResponse<B>, ActixError>>
+where
+    F: Future<Output = Result<ServiceResponse<B>, ActixError>>,
+{
+    let start = Instant::now();
+
+    async move {
+        let response = response.await?;
+        let request = response.request();
+        let status = response.status();
+
+        REQUEST_DURATION
+            .with_label_values(&[request.method().as_str(), &route(request), status.as_str()])
+            .observe(start.elapsed().as_secs_f64());
+
+        Ok(response)
+    }
+}
+
+/// The route a request matched, e.g. `/api/v1/games/{game_id}`, so requests for
+/// different games are counted together. Anything outside the API is served from the
+/// static directory and counted as one route.
+fn route(request: &HttpRequest) -> String {
+    let path = request.path();
+
+    if !path.starts_with("/api/") && path != "/metrics" {
+        return "static".to_string();
+    }
+
+    if !request.resource_map().has_resource(path) {
+        return "unmatched".to_string();
+    }
+
+    let params = request.match_info();
+
+    path.split('/')
+        .map(|segment| {
+            params
+                .iter()
+                .find(|(_, value)| *value == segment)
+                .map_or_else(|| segment.to_string(), |(name, _)| format!("{{{}}}", name))
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+
+    async fn echo_route(request: HttpRequest) -> String {
+        route(&request)
+    }
+
+    async fn routes(paths: &[&str]) -> Vec<String> {
+        let app = App::new().service(
+            web::scope("/api/v1")
+                .route("/games/{game_id}", web::get().to(echo_route))
+                .route("/games/{game_id}/stream", web::get().to(echo_route)),
+        );
+
+        let mut app = test::init_service(app).await;
+        let mut routes = vec![];
+
+        for path in paths {
+            let request = test::TestRequest::get().uri(path).to_request();
+            let body = test::read_response(&mut app, request).await;
+            routes.push(String::from_utf8(body.to_vec()).unwrap());
+        }
+
+        routes
+    }
+
+    #[actix_rt::test]
+    async fn routes_are_counted_by_pattern() {
+        // act
+        let routes = routes(&["/api/v1/games/1", "/api/v1/games/2/stream"]).await;
+
+        // assert
+        assert_eq!(
+            routes,
+            vec!["/api/v1/games/{game_id}", "/api/v1/games/{game_id}/stream"]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn metrics_require_the_token() {
+        // arrange
+        let app = App::new()
+            .data(GameActors::new(
+                std::sync::Arc::new(super::super::db::MemoryStore::new()),
+                Broadcaster::new(),
+                Duration::from_secs(1),
+            ))
+            .data(Broadcaster::new())
+            .configure(|cfg| configure(Some("secret".to_string()), cfg));
+        let mut app = test::init_service(app).await;
+
+        let request = |token: &str| {
+            test::TestRequest::get()
+                .uri("/metrics")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .to_request()
+        };
+
+        // act
+        let wrong = test::call_service(&mut app, request("wrong")).await;
+        let right = test::call_service(&mut app, request("secret")).await;
+
+        // assert
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(right.status(), StatusCode::OK);
+
+        let body = test::read_body(right).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("sushi_active_games 0"));
+    }
+
+    #[actix_rt::test]
+    async fn metrics_are_not_served_without_a_token() {
+        // arrange
+        let app = App::new().configure(|cfg| configure(None, cfg));
+        let mut app = test::init_service(app).await;
+        let request = test::TestRequest::get().uri("/metrics").to_request();
+
+        // act
+        let response = test::call_service(&mut app, request).await;
+
+        // assert
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
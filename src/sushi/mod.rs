@@ -1,16 +1,44 @@
+mod actor;
+mod auth;
+mod bots;
+mod chat;
+mod config;
 mod db;
 mod domain;
+mod errors;
 mod handlers;
+mod i18n;
+mod matchmaker;
+mod metrics;
+mod openapi;
+mod password;
 mod pubsub;
+mod tokens;
+mod typescript;
 
-pub use db::Database;
-pub use handlers::app;
-pub use pubsub::{receiver, Broadcaster};
+pub use actor::GameActors;
+pub use auth::Role;
+pub use config::{Backend, Config};
+#[cfg(feature = "postgres")]
+pub use db::PostgresStore;
+pub use db::{InstrumentedStore, MemoryStore, SqliteStore, Store};
+pub use errors::error_handlers;
+pub use handlers::{app, ApiVersion};
+pub use matchmaker::Matchmaker;
+pub use metrics::{configure as metrics, time_request};
+pub use openapi::spec as openapi;
+pub use pubsub::Broadcaster;
+pub use tokens::{new_claim_code, Tokens};
+pub use typescript::definitions as typescript;
 
+use chat::ChatMessage;
+use chrono::{DateTime, Utc};
+use domain::{GameView, Lobby};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Error as FormatError, Formatter};
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
 pub struct GameId(pub i64);
 
 impl Display for GameId {
@@ -19,8 +47,46 @@ impl Display for GameId {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
+pub struct LobbyId(pub i64);
+
+impl Display for LobbyId {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FormatError> {
+        self.0.fmt(f)
+    }
+}
+
+/// A lobby as stored, with the game it turned into once everyone accepted.
+#[derive(Debug, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LobbyView {
+    id: LobbyId,
+    #[serde(flatten)]
+    lobby: Lobby,
+    game_id: Option<GameId>,
+}
+
+/// A game as seen by one of its players, with the most recent chat messages.
+#[derive(Debug, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameDetails {
+    #[serde(flatten)]
+    view: GameView,
+    chat: Vec<ChatMessage>,
+}
+
+/// A game as shown in a user's list of games. `your_move` and `num_pending` describe
+/// the current turn and are always false and zero once the game is over.
+#[derive(Debug, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct GameListItem {
     id: GameId,
     players: Vec<String>,
+    round: usize,
+    game_over: bool,
+    winner: Option<String>,
+    your_move: bool,
+    num_pending: usize,
+    created: DateTime<Utc>,
+    updated: DateTime<Utc>,
 }
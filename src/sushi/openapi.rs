@@ -0,0 +1,572 @@
+use super::bots::{Bot, NewApiToken};
+use super::chat::ChatMessage;
+use super::db::{GamePage, GameQuery};
+use super::domain::{RoundOver, UserId};
+use super::handlers::{v2, ApiVersion, Credentials};
+use super::i18n::Locale;
+use super::tokens::TokenPair;
+use super::{GameDetails, GameId, LobbyId, LobbyView};
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::Schema,
+    JsonSchema,
+};
+use serde_json::{json, Map, Value};
+
+lazy_static! {
+    /// The OpenAPI 3 documents describing each version of the HTTP API, served at
+    /// `/api/v1/openapi.json` and `/api/v2/openapi.json`.
+    pub static ref V1: Value = spec(ApiVersion::V1);
+    pub static ref V2: Value = spec(ApiVersion::V2);
+}
+
+/// One route in `handlers.rs`. Everything sent or received is described by the schema
+/// of the Rust type that is (de)serialized, so the document follows the code.
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    public: bool,
+    deprecated: bool,
+    query: Option<Schema>,
+    body: Option<Schema>,
+    response: Response,
+}
+
+enum Response {
+    Json(Schema),
+    /// The `{ success, payload, error }` envelope in version 1, and the payload itself, or
+    /// no content, in version 2.
+    Payload(Option<Schema>),
+    Stream(&'static str),
+}
+
+impl Operation {
+    fn new(method: &'static str, path: &'static str, summary: &'static str) -> Self {
+        Self {
+            method,
+            path,
+            summary,
+            public: false,
+            deprecated: false,
+            query: None,
+            body: None,
+            response: Response::Payload(None),
+        }
+    }
+
+    /// Can be used without an access token.
+    fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    /// Kept for older clients; there is a better route for it.
+    fn deprecated(mut self) -> Self {
+        self.deprecated = true;
+        self
+    }
+
+    fn query(mut self, schema: Schema) -> Self {
+        self.query = Some(schema);
+        self
+    }
+
+    fn body(mut self, schema: Schema) -> Self {
+        self.body = Some(schema);
+        self
+    }
+
+    /// Responds with the JSON of the type itself.
+    fn returns(mut self, schema: Schema) -> Self {
+        self.response = Response::Json(schema);
+        self
+    }
+
+    /// Responds with the payload as shaped by the version, see `ApiVersion`.
+    fn succeeds(mut self, payload: Option<Schema>) -> Self {
+        self.response = Response::Payload(payload);
+        self
+    }
+
+    /// Responds with server-sent events, each with an `event` name and JSON `data`.
+    fn streams(mut self, events: &'static str) -> Self {
+        self.response = Response::Stream(events);
+        self
+    }
+
+    fn response(&self, version: ApiVersion) -> (&'static str, Value) {
+        let json = |schema| {
+            json!({
+                "description": "OK",
+                "content": { "application/json": { "schema": schema } },
+            })
+        };
+
+        match (&self.response, version) {
+            (Response::Json(schema), _) | (Response::Payload(Some(schema)), ApiVersion::V2) => {
+                ("200", json(json!(schema)))
+            }
+            (Response::Payload(None), ApiVersion::V2) => {
+                ("204", json!({ "description": "No content" }))
+            }
+            (Response::Payload(payload), ApiVersion::V1) => {
+                let payload = payload
+                    .as_ref()
+                    .map_or_else(|| json!({ "nullable": true }), |s| json!(s));
+
+                let envelope = json!({
+                    "type": "object",
+                    "required": ["success", "payload", "error"],
+                    "properties": {
+                        "success": { "type": "boolean", "enum": [true] },
+                        "payload": payload,
+                        "error": { "nullable": true },
+                    },
+                });
+
+                ("200", json(envelope))
+            }
+            (Response::Stream(events), _) => (
+                "200",
+                json!({
+                    "description": "A stream of server-sent events",
+                    "content": {
+                        "text/event-stream": {
+                            "schema": { "$ref": format!("#/components/schemas/{}", events) },
+                        },
+                    },
+                }),
+            ),
+        }
+    }
+
+    fn to_json(&self, version: ApiVersion) -> Value {
+        let mut parameters: Vec<Value> = self
+            .path
+            .split('/')
+            .filter_map(|s| s.strip_prefix('{')?.strip_suffix('}'))
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer", "format": "int64" },
+                })
+            })
+            .collect();
+
+        if let Some(Schema::Object(query)) = &self.query {
+            let properties = query.object.iter().flat_map(|o| &o.properties);
+
+            parameters
+                .extend(properties.map(
+                    |(name, schema)| json!({ "name": name, "in": "query", "schema": schema }),
+                ));
+        }
+
+        let (status, response) = self.response(version);
+
+        let mut operation = json!({
+            "summary": self.summary,
+            "responses": { "default": { "$ref": "#/components/responses/Error" } },
+        });
+
+        operation["responses"][status] = response;
+
+        if !parameters.is_empty() {
+            operation["parameters"] = json!(parameters);
+        }
+
+        if let Some(body) = &self.body {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": body } },
+            });
+        }
+
+        if self.deprecated {
+            operation["deprecated"] = json!(true);
+        }
+
+        if self.public {
+            operation["security"] = json!([]);
+        } else if let Response::Stream(_) = self.response {
+            operation["security"] = json!([{ "bearer": [] }, { "queryToken": [] }]);
+        }
+
+        operation
+    }
+}
+
+fn operations(gen: &mut SchemaGenerator, version: ApiVersion) -> Vec<Operation> {
+    use Operation as Op;
+
+    // Picks the schema of the version, so the other is not added to the components
+    macro_rules! body {
+        ($v1:ty, $v2:ty) => {
+            match version {
+                ApiVersion::V1 => gen.subschema_for::<$v1>(),
+                ApiVersion::V2 => gen.subschema_for::<$v2>(),
+            }
+        };
+    }
+
+    vec![
+        Op::new("post", "/register", "Creates an account and logs in")
+            .public()
+            .body(gen.subschema_for::<Credentials>())
+            .returns(gen.subschema_for::<TokenPair>()),
+        Op::new("post", "/login", "Logs in with a password")
+            .public()
+            .body(gen.subschema_for::<Credentials>())
+            .returns(gen.subschema_for::<TokenPair>()),
+        Op::new(
+            "post",
+            "/token/refresh",
+            "Exchanges a refresh token for new tokens",
+        )
+        .public()
+        .body(body!(String, v2::RefreshToken))
+        .returns(gen.subschema_for::<TokenPair>()),
+        Op::new("post", "/logout", "Revokes a refresh token")
+            .public()
+            .body(body!(String, v2::RefreshToken))
+            .succeeds(None),
+        Op::new(
+            "post",
+            "/account/password",
+            "Sets the password of an account without one",
+        )
+        .body(body!(String, v2::Password))
+        .succeeds(None),
+        Op::new(
+            "put",
+            "/account/locale",
+            "Sets or clears the preferred language",
+        )
+        .body(body!(Option<Locale>, v2::LocalePreference))
+        .returns(gen.subschema_for::<TokenPair>()),
+        Op::new("get", "/bots", "Lists the user's bots").returns(gen.subschema_for::<Vec<Bot>>()),
+        Op::new("post", "/bots", "Creates a bot")
+            .body(body!(String, v2::Name))
+            .succeeds(Some(gen.subschema_for::<Bot>())),
+        Op::new(
+            "post",
+            "/bots/{bot_id}/tokens",
+            "Creates an API token for a bot",
+        )
+        .succeeds(Some(gen.subschema_for::<NewApiToken>())),
+        Op::new(
+            "delete",
+            "/bots/{bot_id}/tokens/{token_id}",
+            "Revokes an API token",
+        )
+        .succeeds(None),
+        Op::new("get", "/games", "Lists the user's games")
+            .query(GameQuery::json_schema(gen))
+            .returns(gen.subschema_for::<GamePage>()),
+        Op::new(
+            "post",
+            "/games",
+            "Creates a lobby and invites players, like `POST /lobbies`",
+        )
+        .deprecated()
+        .body(body!(Vec<String>, v2::Opponents))
+        .succeeds(Some(gen.subschema_for::<LobbyId>())),
+        Op::new("get", "/games/{game_id}", "Gets a game")
+            .returns(gen.subschema_for::<GameDetails>()),
+        Op::new("put", "/games/{game_id}", "Selects cards to play")
+            .body(body!(Vec<usize>, v2::SelectedCards))
+            .succeeds(None),
+        Op::new("post", "/games/{game_id}/chat", "Sends a chat message")
+            .body(body!(String, v2::ChatText))
+            .succeeds(Some(gen.subschema_for::<ChatMessage>())),
+        Op::new(
+            "get",
+            "/games/{game_id}/stream",
+            "Streams the events of a game",
+        )
+        .streams("GameStreamEvent"),
+        Op::new(
+            "post",
+            "/games/{game_id}/rematch",
+            "Proposes or accepts a rematch",
+        )
+        .succeeds(Some(gen.subschema_for::<LobbyView>())),
+        Op::new("get", "/stream", "Streams the events of the user").streams("UserStreamEvent"),
+        Op::new("post", "/queue", "Queues for a game of the given size")
+            .body(body!(usize, v2::GameSize))
+            .succeeds(None),
+        Op::new("delete", "/queue", "Leaves the queue").succeeds(Some(gen.subschema_for::<bool>())),
+        Op::new("get", "/lobbies", "Lists the user's lobbies")
+            .returns(gen.subschema_for::<Vec<LobbyView>>()),
+        Op::new("post", "/lobbies", "Creates a lobby and invites players")
+            .body(body!(Vec<String>, v2::Opponents))
+            .succeeds(Some(body!(LobbyId, LobbyView))),
+        Op::new(
+            "post",
+            "/lobbies/open",
+            "Opens a lobby that can be joined by code",
+        )
+        .body(body!(usize, v2::Capacity))
+        .succeeds(Some(gen.subschema_for::<LobbyView>())),
+        Op::new("post", "/lobbies/join", "Joins a lobby by code")
+            .body(body!(String, v2::LobbyCode))
+            .succeeds(Some(gen.subschema_for::<LobbyView>())),
+        Op::new("get", "/lobbies/{lobby_id}", "Gets a lobby")
+            .returns(gen.subschema_for::<LobbyView>()),
+        Op::new("post", "/lobbies/{lobby_id}/seats", "Invites a player")
+            .body(body!(String, v2::Name))
+            .succeeds(Some(gen.subschema_for::<LobbyView>())),
+        Op::new(
+            "delete",
+            "/lobbies/{lobby_id}/seats/{user_id}",
+            "Removes a player",
+        )
+        .succeeds(Some(gen.subschema_for::<LobbyView>())),
+        Op::new(
+            "post",
+            "/lobbies/{lobby_id}/accept",
+            "Accepts an invitation",
+        )
+        .succeeds(Some(gen.subschema_for::<LobbyView>())),
+        Op::new(
+            "post",
+            "/lobbies/{lobby_id}/decline",
+            "Declines an invitation",
+        )
+        .succeeds(Some(gen.subschema_for::<LobbyView>())),
+        Op::new(
+            "post",
+            "/lobbies/{lobby_id}/start",
+            "Starts the game with the players seated",
+        )
+        .succeeds(Some(gen.subschema_for::<LobbyView>())),
+        Op::new("get", "/openapi.json", "Gets this document")
+            .public()
+            .returns(gen.subschema_for::<Value>()),
+    ]
+}
+
+/// Describes the events of a stream as one of several `{ event, data }` pairs, where
+/// `data` is the JSON after `data:` in the event.
+fn events(description: &str, events: Vec<(&str, Value)>) -> Value {
+    let variants: Vec<_> = events
+        .into_iter()
+        .map(|(event, data)| {
+            json!({
+                "type": "object",
+                "required": ["event", "data"],
+                "properties": {
+                    "event": { "type": "string", "enum": [event] },
+                    "data": data,
+                },
+            })
+        })
+        .collect();
+
+    json!({ "description": description, "oneOf": variants })
+}
+
+pub fn spec(version: ApiVersion) -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let operations = operations(&mut gen, version);
+    let null = json!({ "nullable": true });
+
+    let game_events = events(
+        "An event in a game, sent to everyone watching it",
+        vec![
+            ("cardsselected", json!(gen.subschema_for::<UserId>())),
+            ("countdownstarted", json!(gen.subschema_for::<u64>())),
+            ("countdowncancelled", null.clone()),
+            ("turnover", null),
+            ("roundover", json!(gen.subschema_for::<RoundOver>())),
+            ("gameover", json!(gen.subschema_for::<UserId>())),
+            ("rematchoffered", json!(gen.subschema_for::<LobbyView>())),
+            ("chat", json!(gen.subschema_for::<ChatMessage>())),
+        ],
+    );
+
+    let user_events = events(
+        "An event for a single user",
+        vec![("matchfound", json!(gen.subschema_for::<GameId>()))],
+    );
+
+    let mut paths = Map::new();
+
+    for operation in &operations {
+        let path = paths.entry(operation.path).or_insert_with(|| json!({}));
+        path[operation.method] = operation.to_json(version);
+    }
+
+    let mut schemas: Map<String, Value> = gen
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, json!(schema)))
+        .collect();
+
+    schemas.insert("GameStreamEvent".to_string(), game_events);
+    schemas.insert("UserStreamEvent".to_string(), user_events);
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "sushi",
+            "version": format!("{}-{}", env!("CARGO_PKG_VERSION"), version),
+        },
+        "servers": [{ "url": version.prefix() }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "responses": {
+                "Error": {
+                    "description": "The request failed; `code` and `params` are stable, \
+                        `message` is translated",
+                    "content": {
+                        "application/json": {
+                            "schema": {
+                                "type": "object",
+                                "required": ["success", "payload", "error"],
+                                "properties": {
+                                    "success": { "type": "boolean", "enum": [false] },
+                                    "payload": { "nullable": true },
+                                    "error": {
+                                        "type": "object",
+                                        "required": ["code", "message"],
+                                        "properties": {
+                                            "code": { "type": "string" },
+                                            "message": { "type": "string" },
+                                            "params": { "type": "object" },
+                                        },
+                                    },
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "An access token, or an API token for bots",
+                },
+                "queryToken": {
+                    "type": "apiKey",
+                    "in": "query",
+                    "name": "access_token",
+                    "description": "The same token for streams, since browsers cannot \
+                                    set headers on an EventSource",
+                },
+            },
+        },
+        "security": [{ "bearer": [] }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// Every route attribute in a handler file, e.g. `("get", "/games")`.
+    fn routes(source: &str) -> BTreeSet<(String, String)> {
+        source
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim().strip_prefix("#[")?.strip_suffix(")]")?;
+                let (method, path) = line.split_once('(')?;
+                let path = path.strip_prefix('"')?.strip_suffix('"')?;
+                Some((method.to_string(), path.to_string()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        for &version in &[ApiVersion::V1, ApiVersion::V2] {
+            // arrange
+            let mut gen = SchemaSettings::openapi3().into_generator();
+
+            // act
+            let documented: BTreeSet<_> = operations(&mut gen, version)
+                .iter()
+                .map(|o| (o.method.to_string(), o.path.to_string()))
+                .collect();
+
+            // assert
+            assert!(!documented.is_empty());
+            assert_eq!(documented, routes(include_str!("handlers.rs")));
+        }
+    }
+
+    #[test]
+    fn version_2_only_replaces_routes() {
+        // act
+        let v1 = routes(include_str!("handlers.rs"));
+        let v2 = routes(include_str!("handlers/v2.rs"));
+
+        // assert
+        assert!(!v2.is_empty());
+        assert!(v2.is_subset(&v1));
+    }
+
+    #[test]
+    fn specs_match_committed_documents() {
+        let committed = [
+            (&*V1, include_str!("../../openapi/v1.json"), ApiVersion::V1),
+            (&*V2, include_str!("../../openapi/v2.json"), ApiVersion::V2),
+        ];
+
+        for (spec, document, version) in &committed {
+            // arrange
+            let document: Value = serde_json::from_str(document).unwrap();
+
+            // assert
+            assert!(
+                **spec == document,
+                "openapi/{0}.json is out of date; run \
+                 `cargo run -- --print-openapi {0} > openapi/{0}.json` and review the changes",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn schemas_are_referenced_by_name() {
+        // act
+        let game = &V1["paths"]["/games/{game_id}"]["get"];
+        let schemas = &V1["components"]["schemas"];
+
+        // assert
+        assert_eq!(
+            game["responses"]["200"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/GameDetails"
+        );
+        assert!(schemas["GameDetails"].is_object());
+        assert!(schemas["Card"]["oneOf"].is_array());
+    }
+
+    #[test]
+    fn versions_differ_in_bodies_and_responses() {
+        // act
+        let v1 = &V1["paths"]["/games/{game_id}"]["put"];
+        let v2 = &V2["paths"]["/games/{game_id}"]["put"];
+
+        // assert
+        assert_eq!(V1["servers"][0]["url"], "/api/v1");
+        assert_eq!(V2["servers"][0]["url"], "/api/v2");
+        assert_eq!(
+            v1["requestBody"]["content"]["application/json"]["schema"]["type"],
+            "array"
+        );
+        assert_eq!(
+            v2["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/SelectedCards"
+        );
+        assert!(v1["responses"]["200"].is_object());
+        assert!(v2["responses"]["204"].is_object());
+        assert!(V1["components"]["schemas"].get("SelectedCards").is_none());
+    }
+}
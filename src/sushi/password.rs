@@ -0,0 +1,104 @@
+use ring::{
+    digest::SHA256_OUTPUT_LEN,
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::fmt::Write;
+use std::num::NonZeroU32;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+static ALGORITHM: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
+const ITERATIONS: u32 = 100_000;
+const SALT_LENGTH: usize = 16;
+
+/// Hashes a password with a random salt. The result is stored as
+/// `iterations$salt$hash`, with salt and hash in hex, so the number of iterations can be
+/// raised later without invalidating existing passwords.
+pub fn hash(password: &str) -> String {
+    let mut salt = [0u8; SALT_LENGTH];
+
+    SystemRandom::new()
+        .fill(&mut salt)
+        .expect("failed to generate salt");
+
+    let mut hash = [0u8; SHA256_OUTPUT_LEN];
+    let iterations = NonZeroU32::new(ITERATIONS).unwrap();
+    pbkdf2::derive(ALGORITHM, iterations, &salt, password.as_bytes(), &mut hash);
+
+    format!("{}${}${}", ITERATIONS, to_hex(&salt), to_hex(&hash))
+}
+
+/// Checks a password against a stored hash in constant time. Malformed hashes never
+/// match.
+pub fn verify(password: &str, stored: &str) -> bool {
+    let parts: Vec<_> = stored.split('$').collect();
+
+    let (iterations, salt, hash) = match parts.as_slice() {
+        [iterations, salt, hash] => (
+            iterations.parse().ok().and_then(NonZeroU32::new),
+            from_hex(salt),
+            from_hex(hash),
+        ),
+        _ => return false,
+    };
+
+    match (iterations, salt, hash) {
+        (Some(iterations), Some(salt), Some(hash)) => {
+            pbkdf2::verify(ALGORITHM, iterations, &salt, password.as_bytes(), &hash).is_ok()
+        }
+        _ => false,
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).expect("can write to string");
+    }
+
+    hex
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_verify_hashed_password() {
+        // arrange
+        let stored = hash("correct horse");
+
+        // act
+        let correct = verify("correct horse", &stored);
+        let wrong = verify("battery staple", &stored);
+
+        // assert
+        assert!(correct);
+        assert!(!wrong);
+    }
+
+    #[test]
+    fn same_password_gets_different_salt() {
+        assert_ne!(hash("password"), hash("password"));
+    }
+
+    #[test]
+    fn malformed_hash_never_matches() {
+        assert!(!verify("password", ""));
+        assert!(!verify("password", "100000$zz$00"));
+        assert!(!verify("password", "0$00$00"));
+    }
+}
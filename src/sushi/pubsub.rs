@@ -1,135 +1,114 @@
-use super::db::*;
-use super::domain::GameEvent;
-use super::GameId;
+use super::chat::ChatMessage;
+use super::domain::{GameEvent, UserId};
+use super::{GameId, LobbyView};
 use actix_web::web::Bytes;
 use std::collections::HashMap;
+use std::hash::Hash;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::{
-    sync::{
-        broadcast::{channel, Receiver as BroadcastReceiver, Sender as BroadcastSender},
-        mpsc::UnboundedReceiver,
-        oneshot, Mutex,
-    },
-    time::delay_for,
+use std::time::{Duration, Instant};
+use tokio::sync::{
+    broadcast::{channel, Receiver as BroadcastReceiver, SendError, Sender as BroadcastSender},
+    Mutex,
 };
 
-#[derive(Debug)]
-pub enum Countdown {
-    Start { game_id: GameId, duration: Duration },
-    Cancel { game_id: GameId },
-}
-
-struct Countdowns {
-    map: Mutex<HashMap<GameId, oneshot::Receiver<()>>>,
-}
-
-impl Countdowns {
-    pub fn new() -> Self {
-        Self {
-            map: Mutex::new(HashMap::new()),
-        }
-    }
+type Senders<K> = Arc<Mutex<HashMap<K, BroadcastSender<Bytes>>>>;
 
-    async fn insert(&self, game_id: GameId, receiver: oneshot::Receiver<()>) {
-        self.map.lock().await.insert(game_id, receiver);
-    }
+/// How long events are kept for a user without an open stream.
+const MISSED_EVENT_TTL: Duration = Duration::from_secs(5 * 60);
 
-    async fn remove(&self, game_id: GameId) -> Option<oneshot::Receiver<()>> {
-        self.map.lock().await.remove(&game_id)
-    }
+/// The stream of a user, or the events sent while they had none open, which are
+/// replayed when they next subscribe.
+#[derive(Debug)]
+enum UserStream {
+    Open(BroadcastSender<Bytes>),
+    Missed(Vec<(Instant, Bytes)>),
 }
 
-pub async fn receiver(
-    broadcaster: Broadcaster,
-    db: Database,
-    mut rx: UnboundedReceiver<Countdown>,
-) {
-    let countdowns = Arc::new(Countdowns::new());
-
-    while let Some(msg) = rx.recv().await {
-        debug!("received a countdown message {:?}", msg);
-
-        match msg {
-            Countdown::Start { game_id, duration } => {
-                let (tx, rx) = oneshot::channel();
-
-                tokio::spawn(countdown(
-                    broadcaster.clone(),
-                    db.clone(),
-                    countdowns.clone(),
-                    tx,
-                    game_id,
-                    duration,
-                ));
-
-                countdowns.insert(game_id, rx).await;
-
-                broadcaster
-                    .send(game_id, &GameEvent::CountdownStarted(duration))
-                    .await;
-            }
-
-            Countdown::Cancel { game_id } => {
-                if let Some(mut receiver) = countdowns.remove(game_id).await {
-                    receiver.close();
-
-                    broadcaster
-                        .send(game_id, &GameEvent::CountdownCancelled)
-                        .await;
-                }
-            }
+impl UserStream {
+    fn is_live(&self, now: Instant) -> bool {
+        match self {
+            UserStream::Open(tx) => tx.receiver_count() > 0,
+            UserStream::Missed(events) => events
+                .iter()
+                .any(|(sent, _)| now.duration_since(*sent) < MISSED_EVENT_TTL),
         }
     }
 }
 
-async fn countdown(
-    broadcaster: Broadcaster,
-    db: Database,
-    countdowns: Arc<Countdowns>,
-    mut tx: oneshot::Sender<()>,
-    game_id: GameId,
-    duration: Duration,
-) {
-    tokio::select! {
-        _ = &mut delay_for(duration) => {
-            info!("Countdown for {} completed after {:?}", game_id, duration);
-
-            match db.read_game(game_id) {
-                Err(e) => error!("failed to read game from db because {}", e),
-                Ok(None) => warn!("failed to find game with id {}", game_id),
-                Ok(Some((mut game, version))) => {
-                    let events = game.end_turn();
-
-                    if let Err(e) = db.update_game(game_id, &game, version) {
-                        error!("failed to update game because {}", e);
-                    }
-
-                    for event in events {
-                        broadcaster.send(game_id, &event).await;
-                    }
-                }
-            }
-        },
-
-        _ = tx.closed() => {
-            // interrupted
-            info!("Countdown for {} was interrupted", game_id);
-        }
-    }
-
-    countdowns.remove(game_id).await;
+/// Events sent to a single user, independent of any game.
+#[derive(Debug)]
+pub enum UserEvent {
+    MatchFound(GameId),
 }
 
 #[derive(Debug, Clone)]
 pub struct Broadcaster {
-    senders_by_game_id: Arc<Mutex<HashMap<GameId, BroadcastSender<Bytes>>>>,
+    senders_by_game_id: Senders<GameId>,
+    streams_by_user_id: Arc<Mutex<HashMap<UserId, UserStream>>>,
 }
 
 impl Broadcaster {
     pub fn new() -> Broadcaster {
         Self {
             senders_by_game_id: Arc::new(Mutex::new(HashMap::new())),
+            streams_by_user_id: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the events the user missed since their last stream closed, followed by
+    /// the receiver for new ones.
+    pub async fn subscribe_user(&self, user_id: UserId) -> (Vec<Bytes>, BroadcastReceiver<Bytes>) {
+        debug!("New subscriber for user {}", user_id);
+
+        let mut guard = self.streams_by_user_id.lock().await;
+        let now = Instant::now();
+        guard.retain(|_, stream| stream.is_live(now));
+
+        if let Some(UserStream::Open(tx)) = guard.get(&user_id) {
+            return (vec![], tx.subscribe());
+        }
+
+        let (tx, rx) = channel(100);
+
+        let missed = match guard.insert(user_id, UserStream::Open(tx)) {
+            Some(UserStream::Missed(events)) => events
+                .into_iter()
+                .filter(|(sent, _)| now.duration_since(*sent) < MISSED_EVENT_TTL)
+                .map(|(_, msg)| msg)
+                .collect(),
+            _ => vec![],
+        };
+
+        (missed, rx)
+    }
+
+    /// Sends the event to the user's open streams, or keeps it for the next one if
+    /// there are none.
+    pub async fn send_to_user(&self, user_id: UserId, user_event: &UserEvent) {
+        let msg = serialize_user_event(user_event).expect("failed to serialize user event");
+        let msg = Bytes::from(msg);
+
+        let mut guard = self.streams_by_user_id.lock().await;
+        let now = Instant::now();
+        guard.retain(|_, stream| stream.is_live(now));
+
+        let msg = match guard.get(&user_id) {
+            Some(UserStream::Open(tx)) => match tx.send(msg) {
+                Ok(_) => return,
+                // The last stream closed after the retain above
+                Err(SendError(msg)) => msg,
+            },
+            _ => msg,
+        };
+
+        debug!("no one was listening for user {}, keeping event", user_id);
+
+        match guard
+            .entry(user_id)
+            .or_insert_with(|| UserStream::Missed(vec![]))
+        {
+            UserStream::Missed(events) => events.push((now, msg)),
+            stream => *stream = UserStream::Missed(vec![(now, msg)]),
         }
     }
 
@@ -153,6 +132,41 @@ impl Broadcaster {
         rx
     }
 
+    /// How many clients are streaming each game, leaving out games no one is watching.
+    pub async fn subscriber_counts(&self) -> Vec<(GameId, usize)> {
+        self.senders_by_game_id
+            .lock()
+            .await
+            .iter()
+            .map(|(game_id, tx)| (*game_id, tx.receiver_count()))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    /// Tells everyone watching a finished game that one of the players wants a rematch.
+    pub async fn send_rematch_offer(&self, game_id: GameId, lobby: &LobbyView) {
+        let tx = sender(&self.senders_by_game_id, game_id).await;
+        let data = serde_json::to_string(lobby).expect("failed to serialize lobby");
+        let msg = ["event: rematchoffered\n", "data: ", &data, "\n\n"].concat();
+
+        if let Err(e) = tx.send(Bytes::from(msg)) {
+            debug!(
+                "no one was listening for rematch offer {:?} in game {}",
+                e, game_id
+            );
+        }
+    }
+
+    pub async fn send_chat(&self, game_id: GameId, message: &ChatMessage) {
+        let tx = sender(&self.senders_by_game_id, game_id).await;
+        let data = serde_json::to_string(message).expect("failed to serialize chat message");
+        let msg = ["event: chat\n", "data: ", &data, "\n\n"].concat();
+
+        if let Err(e) = tx.send(Bytes::from(msg)) {
+            debug!("no one was listening for chat {:?} in game {}", e, game_id);
+        }
+    }
+
     pub async fn send(&self, game_id: GameId, game_event: &GameEvent) {
         let tx = {
             let mut guard = self.senders_by_game_id.lock().await;
@@ -176,6 +190,23 @@ impl Broadcaster {
     }
 }
 
+async fn sender<K: Eq + Hash>(senders: &Senders<K>, key: K) -> BroadcastSender<Bytes> {
+    senders
+        .lock()
+        .await
+        .entry(key)
+        .or_insert_with(|| channel(100).0)
+        .clone()
+}
+
+fn serialize_user_event(event: &UserEvent) -> Result<String, serde_json::Error> {
+    let (event, data) = match event {
+        UserEvent::MatchFound(game_id) => ("matchfound", serde_json::to_string(&game_id)?),
+    };
+
+    Ok(["event: ", event, "\n", "data: ", &data, "\n\n"].concat())
+}
+
 fn serialize(event: &GameEvent) -> Result<String, serde_json::Error> {
     use GameEvent::*;
 
@@ -190,3 +221,41 @@ fn serialize(event: &GameEvent) -> Result<String, serde_json::Error> {
 
     Ok(["event: ", event, "\n", "data: ", &data, "\n\n"].concat())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn closed_user_streams_are_removed() {
+        // arrange
+        let broadcaster = Broadcaster::new();
+        let (_, rx) = broadcaster.subscribe_user(UserId(1)).await;
+        drop(rx);
+
+        // act
+        broadcaster.subscribe_user(UserId(2)).await;
+
+        // assert
+        let guard = broadcaster.streams_by_user_id.lock().await;
+        assert!(!guard.contains_key(&UserId(1)));
+    }
+
+    #[actix_rt::test]
+    async fn events_are_only_replayed_once() {
+        // arrange
+        let broadcaster = Broadcaster::new();
+        broadcaster
+            .send_to_user(UserId(1), &UserEvent::MatchFound(GameId(1)))
+            .await;
+        let (missed, rx) = broadcaster.subscribe_user(UserId(1)).await;
+        drop(rx);
+
+        // act
+        let (missed_again, _) = broadcaster.subscribe_user(UserId(1)).await;
+
+        // assert
+        assert_eq!(missed.len(), 1);
+        assert!(missed_again.is_empty());
+    }
+}
@@ -0,0 +1,330 @@
+use super::auth::Role;
+use super::config::JwtConfig;
+use super::domain::UserId;
+use super::i18n::Locale;
+use super::password::to_hex;
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{
+    errors::{Error as JwtError, ErrorKind},
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const JWT_ALGORITHM: Algorithm = Algorithm::HS256;
+const REFRESH_TOKEN_LENGTH: usize = 32;
+const CLAIM_CODE_HOURS: i64 = 24;
+
+/// Marks API tokens, so they can be told apart from access tokens.
+pub const API_TOKEN_PREFIX: &str = "sushi_";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Claims {
+    pub sub: UserId,
+    pub name: String,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
+    pub exp: i64,
+}
+
+/// What a client gets when logging in or refreshing. The access token is sent with
+/// every request; the refresh token is only sent to get a new pair.
+#[derive(Debug, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
+/// A refresh token as handed to the client, along with what is stored about it. Only
+/// the hash is stored, so a leaked database cannot be used to log in.
+pub struct RefreshToken {
+    pub token: String,
+    pub hash: String,
+    pub expires: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+struct Key {
+    encoding: EncodingKey,
+    decoding: DecodingKey<'static>,
+}
+
+/// Signs and verifies access tokens. Keys are identified by the `kid` header, so the
+/// signing key can be rotated while tokens signed with older keys stay valid until the
+/// old key is removed from the configuration.
+#[derive(Clone)]
+pub struct Tokens {
+    current: String,
+    keys: HashMap<String, Key>,
+    access_ttl: Duration,
+    refresh_ttl: Duration,
+}
+
+impl Tokens {
+    /// Creates tokens signed with the first of `keys`, given as key id and secret.
+    pub fn new(keys: Vec<(String, Vec<u8>)>, access_ttl: Duration, refresh_ttl: Duration) -> Self {
+        let current = keys.first().expect("at least one jwt key").0.clone();
+
+        let keys = keys
+            .into_iter()
+            .map(|(id, secret)| {
+                let key = Key {
+                    encoding: EncodingKey::from_secret(&secret),
+                    decoding: DecodingKey::from_secret(&secret).into_static(),
+                };
+
+                (id, key)
+            })
+            .collect();
+
+        Self {
+            current,
+            keys,
+            access_ttl,
+            refresh_ttl,
+        }
+    }
+
+    /// Creates tokens from the configured keys. Without keys, a random key is generated
+    /// and tokens do not survive a restart.
+    pub fn from_config(config: &JwtConfig) -> Self {
+        let mut keys: Vec<_> = config
+            .keys
+            .iter()
+            .map(|key| (key.id.clone(), key.secret.as_bytes().to_vec()))
+            .collect();
+
+        if keys.is_empty() {
+            warn!("No jwt keys are configured; using a random key");
+            keys.push(("random".to_string(), random_bytes(32)));
+        }
+
+        Self::new(
+            keys,
+            Duration::minutes(config.access_token_minutes),
+            Duration::days(config.refresh_token_days),
+        )
+    }
+
+    pub fn issue(
+        &self,
+        user_id: UserId,
+        name: String,
+        role: Role,
+        locale: Option<Locale>,
+        refresh_token: &RefreshToken,
+    ) -> TokenPair {
+        let claims = Claims {
+            sub: user_id,
+            name,
+            role,
+            locale,
+            exp: (Utc::now() + self.access_ttl).timestamp(),
+        };
+
+        let mut header = Header::new(JWT_ALGORITHM);
+        header.kid = Some(self.current.clone());
+
+        let access_token =
+            jsonwebtoken::encode(&header, &claims, &self.keys[&self.current].encoding)
+                .expect("failed to encode jwt");
+
+        TokenPair {
+            access_token,
+            refresh_token: refresh_token.token.clone(),
+            expires_in: self.access_ttl.num_seconds(),
+        }
+    }
+
+    /// Verifies the signature and expiry of an access token.
+    pub fn decode(&self, jwt: &str) -> Result<Claims, JwtError> {
+        let header = jsonwebtoken::decode_header(jwt)?;
+
+        let key = header
+            .kid
+            .and_then(|kid| self.keys.get(&kid))
+            .ok_or(ErrorKind::InvalidToken)?;
+
+        let validation = Validation::new(JWT_ALGORITHM);
+        jsonwebtoken::decode::<Claims>(jwt, &key.decoding, &validation).map(|c| c.claims)
+    }
+
+    pub fn new_refresh_token(&self) -> RefreshToken {
+        let token = to_hex(&random_bytes(REFRESH_TOKEN_LENGTH));
+
+        RefreshToken {
+            hash: hash_token(&token),
+            token,
+            expires: Utc::now() + self.refresh_ttl,
+        }
+    }
+}
+
+/// Creates a refresh token for an admin to give to the owner of an account without a
+/// password, who exchanges it once at `/token/refresh` and then sets a password. Unlike
+/// a signed token it cannot be forged, and it expires within a day.
+pub fn new_claim_code() -> RefreshToken {
+    let token = to_hex(&random_bytes(REFRESH_TOKEN_LENGTH));
+
+    RefreshToken {
+        hash: hash_token(&token),
+        token,
+        expires: Utc::now() + Duration::hours(CLAIM_CODE_HOURS),
+    }
+}
+
+/// Creates a long-lived token for a bot, returned with the hash to store.
+pub fn new_api_token() -> (String, String) {
+    let token = format!(
+        "{}{}",
+        API_TOKEN_PREFIX,
+        to_hex(&random_bytes(REFRESH_TOKEN_LENGTH))
+    );
+
+    let hash = hash_token(&token);
+    (token, hash)
+}
+
+/// Hashes a refresh or API token for storage.
+pub fn hash_token(token: &str) -> String {
+    to_hex(digest(&SHA256, token.as_bytes()).as_ref())
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("failed to generate random bytes");
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(keys: &[&str]) -> Tokens {
+        let keys = keys
+            .iter()
+            .map(|id| (id.to_string(), format!("secret-{}", id).into_bytes()))
+            .collect();
+
+        Tokens::new(keys, Duration::minutes(15), Duration::days(30))
+    }
+
+    #[test]
+    fn can_decode_issued_token() {
+        // arrange
+        let tokens = tokens(&["a"]);
+        let refresh_token = tokens.new_refresh_token();
+
+        // act
+        let pair = tokens.issue(
+            UserId(1),
+            "a".to_string(),
+            Role::Admin,
+            Some(Locale::Da),
+            &refresh_token,
+        );
+
+        // assert
+        let claims = tokens.decode(&pair.access_token).unwrap();
+        assert_eq!(claims.sub, UserId(1));
+        assert_eq!(claims.role, Role::Admin);
+        assert_eq!(claims.locale, Some(Locale::Da));
+        assert_eq!(pair.refresh_token, refresh_token.token);
+        assert_eq!(pair.expires_in, 15 * 60);
+    }
+
+    #[test]
+    fn cannot_decode_expired_token() {
+        // arrange
+        let tokens = Tokens::new(
+            vec![("a".to_string(), b"secret".to_vec())],
+            Duration::minutes(-5),
+            Duration::days(30),
+        );
+
+        let pair = tokens.issue(
+            UserId(1),
+            "a".to_string(),
+            Role::Player,
+            None,
+            &tokens.new_refresh_token(),
+        );
+
+        // act
+        let error = tokens.decode(&pair.access_token).unwrap_err();
+
+        // assert
+        assert!(matches!(error.kind(), ErrorKind::ExpiredSignature));
+    }
+
+    #[test]
+    fn tokens_signed_with_previous_key_are_valid_after_rotation() {
+        // arrange
+        let old = tokens(&["old"]);
+        let pair = old.issue(
+            UserId(1),
+            "a".to_string(),
+            Role::Player,
+            None,
+            &old.new_refresh_token(),
+        );
+        let rotated = tokens(&["new", "old"]);
+
+        // act
+        let claims = rotated.decode(&pair.access_token);
+
+        // assert
+        assert!(claims.is_ok());
+        assert!(tokens(&["new"]).decode(&pair.access_token).is_err());
+    }
+
+    #[test]
+    fn claim_codes_expire_before_refresh_tokens() {
+        // arrange
+        let tokens = tokens(&["a"]);
+
+        // act
+        let claim_code = new_claim_code();
+
+        // assert
+        assert!(claim_code.expires <= Utc::now() + Duration::hours(CLAIM_CODE_HOURS));
+        assert!(claim_code.expires < tokens.new_refresh_token().expires);
+        assert_eq!(claim_code.hash, hash_token(&claim_code.token));
+    }
+
+    #[test]
+    fn api_tokens_are_prefixed_and_stored_hashed() {
+        // act
+        let (token, hash) = new_api_token();
+
+        // assert
+        assert!(token.starts_with(API_TOKEN_PREFIX));
+        assert_eq!(hash, hash_token(&token));
+    }
+
+    #[test]
+    fn refresh_tokens_are_stored_hashed() {
+        // arrange
+        let tokens = tokens(&["a"]);
+
+        // act
+        let refresh_token = tokens.new_refresh_token();
+
+        // assert
+        assert_ne!(refresh_token.hash, refresh_token.token);
+        assert_eq!(refresh_token.hash, hash_token(&refresh_token.token));
+    }
+}
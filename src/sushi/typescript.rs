@@ -0,0 +1,63 @@
+use super::domain::{
+    Card, FaceUpCard, GameView, MakiRolls, Nigiri, OpponentView, PlayerView, RoundOver, UserId,
+};
+use ts_rs::TS;
+
+/// TypeScript declarations of the game state sent to clients, written to
+/// `frontend/src/common/types.d.ts`. Generated from the Rust types, so the serde
+/// encodings, e.g. how a maki roll card is tagged, cannot drift from what the server sends.
+pub fn definitions() -> String {
+    let declarations = [
+        UserId::decl(),
+        MakiRolls::decl(),
+        Nigiri::decl(),
+        Card::decl(),
+        FaceUpCard::decl(),
+        PlayerView::decl(),
+        OpponentView::decl(),
+        GameView::decl(),
+        RoundOver::decl(),
+    ];
+
+    let mut out = String::from(
+        "// Generated from the Rust types; do not edit.\n\
+         // Run `cargo run -- --print-typescript > frontend/src/common/types.d.ts` to update.\n",
+    );
+
+    for declaration in &declarations {
+        out.push_str("\nexport ");
+        out.push_str(declaration);
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn definitions_match_committed_file() {
+        // arrange
+        let committed = include_str!("../../frontend/src/common/types.d.ts");
+
+        // assert
+        assert!(
+            definitions() == committed,
+            "types.d.ts is out of date; run \
+             `cargo run -- --print-typescript > frontend/src/common/types.d.ts` \
+             and update the frontend"
+        );
+    }
+
+    #[test]
+    fn nested_tags_are_flattened_into_card() {
+        // act
+        let definitions = definitions();
+
+        // assert
+        assert!(definitions.contains(r#"{ "kind": "makiRolls" } & MakiRolls"#));
+        assert!(definitions.contains(r#"export type MakiRolls = { "makiRolls": "one" }"#));
+    }
+}